@@ -0,0 +1,199 @@
+use super::database::{discard_interrupted_save, name_parts, qualified_name, split_name};
+use super::database::{Attachment, Database, DEFAULT_SCHEMA, MAIN_DATABASE};
+use sqlparser::ast::{visit_expressions_mut, visit_relations_mut};
+use sqlparser::ast::{ColumnOption, Expr, FunctionArg, FunctionArgExpr, Ident, ObjectName};
+use sqlparser::ast::{Statement, TableConstraint, Value as Literal};
+use std::ops::ControlFlow;
+use std::path::Path;
+
+/// `ATTACH [DATABASE] 'file' AS name`: makes the objects of another database
+/// file available as the schema `name`. A file that does not exist yet is
+/// created when the database is saved.
+pub fn attach_database(
+    database: &mut Database,
+    file_path: &str,
+    name: &Ident,
+) -> Result<(), String> {
+    let name = name.value.clone();
+    if name == MAIN_DATABASE || database.schemas.contains(&name) || name == "information_schema" {
+        return Err(format!("Database or schema '{}' already exists", name));
+    }
+    if in_use(database, file_path) {
+        return Err(format!("Database file '{}' is already in use", file_path));
+    }
+
+    // Read the file on the side so that a bad file leaves nothing behind
+    let mut attached = Database::new(file_path);
+    let notice = discard_interrupted_save(file_path)
+        .map_err(|err| format!("Cannot attach '{}': {}", file_path, err))?;
+    if Path::new(file_path).exists() {
+        attached
+            .load_file(file_path, Some(&name))
+            .map_err(|err| format!("Cannot attach '{}': {}", file_path, err))?;
+    }
+    if let Some(notice) = notice {
+        database.report(notice);
+    }
+    database.tables.extend(attached.tables);
+    database.views.extend(attached.views);
+    database.sequences.extend(attached.sequences);
+    database.schemas.push(name.clone());
+    database.attachments.push(Attachment {
+        name: name.clone(),
+        file_path: file_path.to_string(),
+    });
+    database.report(format!("Attached database '{}' as {}", file_path, name));
+    Ok(())
+}
+
+/// `DETACH [DATABASE] name`: saves the attached file and forgets its objects.
+/// Views of other files that use them fail from then on.
+pub fn detach_database(database: &mut Database, name: &Ident) -> Result<(), String> {
+    let name = &name.value;
+    let position = database
+        .attachments
+        .iter()
+        .position(|attachment| attachment.name == *name)
+        .ok_or_else(|| format!("No database is attached as '{}'", name))?;
+    let file_path = database.attachments[position].file_path.clone();
+    database
+        .save_file(&file_path, Some(name))
+        .map_err(|err| format!("Failed to save '{}': {}", file_path, err))?;
+    let in_attachment = |key: &String| split_name(key).0 == *name;
+    database.tables.retain(|key, _| !in_attachment(key));
+    database.views.retain(|key, _| !in_attachment(key));
+    database.sequences.retain(|key, _| !in_attachment(key));
+    database.schemas.retain(|schema| schema != name);
+    database.attachments.remove(position);
+    database.report(format!("Detached database: {}", name));
+    Ok(())
+}
+
+/// `BACKUP [DATABASE] [name] TO 'file'`: writes a copy of the main database,
+/// or of the file attached as `name`, that can be opened on its own. It holds
+/// the database as it is between two statements. There is no write-ahead log
+/// to replay, so a backup cannot be rolled forward to a later point.
+pub fn backup_database(
+    database: &mut Database,
+    name: Option<&Ident>,
+    file_path: &str,
+) -> Result<(), String> {
+    let attachment = match name.map(|name| name.value.as_str()) {
+        None | Some(MAIN_DATABASE) => None,
+        Some(name) => Some(
+            database
+                .attachments
+                .iter()
+                .find(|attachment| attachment.name == name)
+                .map(|attachment| attachment.name.as_str())
+                .ok_or_else(|| format!("No database is attached as '{}'", name))?,
+        ),
+    };
+    if in_use(database, file_path) {
+        return Err(format!(
+            "Cannot back up into '{}', which is an open database file",
+            file_path
+        ));
+    }
+    database
+        .save_file(file_path, attachment)
+        .map_err(|err| format!("Failed to write '{}': {}", file_path, err))?;
+    database.report(format!(
+        "Backed up database '{}' to '{}'",
+        attachment.unwrap_or(MAIN_DATABASE),
+        file_path
+    ));
+    Ok(())
+}
+
+/// Whether `file_path` is the database file or one of the attached files.
+fn in_use(database: &Database, file_path: &str) -> bool {
+    let same_file = |other: &str| {
+        other == file_path
+            || matches!((Path::new(other).canonicalize(), Path::new(file_path).canonicalize()),
+                (Ok(a), Ok(b)) if a == b)
+    };
+    same_file(database.file_path())
+        || database
+            .attachments
+            .iter()
+            .any(|attachment| same_file(&attachment.file_path))
+}
+
+/// The key, once attached as `attachment`, of an object stored under `key`
+/// in the attached file. Its own objects move into the schema of the
+/// attachment, and `main.t` names a table of the main database.
+pub fn attached_key(key: &str, attachment: &str) -> String {
+    match &name_parts(key)[..] {
+        [name] => qualified_name(attachment, name),
+        [schema, name] if schema == MAIN_DATABASE => qualified_name(DEFAULT_SCHEMA, name),
+        _ => key.to_string(),
+    }
+}
+
+/// The inverse of `attached_key`: the name under which the attached file
+/// stores the object with catalog key `key`.
+pub fn detached_key(key: &str, attachment: &str) -> String {
+    match split_name(key) {
+        (schema, name) if schema == attachment => qualified_name(DEFAULT_SCHEMA, &name),
+        (schema, name) if schema == DEFAULT_SCHEMA => qualified_name(MAIN_DATABASE, &name),
+        _ => key.to_string(),
+    }
+}
+
+/// Renames every table, view, index and sequence named in a stored
+/// statement, including sequences passed to `nextval` by name.
+pub fn rename_objects(statement: &mut Statement, rename: &dyn Fn(&str) -> String) {
+    let renamed = |name: &ObjectName| ObjectName(vec![Ident::new(rename(&name.to_string()))]);
+    let _ = visit_relations_mut(statement, |name| {
+        *name = renamed(name);
+        ControlFlow::<()>::Continue(())
+    });
+    let _ = visit_expressions_mut(statement, |expr| {
+        if let Expr::Function(function) = expr {
+            let name = function.name.to_string().to_uppercase();
+            if matches!(name.as_str(), "NEXTVAL" | "CURRVAL" | "SETVAL") {
+                if let Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+                    Literal::SingleQuotedString(sequence_name),
+                )))) = function.args.first_mut()
+                {
+                    *sequence_name = rename(sequence_name);
+                }
+            }
+        }
+        ControlFlow::<()>::Continue(())
+    });
+    let rename_constraint = |constraint: &mut TableConstraint| {
+        if let TableConstraint::ForeignKey { foreign_table, .. } = constraint {
+            *foreign_table = renamed(foreign_table);
+        }
+    };
+    match statement {
+        Statement::CreateTable {
+            columns,
+            constraints,
+            ..
+        } => {
+            for option_def in columns.iter_mut().flat_map(|col| col.options.iter_mut()) {
+                if let ColumnOption::ForeignKey { foreign_table, .. } = &mut option_def.option {
+                    *foreign_table = renamed(foreign_table);
+                }
+            }
+            constraints.iter_mut().for_each(rename_constraint);
+        }
+        Statement::CreateView { name, .. } => *name = renamed(name),
+        Statement::CreateIndex {
+            name: Some(name), ..
+        } => *name = renamed(name),
+        Statement::CreateSequence { name, owned_by, .. } => {
+            *name = renamed(name);
+            if let Some(owner) = owned_by {
+                if let Some(column) = owner.0.pop() {
+                    *owner = renamed(owner);
+                    owner.0.push(column);
+                }
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,155 @@
+use super::database::Database;
+use super::dump::dump;
+use super::output::OutputMode;
+use super::shell::{print_output, repl, run_script};
+use super::transactions::Session;
+use std::io;
+use std::io::{IsTerminal, Read};
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: dbms [DATABASE] [-m MODE] [-c SQL]... [-f FILE]... [--dump]
+
+Opens DATABASE (db.txt by default) and runs the SQL given with -c or read
+from the files given with -f, in order, then exits. Without either, SQL is
+read from standard input, or typed at a prompt when it is a terminal.
+Execution stops at the first error, and the exit status is then non-zero.
+
+A dump is restored by running it into a new database: dbms new.db -f dump.sql
+
+Options:
+  -c SQL    Run the statements in SQL
+  -f FILE   Run the statements in FILE
+  -m MODE   Print results as table (the default), csv, tsv, json, ndjson,
+            markdown or vertical
+  --dump    Print the statements that rebuild the database and its rows,
+            after running any -c and -f
+  -h        Show this help";
+
+/// Where the SQL to run comes from.
+enum Input {
+    Command(String),
+    File(String),
+}
+
+struct Options {
+    database_path: String,
+    inputs: Vec<Input>,
+    mode: OutputMode,
+    dump: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut database_path = None;
+    let mut inputs = Vec::new();
+    let mut mode = OutputMode::Table;
+    let mut dump = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-m" => {
+                let name = args.next().ok_or("Option -m needs an argument")?;
+                mode = OutputMode::from_name(&name)?;
+            }
+            "-c" | "-f" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Option {} needs an argument", arg))?;
+                inputs.push(if arg == "-c" {
+                    Input::Command(value)
+                } else {
+                    Input::File(value)
+                });
+            }
+            "--dump" => dump = true,
+            option if option.starts_with('-') && option != "-" => {
+                return Err(format!("Unknown option: {}", option))
+            }
+            _ if database_path.is_some() => return Err(format!("Unexpected argument: {}", arg)),
+            _ => database_path = Some(arg),
+        }
+    }
+    Ok(Options {
+        database_path: database_path.unwrap_or_else(|| "db.txt".to_string()),
+        inputs,
+        mode,
+        dump,
+    })
+}
+
+/// The `dbms` command.
+pub fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1).peekable();
+    if args
+        .peek()
+        .is_some_and(|arg| arg == "-h" || arg == "--help")
+    {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let mut database = match Database::open(&options.database_path) {
+        Ok(database) => database,
+        Err(err) => {
+            eprintln!("Cannot open '{}': {}", options.database_path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    print_output(std::mem::take(&mut database.output), options.mode);
+
+    if options.inputs.is_empty() && !options.dump && io::stdin().is_terminal() {
+        repl(&mut database, options.mode);
+        return ExitCode::SUCCESS;
+    }
+    let mut session = Session::default();
+    let result = if options.inputs.is_empty() && !options.dump {
+        let mut sql = String::new();
+        io::stdin()
+            .read_to_string(&mut sql)
+            .map_err(|err| format!("Cannot read standard input: {}", err))
+            .and_then(|_| {
+                run_script(
+                    &mut database,
+                    &mut session,
+                    &sql,
+                    Some("<stdin>"),
+                    options.mode,
+                )
+            })
+    } else {
+        options.inputs.iter().try_for_each(|input| match input {
+            Input::Command(sql) => run_script(&mut database, &mut session, sql, None, options.mode),
+            Input::File(path) => std::fs::read_to_string(path)
+                .map_err(|err| format!("Cannot read '{}': {}", path, err))
+                .and_then(|sql| {
+                    run_script(&mut database, &mut session, &sql, Some(path), options.mode)
+                }),
+        })
+    };
+    if options.dump && result.is_ok() {
+        print!("{}", dump(&session.snapshot(&database)));
+    }
+    if session.close(&mut database) {
+        let message = "Rolled back the transaction that was left open";
+        if options.mode.is_data() {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+    // Whatever ran before an error stays done
+    let saved = database
+        .save()
+        .map_err(|err| format!("Failed to save database: {}", err));
+    match result.and(saved) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
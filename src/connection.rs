@@ -0,0 +1,106 @@
+use super::database::Database;
+use super::query_execution::{execute_statement, QueryResult, StatementOutput};
+use super::sql_parsing::{parse_statements, ParsedStatement};
+use super::transactions::{reads_only, Session};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// A database that threads share. Clones are handles to the same database,
+/// through which each thread opens connections of its own.
+#[derive(Clone)]
+pub struct SharedDatabase {
+    database: Arc<RwLock<Database>>,
+}
+
+impl SharedDatabase {
+    /// Opens the database stored at `file_path`. A file that does not exist
+    /// yet starts out empty and is created on the first save. What opening
+    /// it reports comes with the output of the first statement that is not
+    /// only a read.
+    pub fn open(file_path: &str) -> std::io::Result<Self> {
+        Ok(SharedDatabase {
+            database: Arc::new(RwLock::new(Database::open(file_path)?)),
+        })
+    }
+
+    pub fn connect(&self) -> Connection {
+        Connection {
+            database: self.clone(),
+            session: Session::default(),
+        }
+    }
+
+    /// Saves what has been committed to the database file and attached files.
+    pub fn save(&self) -> std::io::Result<()> {
+        self.read().save()
+    }
+
+    // A statement that panicked while holding the lock is a bug, which should
+    // not make the database unusable for every other connection
+    fn read(&self) -> RwLockReadGuard<'_, Database> {
+        self.database.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Database> {
+        self.database
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A session on a shared database, with its own transaction and search
+/// path. A connection that is dropped rolls back its open transaction.
+pub struct Connection {
+    database: SharedDatabase,
+    session: Session,
+}
+
+impl Connection {
+    /// Runs the statements of `sql` in order, stopping at the first error,
+    /// and returns what they report and the rows of those that produce any.
+    /// Statements that only read run alongside each other, while the others
+    /// take turns.
+    pub fn execute(&mut self, sql: &str) -> Result<Vec<StatementOutput>, String> {
+        self.run(parse_statements(sql)?)
+    }
+
+    /// Runs a single statement and returns its rows, as for a SELECT or a
+    /// statement with a RETURNING clause.
+    pub fn query(&mut self, sql: &str) -> Result<QueryResult, String> {
+        let ast = parse_statements(sql)?;
+        if ast.len() != 1 {
+            return Err(format!("Expected a single statement, got {}", ast.len()));
+        }
+        self.run(ast)?
+            .into_iter()
+            .rev()
+            .find_map(|output| match output {
+                StatementOutput::Rows(result) => Some(result),
+                _ => None,
+            })
+            .ok_or_else(|| "The statement returned no rows".to_string())
+    }
+
+    fn run(&mut self, ast: Vec<ParsedStatement>) -> Result<Vec<StatementOutput>, String> {
+        let mut output = Vec::new();
+        for statement in ast {
+            let database = self.database.read();
+            if reads_only(&database, &statement) {
+                // The lock is only held while the rows are copied
+                let mut view = self.session.read_view(&database, &statement);
+                drop(database);
+                output.extend(execute_statement(&mut view, statement)?);
+            } else {
+                drop(database);
+                let mut database = self.database.write();
+                output.extend(self.session.execute(&mut database, vec![statement])?);
+            }
+        }
+        Ok(output)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.session.close(&mut self.database.write());
+    }
+}
@@ -0,0 +1,557 @@
+use super::database::Database;
+use super::json_copy::copy_json_from;
+use super::output::{render, OutputMode};
+use super::query_execution::{build_insert_row, check_row, QueryResult, StatementOutput};
+use super::select_execution::execute_query;
+use super::sql_parsing::parse_sql_queries;
+use super::value::Value;
+use sqlparser::ast::{CopyLegacyCsvOption, CopyLegacyOption, CopyOption, CopySource, CopyTarget};
+use sqlparser::ast::{Expr, Ident, Statement, Value as Literal};
+
+/// The CSV dialect of a COPY statement. The defaults are those of RFC 4180.
+struct CsvOptions {
+    delimiter: char,
+    quote: char,
+    escape: char,
+    header: bool,
+    /// The unquoted field that stands for NULL.
+    null: String,
+}
+
+impl CsvOptions {
+    fn new(options: &[CopyOption], legacy_options: &[CopyLegacyOption]) -> Result<Self, String> {
+        let mut csv = CsvOptions {
+            delimiter: ',',
+            quote: '"',
+            escape: '"',
+            header: false,
+            null: String::new(),
+        };
+        let mut escape = None;
+        for option in options {
+            match option {
+                CopyOption::Format(format) if format.value.eq_ignore_ascii_case("csv") => {}
+                CopyOption::Delimiter(delimiter) => csv.delimiter = *delimiter,
+                CopyOption::Null(null) => csv.null = null.clone(),
+                CopyOption::Header(header) => csv.header = *header,
+                CopyOption::Quote(quote) => csv.quote = *quote,
+                CopyOption::Escape(ch) => escape = Some(*ch),
+                CopyOption::Encoding(encoding)
+                    if encoding.eq_ignore_ascii_case("utf8")
+                        || encoding.eq_ignore_ascii_case("utf-8") => {}
+                other => return Err(format!("Unsupported COPY option: {}", other)),
+            }
+        }
+        for option in legacy_options {
+            match option {
+                CopyLegacyOption::Delimiter(delimiter) => csv.delimiter = *delimiter,
+                CopyLegacyOption::Null(null) => csv.null = null.clone(),
+                CopyLegacyOption::Csv(csv_options) => {
+                    for csv_option in csv_options {
+                        match csv_option {
+                            CopyLegacyCsvOption::Header => csv.header = true,
+                            CopyLegacyCsvOption::Quote(quote) => csv.quote = *quote,
+                            CopyLegacyCsvOption::Escape(ch) => escape = Some(*ch),
+                            other => return Err(format!("Unsupported COPY option: {}", other)),
+                        }
+                    }
+                }
+                other => return Err(format!("Unsupported COPY option: {}", other)),
+            }
+        }
+        // Quotes are escaped by doubling them unless told otherwise
+        csv.escape = escape.unwrap_or(csv.quote);
+        if csv.delimiter == csv.quote || ['\r', '\n'].contains(&csv.delimiter) {
+            return Err("COPY delimiter must differ from the quote and line breaks".to_string());
+        }
+        Ok(csv)
+    }
+}
+
+/// The data format of a COPY statement.
+enum CopyFormat {
+    Csv(CsvOptions),
+    /// An array of objects, one per row.
+    Json,
+    /// One object per line.
+    Ndjson,
+}
+
+impl CopyFormat {
+    fn new(options: &[CopyOption], legacy_options: &[CopyLegacyOption]) -> Result<Self, String> {
+        let json_format = options.iter().find_map(|option| match option {
+            CopyOption::Format(format) if format.value.eq_ignore_ascii_case("json") => {
+                Some(CopyFormat::Json)
+            }
+            CopyOption::Format(format)
+                if format.value.eq_ignore_ascii_case("ndjson")
+                    || format.value.eq_ignore_ascii_case("jsonl") =>
+            {
+                Some(CopyFormat::Ndjson)
+            }
+            _ => None,
+        });
+        let Some(format) = json_format else {
+            return CsvOptions::new(options, legacy_options).map(CopyFormat::Csv);
+        };
+        let csv_option = options
+            .iter()
+            .find(|option| !matches!(option, CopyOption::Format(_) | CopyOption::Encoding(_)));
+        if let Some(option) = csv_option {
+            return Err(format!("COPY option {} is only valid for CSV", option));
+        }
+        if let Some(option) = legacy_options.first() {
+            return Err(format!("COPY option {} is only valid for CSV", option));
+        }
+        Ok(format)
+    }
+
+    fn write(&self, result: &QueryResult) -> String {
+        match self {
+            CopyFormat::Csv(csv) => write_csv(result, csv),
+            CopyFormat::Json => render(result, OutputMode::Json),
+            CopyFormat::Ndjson => render(result, OutputMode::Ndjson),
+        }
+    }
+}
+
+/// `COPY table [(columns)] FROM 'file'` and `COPY {table [(columns)] | (query)}
+/// TO {'file' | STDOUT}`, reading and writing CSV, or JSON and NDJSON with
+/// `FORMAT json` and `FORMAT ndjson`. With `create_table`, a JSON import
+/// creates a missing table from that many of its first records.
+pub fn copy(
+    database: &mut Database,
+    source: &CopySource,
+    to: bool,
+    target: &CopyTarget,
+    options: &[CopyOption],
+    legacy_options: &[CopyLegacyOption],
+    create_table: Option<usize>,
+) -> Result<(), String> {
+    let format = CopyFormat::new(options, legacy_options)?;
+    if create_table.is_some() && (to || matches!(format, CopyFormat::Csv(_))) {
+        return Err("COPY option CREATE_TABLE is only valid for JSON imports".to_string());
+    }
+    match (target, to) {
+        (CopyTarget::File { filename }, false) => {
+            let CopySource::Table {
+                table_name,
+                columns,
+            } = source
+            else {
+                return Err("COPY FROM needs a table, not a query".to_string());
+            };
+            let table_name = table_name.to_string();
+            let text = std::fs::read_to_string(filename)
+                .map_err(|err| format!("Cannot read '{}': {}", filename, err))?;
+            let count = match &format {
+                CopyFormat::Csv(csv) => {
+                    copy_from(database, &table_name, columns, filename, &text, csv)?
+                }
+                json => copy_json_from(
+                    database,
+                    &table_name,
+                    columns,
+                    filename,
+                    &text,
+                    matches!(json, CopyFormat::Ndjson),
+                    create_table,
+                )?,
+            };
+            database.report(format!(
+                "Copied {} row(s) into table: {:?}",
+                count, table_name
+            ));
+        }
+        (CopyTarget::File { filename }, true) => {
+            let result = source_rows(database, source)?;
+            std::fs::write(filename, format.write(&result))
+                .map_err(|err| format!("Cannot write '{}': {}", filename, err))?;
+            database.report(format!(
+                "Copied {} row(s) to '{}'",
+                result.rows.len(),
+                filename
+            ));
+        }
+        (CopyTarget::Stdout, true) => {
+            let result = source_rows(database, source)?;
+            database
+                .output
+                .push(StatementOutput::Text(format.write(&result)));
+        }
+        (CopyTarget::Stdin, false) => {
+            return Err("COPY FROM STDIN is not supported, copy from a file".to_string())
+        }
+        (target, _) => return Err(format!("Unsupported COPY target: {}", target)),
+    }
+    Ok(())
+}
+
+/// The rows a COPY TO writes: those of a table or view, or of a query.
+fn source_rows(database: &Database, source: &CopySource) -> Result<QueryResult, String> {
+    let query = match source {
+        CopySource::Query(query) => *query.clone(),
+        CopySource::Table {
+            table_name,
+            columns,
+        } => {
+            let columns = if columns.is_empty() {
+                "*".to_string()
+            } else {
+                let names: Vec<String> = columns.iter().map(|col| col.to_string()).collect();
+                names.join(", ")
+            };
+            let sql = format!("SELECT {} FROM {}", columns, table_name);
+            match parse_sql_queries(&sql)?.pop() {
+                Some(Statement::Query(query)) => *query,
+                _ => return Err(format!("Cannot copy from '{}'", table_name)),
+            }
+        }
+    };
+    execute_query(database, &query)
+}
+
+/// Appends the records of `text`, read from `filename`, to a table and
+/// returns how many there were. Nothing is added unless every record converts and passes the
+/// table's constraints.
+fn copy_from(
+    database: &mut Database,
+    table_name: &str,
+    columns: &[Ident],
+    filename: &str,
+    text: &str,
+    csv: &CsvOptions,
+) -> Result<usize, String> {
+    let mut table = match database.tables.get(table_name) {
+        Some(table) if !database.is_materialized_view(table_name) => table.clone(),
+        _ if database.views.contains_key(table_name) => {
+            return Err(format!("Cannot copy into view '{}'", table_name))
+        }
+        _ => return Err(format!("Table '{}' does not exist", table_name)),
+    };
+    let column_indexes = columns
+        .iter()
+        .map(|col| {
+            table.column_index(&col.value).ok_or_else(|| {
+                format!(
+                    "Column '{}' does not exist in table '{}'",
+                    col.value, table_name
+                )
+            })
+        })
+        .collect::<Result<Vec<usize>, String>>()?;
+    let field_count = if columns.is_empty() {
+        table.columns.len()
+    } else {
+        columns.len()
+    };
+
+    let mut records = read_csv(text, csv).map_err(|err| format!("{}: {}", filename, err))?;
+    if csv.header && !records.is_empty() {
+        records.remove(0);
+    }
+    let count = records.len();
+    for (line, fields) in records {
+        let at_line = |err: String| format!("{}: line {}: {}", filename, line, err);
+        if fields.len() != field_count {
+            return Err(at_line(format!(
+                "expected {} field(s), found {}",
+                field_count,
+                fields.len()
+            )));
+        }
+        let exprs: Vec<Expr> = fields
+            .into_iter()
+            .map(|field| {
+                Expr::Value(match field {
+                    Some(text) => Literal::SingleQuotedString(text),
+                    None => Literal::Null,
+                })
+            })
+            .collect();
+        let row = build_insert_row(&table, &column_indexes, &exprs, database).map_err(at_line)?;
+        check_row(&table, &row, None, database).map_err(at_line)?;
+        table.push_row(row);
+    }
+    database.tables.insert(table_name.to_string(), table);
+    Ok(count)
+}
+
+/// A CSV record: the line it starts on and its fields.
+type Record = (usize, Vec<Option<String>>);
+
+/// Splits CSV text into records, each with the line it starts on. A field is
+/// `None` for NULL: the NULL string, unquoted. Quoted fields may hold the
+/// delimiter, line breaks and escaped quotes.
+fn read_csv(text: &str, csv: &CsvOptions) -> Result<Vec<Record>, String> {
+    let mut records = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while chars.peek().is_some() {
+        let record_line = line;
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        loop {
+            match chars.next() {
+                None => {
+                    fields.push(finish_field(field, quoted, csv));
+                    break;
+                }
+                Some(ch) if ch == csv.quote && field.is_empty() && !quoted => {
+                    quoted = true;
+                    // Read up to the closing quote
+                    loop {
+                        match chars.next() {
+                            None => {
+                                return Err(format!(
+                                    "line {}: unterminated quoted field",
+                                    record_line
+                                ))
+                            }
+                            Some(ch) if ch == csv.escape && chars.peek() == Some(&csv.quote) => {
+                                chars.next();
+                                field.push(csv.quote);
+                            }
+                            Some(ch) if ch == csv.escape && ch != csv.quote => match chars.peek() {
+                                Some(&next) if next == csv.escape => {
+                                    chars.next();
+                                    field.push(next);
+                                }
+                                _ => field.push(ch),
+                            },
+                            Some(ch) if ch == csv.quote => break,
+                            Some(ch) => {
+                                if ch == '\n' {
+                                    line += 1;
+                                }
+                                field.push(ch);
+                            }
+                        }
+                    }
+                }
+                Some(ch) if ch == csv.delimiter => {
+                    fields.push(finish_field(std::mem::take(&mut field), quoted, csv));
+                    quoted = false;
+                }
+                Some('\r') if chars.peek() == Some(&'\n') => {}
+                Some('\n') | Some('\r') => {
+                    line += 1;
+                    fields.push(finish_field(field, quoted, csv));
+                    break;
+                }
+                Some(ch) if quoted => {
+                    return Err(format!(
+                        "line {}: unexpected '{}' after a quoted field",
+                        line, ch
+                    ))
+                }
+                Some(ch) => field.push(ch),
+            }
+        }
+        // Blank lines hold no record
+        let blank =
+            fields.len() == 1 && !quoted && fields[0].as_deref().unwrap_or(&csv.null).is_empty();
+        if !blank {
+            records.push((record_line, fields));
+        }
+    }
+    Ok(records)
+}
+
+fn finish_field(field: String, quoted: bool, csv: &CsvOptions) -> Option<String> {
+    if !quoted && field == csv.null {
+        None
+    } else {
+        Some(field)
+    }
+}
+
+fn write_csv(result: &QueryResult, csv: &CsvOptions) -> String {
+    let mut text = String::new();
+    let mut write_record = |fields: Vec<String>| {
+        text += &fields.join(&csv.delimiter.to_string());
+        text += "\n";
+    };
+    if csv.header {
+        write_record(
+            result
+                .columns
+                .iter()
+                .map(|name| quote_field(name, csv))
+                .collect(),
+        );
+    }
+    for row in &result.rows {
+        write_record(
+            row.iter()
+                .map(|value| match value {
+                    Value::Null => csv.null.clone(),
+                    value => quote_field(&value.to_string(), csv),
+                })
+                .collect(),
+        );
+    }
+    text
+}
+
+/// Quotes a field holding the delimiter, a quote or a line break, and one
+/// that would otherwise read back as NULL.
+fn quote_field(text: &str, csv: &CsvOptions) -> String {
+    let needs_quotes = text == csv.null || text.contains([csv.delimiter, csv.quote, '\n', '\r']);
+    if !needs_quotes {
+        return text.to_string();
+    }
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push(csv.quote);
+    for ch in text.chars() {
+        if ch == csv.quote || (ch == csv.escape && csv.escape != csv.quote) {
+            quoted.push(csv.escape);
+        }
+        quoted.push(ch);
+    }
+    quoted.push(csv.quote);
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_parsing::parse_statements;
+    use crate::transactions::Session;
+
+    fn fields(values: &[Option<&str>]) -> Vec<Option<String>> {
+        values
+            .iter()
+            .map(|value| value.map(str::to_string))
+            .collect()
+    }
+
+    fn rfc_4180() -> CsvOptions {
+        CsvOptions::new(&[], &[]).unwrap()
+    }
+
+    #[test]
+    fn quoted_fields_hold_delimiters_quotes_and_line_breaks() {
+        let text = "a,\"b,c\",\"say \"\"hi\"\"\"\r\n\"two\r\nlines\",x,y\r\nlast,,\"\"";
+        let records = read_csv(text, &rfc_4180()).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                (1, fields(&[Some("a"), Some("b,c"), Some("say \"hi\"")])),
+                (2, fields(&[Some("two\r\nlines"), Some("x"), Some("y")])),
+                (4, fields(&[Some("last"), None, Some("")])),
+            ]
+        );
+    }
+
+    #[test]
+    fn blank_lines_hold_no_record() {
+        let records = read_csv("1\n\n2\n", &rfc_4180()).unwrap();
+        assert_eq!(
+            records,
+            vec![(1, fields(&[Some("1")])), (3, fields(&[Some("2")]))]
+        );
+        let records = read_csv("\"\"\n", &rfc_4180()).unwrap();
+        assert_eq!(records, vec![(1, fields(&[Some("")]))]);
+    }
+
+    #[test]
+    fn malformed_quotes_are_errors() {
+        let err = read_csv("a\n\"open\nstill open", &rfc_4180()).unwrap_err();
+        assert_eq!(err, "line 2: unterminated quoted field");
+        let err = read_csv("\"a\"b", &rfc_4180()).unwrap_err();
+        assert_eq!(err, "line 1: unexpected 'b' after a quoted field");
+    }
+
+    #[test]
+    fn escape_and_null_options() {
+        let csv = CsvOptions {
+            delimiter: ';',
+            quote: '\'',
+            escape: '\\',
+            header: false,
+            null: "NULL".to_string(),
+        };
+        let records = read_csv("'it\\'s';NULL;'NULL';'a\\\\b'", &csv).unwrap();
+        assert_eq!(
+            records,
+            vec![(1, fields(&[Some("it's"), None, Some("NULL"), Some("a\\b")]))]
+        );
+    }
+
+    #[test]
+    fn written_fields_read_back_the_same() {
+        let result = QueryResult {
+            columns: vec!["a, b".to_string(), "c".to_string()],
+            rows: vec![
+                vec![Value::Text("\"quoted\"\nline".to_string()), Value::Null],
+                vec![Value::Text(String::new()), Value::Integer(1)],
+            ],
+        };
+        let mut csv = rfc_4180();
+        csv.header = true;
+        let text = write_csv(&result, &csv);
+        assert_eq!(text, "\"a, b\",c\n\"\"\"quoted\"\"\nline\",\n\"\",1\n");
+        assert_eq!(
+            read_csv(&text, &csv).unwrap(),
+            vec![
+                (1, fields(&[Some("a, b"), Some("c")])),
+                (2, fields(&[Some("\"quoted\"\nline"), None])),
+                (4, fields(&[Some(""), Some("1")])),
+            ]
+        );
+    }
+
+    fn run(database: &mut Database, sql: &str) -> Result<Vec<StatementOutput>, String> {
+        Session::default().execute(database, parse_statements(sql)?)
+    }
+
+    #[test]
+    fn json_imports_create_missing_tables_only_when_asked() {
+        let path = std::env::temp_dir().join(format!("dbms-{}-create.ndjson", std::process::id()));
+        std::fs::write(
+            &path,
+            "{\"a\": 1, \"b\": \"x\"}\n{\"a\": 2.5, \"b\": null}\n",
+        )
+        .unwrap();
+        let path = path.to_string_lossy().to_string();
+        let mut database = Database::new("test.db");
+
+        let sql = format!("COPY t FROM '{}' WITH (FORMAT ndjson)", path);
+        assert_eq!(
+            run(&mut database, &sql).unwrap_err(),
+            "Table 't' does not exist"
+        );
+        assert!(!database.tables.contains_key("t"));
+
+        // Only the first record is sampled, so 2.5 no longer fits column a
+        let sql = format!(
+            "COPY t FROM '{}' WITH (FORMAT ndjson, CREATE_TABLE 1)",
+            path
+        );
+        assert_eq!(
+            run(&mut database, &sql).unwrap_err(),
+            format!("{}: line 2: Cannot convert 2.5 to INTEGER", path)
+        );
+        assert!(!database.tables.contains_key("t"));
+
+        let sql = format!("COPY t FROM '{}' (CREATE_TABLE, FORMAT ndjson)", path);
+        run(&mut database, &sql).unwrap();
+        let table = &database.tables["t"];
+        let types: Vec<String> = table
+            .columns
+            .iter()
+            .map(|column| column.data_type.to_string())
+            .collect();
+        assert_eq!(types, ["FLOAT", "TEXT"]);
+        assert_eq!(table.data.len(), 2);
+
+        let sql = format!("COPY u FROM '{}' (FORMAT csv, CREATE_TABLE)", path);
+        assert_eq!(
+            run(&mut database, &sql).unwrap_err(),
+            "COPY option CREATE_TABLE is only valid for JSON imports"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
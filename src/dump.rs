@@ -0,0 +1,293 @@
+use super::database::{Database, Table};
+use super::value::Value;
+use sqlparser::ast::TableConstraint;
+
+/// How many rows go into one INSERT statement of a dump.
+const INSERT_BATCH_SIZE: usize = 100;
+
+/// The database as a script of SQL statements that rebuilds it: schemas,
+/// sequences, tables with their rows, then constraints, indexes and views.
+/// Foreign keys and what follows them are only added once every row is in,
+/// so tables come in name order whatever they reference. Objects of attached
+/// files are left out. Materialized views are recomputed from the restored
+/// tables, and those never refreshed stay empty.
+pub fn dump(database: &Database) -> String {
+    let mut statements: Vec<String> = Vec::new();
+    let in_file = |key: &str| database.attachment_of(key).is_none();
+
+    for schema in database.schemas.iter().skip(1) {
+        if !database.attachments.iter().any(|a| a.name == *schema) {
+            statements.push(format!("CREATE SCHEMA {}", schema));
+        }
+    }
+    let mut sequences: Vec<_> = database
+        .sequences
+        .values()
+        .filter(|sequence| in_file(&sequence.name))
+        .collect();
+    sequences.sort_by(|a, b| a.name.cmp(&b.name));
+    for sequence in sequences.iter().filter(|s| s.owned_by.is_none()) {
+        statements.push(sequence.create_statement());
+    }
+
+    let mut tables: Vec<&Table> = database
+        .tables
+        .values()
+        .filter(|table| in_file(&table.name) && !database.is_materialized_view(&table.name))
+        .collect();
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+    let deferred = |table: &Table| {
+        table
+            .constraints
+            .iter()
+            .position(|c| matches!(c, TableConstraint::ForeignKey { .. }))
+            .unwrap_or(table.constraints.len())
+    };
+    for table in &tables {
+        // Identity columns only take explicit values once the rows are in
+        let mut created = Table {
+            name: table.name.clone(),
+            columns: table.columns.clone(),
+            constraints: table.constraints[..deferred(table)].to_vec(),
+            indexes: vec![],
+            data: vec![],
+            versions: vec![],
+            origins: None,
+        };
+        for column in &mut created.columns {
+            column.generated_always = false;
+        }
+        statements.push(created.create_statement());
+        for sequence in &sequences {
+            if matches!(&sequence.owned_by, Some((owner, _)) if *owner == table.name) {
+                statements.push(sequence.create_statement());
+            }
+        }
+        statements.extend(insert_statements(table));
+    }
+    for table in &tables {
+        for constraint in &table.constraints[deferred(table)..] {
+            statements.push(format!("ALTER TABLE {} ADD {}", table.name, constraint));
+        }
+        for index in &table.indexes {
+            statements.push(index.create_statement(&table.name));
+        }
+        for column in table.columns.iter().filter(|c| c.generated_always) {
+            statements.push(format!(
+                "ALTER TABLE {} ALTER COLUMN {} SET GENERATED ALWAYS",
+                table.name, column.name
+            ));
+        }
+    }
+    for sequence in &sequences {
+        if let Some(last_value) = sequence.last_value.get() {
+            statements.push(format!(
+                "SELECT setval('{}', {})",
+                sequence.name, last_value
+            ));
+        }
+    }
+
+    // A view comes after the views it reads from
+    let mut views: Vec<_> = database
+        .views
+        .values()
+        .filter(|view| in_file(&view.name))
+        .collect();
+    views.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut created: Vec<&str> = Vec::new();
+    while created.len() < views.len() {
+        let ready = views
+            .iter()
+            .find(|view| {
+                !created.contains(&view.name.as_str())
+                    && view.dependencies().iter().all(|name| {
+                        !database.views.contains_key(name)
+                            || *name == view.name
+                            || created.contains(&name.as_str())
+                    })
+            })
+            // Views cannot depend on each other in a cycle, but fall back to
+            // name order rather than loop forever
+            .or_else(|| {
+                views
+                    .iter()
+                    .find(|view| !created.contains(&view.name.as_str()))
+            })
+            .unwrap();
+        if ready.materialized && ready.refreshed_at.is_none() {
+            statements.push(format!("{} WITH NO DATA", ready.create_statement()));
+        } else {
+            statements.push(ready.create_statement());
+        }
+        if ready.materialized {
+            for index in &database.tables[&ready.name].indexes {
+                statements.push(index.create_statement(&ready.name));
+            }
+        }
+        created.push(&ready.name);
+    }
+
+    statements
+        .into_iter()
+        .map(|statement| statement + ";\n")
+        .collect()
+}
+
+/// The rows of a table as INSERT statements of up to `INSERT_BATCH_SIZE` rows.
+fn insert_statements(table: &Table) -> Vec<String> {
+    table
+        .data
+        .chunks(INSERT_BATCH_SIZE)
+        .map(|rows| {
+            let rows: Vec<String> = rows
+                .iter()
+                .map(|row| {
+                    let values: Vec<String> = row.iter().map(literal).collect();
+                    format!("({})", values.join(", "))
+                })
+                .collect();
+            format!("INSERT INTO {} VALUES {}", table.name, rows.join(", "))
+        })
+        .collect()
+}
+
+/// A value as a SQL literal that reads back as the same value.
+fn literal(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Integer(n) => n.to_string(),
+        // NaN and the infinities have no literal, but their text casts back
+        Value::Float(x) if !x.is_finite() => format!("CAST('{}' AS DOUBLE)", x),
+        Value::Float(x) => format!("{:?}", x),
+        Value::Boolean(b) => b.to_string().to_uppercase(),
+        Value::Text(text) => format!("'{}'", text.replace('\'', "''")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query_execution::StatementOutput;
+    use crate::sql_parsing::parse_statements;
+    use crate::transactions::Session;
+
+    fn run(database: &mut Database, sql: &str) -> Vec<StatementOutput> {
+        Session::default()
+            .execute(database, parse_statements(sql).unwrap())
+            .unwrap()
+    }
+
+    fn rows(database: &mut Database, sql: &str) -> Vec<Vec<Value>> {
+        match run(database, sql).pop() {
+            Some(StatementOutput::Rows(result)) => result.rows,
+            output => panic!("Expected rows, got {:?}", output),
+        }
+    }
+
+    fn restored(database: &Database) -> Database {
+        let mut copy = Database::new("restored.db");
+        run(&mut copy, &dump(database));
+        copy
+    }
+
+    #[test]
+    fn restoring_a_dump_rebuilds_the_database() {
+        let mut database = Database::new("test.db");
+        run(
+            &mut database,
+            "CREATE SCHEMA app;
+            CREATE SEQUENCE app.counter INCREMENT BY 5 START WITH 5;
+            CREATE TABLE app.owners (id SERIAL PRIMARY KEY, name TEXT NOT NULL UNIQUE);
+            CREATE TABLE items (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                owner INT REFERENCES app.owners (id),
+                label TEXT DEFAULT 'none',
+                weight DOUBLE CHECK (weight >= 0 OR weight <> weight)
+            );
+            CREATE INDEX items_label ON items (label);
+            INSERT INTO app.owners (name) VALUES ('o''brien'), ('line
+            break');
+            INSERT INTO items (owner, label, weight) VALUES (1, NULL, 1.5), (2, 'b', CAST('NaN' AS DOUBLE));
+            INSERT INTO items VALUES (40, NULL, 'c', 0.0);
+            CREATE VIEW heavy AS SELECT label FROM items WHERE weight > 1;
+            SELECT nextval('app.counter')",
+        );
+        for n in 0..150 {
+            run(
+                &mut database,
+                &format!("INSERT INTO app.owners (name) VALUES ('n{}')", n),
+            );
+        }
+
+        let text = dump(&database);
+        let mut copy = restored(&database);
+        assert_eq!(dump(&copy), text);
+        for table in ["app.owners", "items"] {
+            assert_eq!(
+                format!("{:?}", copy.tables[table].data),
+                format!("{:?}", database.tables[table].data)
+            );
+        }
+        // Sequences go on from where they were
+        assert_eq!(
+            rows(&mut copy, "SELECT nextval('app.counter')"),
+            vec![vec![Value::Integer(10)]]
+        );
+        assert_eq!(
+            rows(
+                &mut copy,
+                "INSERT INTO items (label) VALUES ('d') RETURNING id"
+            ),
+            vec![vec![Value::Integer(41)]]
+        );
+        assert_eq!(
+            rows(&mut copy, "SELECT label FROM heavy"),
+            vec![vec![Value::Null]]
+        );
+    }
+
+    #[test]
+    fn materialized_views_are_recomputed_on_restore() {
+        let mut database = Database::new("test.db");
+        run(
+            &mut database,
+            "CREATE TABLE t (x INT);
+            INSERT INTO t VALUES (1);
+            CREATE MATERIALIZED VIEW m AS SELECT x FROM t;
+            CREATE INDEX m_x ON m (x);
+            CREATE MATERIALIZED VIEW empty AS SELECT x FROM t WITH NO DATA;
+            INSERT INTO t VALUES (2)",
+        );
+        let mut copy = restored(&database);
+        assert_eq!(dump(&copy), dump(&database));
+        assert_eq!(
+            rows(&mut copy, "SELECT x FROM m ORDER BY x"),
+            vec![vec![Value::Integer(1)], vec![Value::Integer(2)]]
+        );
+        assert_eq!(copy.tables["m"].indexes.len(), 1);
+        assert!(copy.views["empty"].refreshed_at.is_none());
+        assert!(copy.tables["empty"].data.is_empty());
+    }
+
+    #[test]
+    fn materialized_views_only_change_through_refresh() {
+        let mut database = Database::new("test.db");
+        run(
+            &mut database,
+            "CREATE TABLE t (x INT);
+            CREATE MATERIALIZED VIEW empty AS SELECT x FROM t WITH NO DATA",
+        );
+        let err = Session::default()
+            .execute(
+                &mut database,
+                parse_statements("INSERT INTO empty VALUES (1)").unwrap(),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "Cannot change materialized view 'empty', use REFRESH MATERIALIZED VIEW"
+        );
+        assert!(parse_statements("ALTER MATERIALIZED VIEW empty SET REFRESHED AT 1").is_err());
+    }
+}
@@ -0,0 +1,551 @@
+use super::database::{split_name, Database, DEFAULT_SCHEMA, MAIN_DATABASE};
+use super::query_execution::QueryResult;
+use super::select_execution::execute_subquery;
+use super::sequences::sequence_function;
+use super::value::Value;
+use sqlparser::ast::UnaryOperator;
+use sqlparser::ast::{BinaryOperator, Expr, FunctionArg, FunctionArgExpr, Ident, Query};
+use std::cmp::Ordering;
+
+/// A column visible to expressions, optionally qualified by a table name or
+/// alias, and by the schema of the table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnRef {
+    pub schema: Option<String>,
+    pub table: Option<String>,
+    pub name: String,
+}
+
+impl ColumnRef {
+    /// `table` is an alias or the catalog key of a table, so that columns of
+    /// `app.users` can be referred to as `users.id` or `app.users.id`.
+    pub fn new(table: &str, name: &str) -> Self {
+        let (schema, table) = split_name(table);
+        ColumnRef {
+            schema: Some(schema.to_string()),
+            table: Some(table.to_string()),
+            name: name.to_string(),
+        }
+    }
+
+    /// Qualifies every column name with `table`.
+    pub fn for_table(table: &str, column_names: &[String]) -> Vec<ColumnRef> {
+        column_names
+            .iter()
+            .map(|name| ColumnRef::new(table, name))
+            .collect()
+    }
+
+    fn matches(&self, idents: &[Ident]) -> bool {
+        match idents {
+            [column] => self.name == column.value,
+            [table, column] => {
+                self.name == column.value && self.table.as_deref() == Some(table.value.as_str())
+            }
+            [schema, table, column] => {
+                // `main.t.c` is a column of the main database's table `t`
+                let schema = if schema.value == MAIN_DATABASE {
+                    DEFAULT_SCHEMA
+                } else {
+                    schema.value.as_str()
+                };
+                self.name == column.value
+                    && self.table.as_deref() == Some(table.value.as_str())
+                    && self.schema.as_deref() == Some(schema)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The row an expression is evaluated against. Names that are not found in
+/// the row are looked up in the parent scope, which holds e.g. the `excluded`
+/// row of an upsert or the outer row of a correlated subquery.
+pub struct Scope<'a> {
+    pub columns: &'a [ColumnRef],
+    pub row: &'a [Value],
+    pub parent: Option<&'a Scope<'a>>,
+}
+
+impl<'a> Scope<'a> {
+    pub fn new(columns: &'a [ColumnRef], row: &'a [Value]) -> Self {
+        Scope {
+            columns,
+            row,
+            parent: None,
+        }
+    }
+
+    pub fn empty() -> Scope<'static> {
+        Scope {
+            columns: &[],
+            row: &[],
+            parent: None,
+        }
+    }
+
+    pub fn with_parent(mut self, parent: &'a Scope<'a>) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+
+    pub fn with_outer(mut self, outer: Option<&'a Scope<'a>>) -> Self {
+        self.parent = outer;
+        self
+    }
+
+    fn lookup(&self, idents: &[Ident]) -> Result<Value, String> {
+        let mut matches = self
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(_, col)| col.matches(idents));
+        match (matches.next(), matches.next()) {
+            (Some((index, _)), None) => Ok(self.row[index].clone()),
+            (Some(_), Some(_)) => Err(format!("Column reference '{}' is ambiguous", join(idents))),
+            (None, _) => match self.parent {
+                Some(parent) => parent.lookup(idents),
+                None => Err(format!("Column '{}' does not exist", join(idents))),
+            },
+        }
+    }
+}
+
+fn join(idents: &[Ident]) -> String {
+    idents
+        .iter()
+        .map(|ident| ident.value.as_str())
+        .collect::<Vec<&str>>()
+        .join(".")
+}
+
+/// Evaluates a WHERE-style condition; a NULL result counts as false.
+pub fn evaluate_predicate(expr: &Expr, scope: &Scope, database: &Database) -> Result<bool, String> {
+    Ok(evaluate(expr, scope, database)?.as_bool()?.unwrap_or(false))
+}
+
+pub fn evaluate(expr: &Expr, scope: &Scope, database: &Database) -> Result<Value, String> {
+    match expr {
+        Expr::Identifier(ident) => scope.lookup(std::slice::from_ref(ident)),
+        Expr::CompoundIdentifier(idents) => scope.lookup(idents),
+        Expr::Value(literal) => Value::from_literal(literal),
+        Expr::Nested(inner) => evaluate(inner, scope, database),
+        Expr::BinaryOp { left, op, right } => match op {
+            BinaryOperator::And => {
+                let left = evaluate(left, scope, database)?.as_bool()?;
+                if left == Some(false) {
+                    return Ok(Value::Boolean(false));
+                }
+                let right = evaluate(right, scope, database)?.as_bool()?;
+                Ok(match (left, right) {
+                    (_, Some(false)) => Value::Boolean(false),
+                    (Some(true), Some(true)) => Value::Boolean(true),
+                    _ => Value::Null,
+                })
+            }
+            BinaryOperator::Or => {
+                let left = evaluate(left, scope, database)?.as_bool()?;
+                if left == Some(true) {
+                    return Ok(Value::Boolean(true));
+                }
+                let right = evaluate(right, scope, database)?.as_bool()?;
+                Ok(match (left, right) {
+                    (_, Some(true)) => Value::Boolean(true),
+                    (Some(false), Some(false)) => Value::Boolean(false),
+                    _ => Value::Null,
+                })
+            }
+            op => {
+                let left = evaluate(left, scope, database)?;
+                let right = evaluate(right, scope, database)?;
+                binary_operation(&left, op, &right)
+            }
+        },
+        Expr::UnaryOp { op, expr } => {
+            let value = evaluate(expr, scope, database)?;
+            match (op, value) {
+                (_, Value::Null) => Ok(Value::Null),
+                (UnaryOperator::Not, value) => {
+                    Ok(Value::Boolean(!value.as_bool()?.unwrap_or(false)))
+                }
+                (UnaryOperator::Minus, Value::Integer(n)) => Ok(Value::Integer(-n)),
+                (UnaryOperator::Minus, Value::Float(f)) => Ok(Value::Float(-f)),
+                (UnaryOperator::Plus, value @ (Value::Integer(_) | Value::Float(_))) => Ok(value),
+                (op, value) => Err(format!("Cannot apply {} to {}", op, value)),
+            }
+        }
+        Expr::IsNull(expr) => Ok(Value::Boolean(evaluate(expr, scope, database)?.is_null())),
+        Expr::IsNotNull(expr) => Ok(Value::Boolean(!evaluate(expr, scope, database)?.is_null())),
+        Expr::IsTrue(expr) => Ok(Value::Boolean(
+            evaluate(expr, scope, database)?.as_bool()? == Some(true),
+        )),
+        Expr::IsNotTrue(expr) => Ok(Value::Boolean(
+            evaluate(expr, scope, database)?.as_bool()? != Some(true),
+        )),
+        Expr::IsFalse(expr) => Ok(Value::Boolean(
+            evaluate(expr, scope, database)?.as_bool()? == Some(false),
+        )),
+        Expr::IsNotFalse(expr) => Ok(Value::Boolean(
+            evaluate(expr, scope, database)?.as_bool()? != Some(false),
+        )),
+        Expr::IsDistinctFrom(left, right) => Ok(Value::Boolean(!not_distinct(
+            &evaluate(left, scope, database)?,
+            &evaluate(right, scope, database)?,
+        ))),
+        Expr::IsNotDistinctFrom(left, right) => Ok(Value::Boolean(not_distinct(
+            &evaluate(left, scope, database)?,
+            &evaluate(right, scope, database)?,
+        ))),
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let value = evaluate(expr, scope, database)?;
+            let candidates = list
+                .iter()
+                .map(|item| evaluate(item, scope, database))
+                .collect::<Result<Vec<Value>, String>>()?;
+            Ok(negate_if(in_values(&value, &candidates), *negated))
+        }
+        Expr::InSubquery {
+            expr,
+            subquery,
+            negated,
+        } => {
+            let value = evaluate(expr, scope, database)?;
+            let candidates = single_column(execute_subquery(database, subquery, scope)?)?;
+            Ok(negate_if(in_values(&value, &candidates), *negated))
+        }
+        Expr::Exists { subquery, negated } => {
+            let found = !execute_subquery(database, subquery, scope)?.rows.is_empty();
+            Ok(Value::Boolean(found != *negated))
+        }
+        Expr::Subquery(subquery) => {
+            let mut values = single_column(execute_subquery(database, subquery, scope)?)?;
+            match values.len() {
+                0 => Ok(Value::Null),
+                1 => Ok(values.remove(0)),
+                _ => Err(
+                    "More than one row returned by a subquery used as an expression".to_string(),
+                ),
+            }
+        }
+        Expr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => {
+            let value = evaluate(expr, scope, database)?;
+            let low = evaluate(low, scope, database)?;
+            let high = evaluate(high, scope, database)?;
+            let result = match (value.compare(&low), value.compare(&high)) {
+                (Some(lower), Some(upper)) => {
+                    Value::Boolean(lower != Ordering::Less && upper != Ordering::Greater)
+                }
+                _ => Value::Null,
+            };
+            Ok(negate_if(result, *negated))
+        }
+        Expr::Like {
+            negated,
+            expr,
+            pattern,
+            escape_char,
+        } => like(
+            expr,
+            pattern,
+            *escape_char,
+            *negated,
+            false,
+            scope,
+            database,
+        ),
+        Expr::ILike {
+            negated,
+            expr,
+            pattern,
+            escape_char,
+        } => like(expr, pattern, *escape_char, *negated, true, scope, database),
+        Expr::Cast { expr, data_type } => evaluate(expr, scope, database)?.cast(data_type),
+        Expr::TryCast { expr, data_type } | Expr::SafeCast { expr, data_type } => {
+            Ok(evaluate(expr, scope, database)?
+                .cast(data_type)
+                .unwrap_or(Value::Null))
+        }
+        Expr::TypedString { data_type, value } => Value::Text(value.clone()).cast(data_type),
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            let operand = match operand {
+                Some(operand) => Some(evaluate(operand, scope, database)?),
+                None => None,
+            };
+            for (condition, result) in conditions.iter().zip(results) {
+                let condition = evaluate(condition, scope, database)?;
+                let matched = match &operand {
+                    Some(operand) => operand.same_key(&condition),
+                    None => condition.as_bool()? == Some(true),
+                };
+                if matched {
+                    return evaluate(result, scope, database);
+                }
+            }
+            match else_result {
+                Some(else_result) => evaluate(else_result, scope, database),
+                None => Ok(Value::Null),
+            }
+        }
+        Expr::Function(function) => {
+            let name = function.name.to_string().to_uppercase();
+            let args = function
+                .args
+                .iter()
+                .map(|arg| match arg {
+                    FunctionArg::Unnamed(FunctionArgExpr::Expr(expr))
+                    | FunctionArg::Named {
+                        arg: FunctionArgExpr::Expr(expr),
+                        ..
+                    } => evaluate(expr, scope, database),
+                    _ => Err(format!("Unsupported argument in call to {}", name)),
+                })
+                .collect::<Result<Vec<Value>, String>>()?;
+            match name.as_str() {
+                "NEXTVAL" | "CURRVAL" | "SETVAL" => sequence_function(&name, args, database),
+                _ => call_function(&name, args),
+            }
+        }
+        _ => Err(format!("Unsupported expression: {}", expr)),
+    }
+}
+
+/// Evaluates a subquery that must produce at most one row, such as the right
+/// side of `SET (a, b) = (SELECT ...)`. No row yields NULLs.
+pub fn evaluate_row_subquery(
+    subquery: &Query,
+    width: usize,
+    scope: &Scope,
+    database: &Database,
+) -> Result<Vec<Value>, String> {
+    let mut result = execute_subquery(database, subquery, scope)?;
+    if result.columns.len() != width {
+        return Err(format!(
+            "Subquery returns {} columns but {} are expected",
+            result.columns.len(),
+            width
+        ));
+    }
+    match result.rows.len() {
+        0 => Ok(vec![Value::Null; width]),
+        1 => Ok(result.rows.remove(0)),
+        _ => Err("More than one row returned by a subquery used as an expression".to_string()),
+    }
+}
+
+fn single_column(result: QueryResult) -> Result<Vec<Value>, String> {
+    if result.columns.len() != 1 {
+        return Err("Subquery has too many columns".to_string());
+    }
+    Ok(result
+        .rows
+        .into_iter()
+        .map(|mut row| row.remove(0))
+        .collect())
+}
+
+fn negate_if(value: Value, negated: bool) -> Value {
+    match value {
+        Value::Boolean(b) if negated => Value::Boolean(!b),
+        value => value,
+    }
+}
+
+fn not_distinct(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Null, Value::Null) => true,
+        (Value::Null, _) | (_, Value::Null) => false,
+        _ => left.same_key(right),
+    }
+}
+
+/// SQL `IN` semantics: true on a match, otherwise NULL if any side was NULL.
+pub fn in_values(value: &Value, candidates: &[Value]) -> Value {
+    if value.is_null() {
+        return Value::Null;
+    }
+    if candidates.iter().any(|candidate| value.same_key(candidate)) {
+        Value::Boolean(true)
+    } else if candidates.iter().any(Value::is_null) {
+        Value::Null
+    } else {
+        Value::Boolean(false)
+    }
+}
+
+pub fn binary_operation(left: &Value, op: &BinaryOperator, right: &Value) -> Result<Value, String> {
+    if left.is_null() || right.is_null() {
+        return Ok(Value::Null);
+    }
+    let comparison = |accept: fn(Ordering) -> bool| {
+        Ok(left
+            .compare(right)
+            .map_or(Value::Null, |ordering| Value::Boolean(accept(ordering))))
+    };
+    match op {
+        BinaryOperator::Eq => comparison(|o| o == Ordering::Equal),
+        BinaryOperator::NotEq => comparison(|o| o != Ordering::Equal),
+        BinaryOperator::Lt => comparison(|o| o == Ordering::Less),
+        BinaryOperator::LtEq => comparison(|o| o != Ordering::Greater),
+        BinaryOperator::Gt => comparison(|o| o == Ordering::Greater),
+        BinaryOperator::GtEq => comparison(|o| o != Ordering::Less),
+        BinaryOperator::StringConcat => Ok(Value::Text(format!("{}{}", left, right))),
+        BinaryOperator::Plus
+        | BinaryOperator::Minus
+        | BinaryOperator::Multiply
+        | BinaryOperator::Divide
+        | BinaryOperator::Modulo => arithmetic(left, op, right),
+        _ => Err(format!("Unsupported operator: {}", op)),
+    }
+}
+
+fn arithmetic(left: &Value, op: &BinaryOperator, right: &Value) -> Result<Value, String> {
+    let numeric = |value: &Value| match value {
+        Value::Integer(_) | Value::Float(_) => Ok(value.clone()),
+        Value::Text(s) => Ok(Value::infer(s.trim())),
+        other => Err(format!("Cannot apply {} to {}", op, other)),
+    };
+    match (numeric(left)?, numeric(right)?) {
+        (Value::Integer(a), Value::Integer(b)) => {
+            let result = match op {
+                BinaryOperator::Plus => a.checked_add(b),
+                BinaryOperator::Minus => a.checked_sub(b),
+                BinaryOperator::Multiply => a.checked_mul(b),
+                BinaryOperator::Divide | BinaryOperator::Modulo if b == 0 => {
+                    return Err("Division by zero".to_string())
+                }
+                BinaryOperator::Divide => a.checked_div(b),
+                _ => a.checked_rem(b),
+            };
+            result
+                .map(Value::Integer)
+                .ok_or_else(|| "Integer out of range".to_string())
+        }
+        (Value::Float(a), Value::Float(b)) => float_arithmetic(a, op, b),
+        (Value::Integer(a), Value::Float(b)) => float_arithmetic(a as f64, op, b),
+        (Value::Float(a), Value::Integer(b)) => float_arithmetic(a, op, b as f64),
+        _ => Err(format!("Cannot apply {} to {} and {}", op, left, right)),
+    }
+}
+
+fn float_arithmetic(a: f64, op: &BinaryOperator, b: f64) -> Result<Value, String> {
+    match op {
+        BinaryOperator::Plus => Ok(Value::Float(a + b)),
+        BinaryOperator::Minus => Ok(Value::Float(a - b)),
+        BinaryOperator::Multiply => Ok(Value::Float(a * b)),
+        _ if b == 0.0 => Err("Division by zero".to_string()),
+        BinaryOperator::Divide => Ok(Value::Float(a / b)),
+        _ => Ok(Value::Float(a % b)),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn like(
+    expr: &Expr,
+    pattern: &Expr,
+    escape_char: Option<char>,
+    negated: bool,
+    case_insensitive: bool,
+    scope: &Scope,
+    database: &Database,
+) -> Result<Value, String> {
+    let value = evaluate(expr, scope, database)?;
+    let pattern = evaluate(pattern, scope, database)?;
+    if value.is_null() || pattern.is_null() {
+        return Ok(Value::Null);
+    }
+    let (mut text, mut pattern) = (value.to_string(), pattern.to_string());
+    if case_insensitive {
+        text = text.to_lowercase();
+        pattern = pattern.to_lowercase();
+    }
+    let text: Vec<char> = text.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    Ok(negate_if(
+        Value::Boolean(like_match(&text, &pattern, escape_char)),
+        negated,
+    ))
+}
+
+fn like_match(text: &[char], pattern: &[char], escape_char: Option<char>) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((&c, rest)) if Some(c) == escape_char => match rest.split_first() {
+            Some((&literal, rest)) => {
+                text.first() == Some(&literal) && like_match(&text[1..], rest, escape_char)
+            }
+            None => false,
+        },
+        Some(('%', rest)) => {
+            (0..=text.len()).any(|skip| like_match(&text[skip..], rest, escape_char))
+        }
+        Some(('_', rest)) => !text.is_empty() && like_match(&text[1..], rest, escape_char),
+        Some((&c, rest)) => text.first() == Some(&c) && like_match(&text[1..], rest, escape_char),
+    }
+}
+
+fn call_function(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    let expect_args = |count: usize| {
+        if args.len() == count {
+            Ok(())
+        } else {
+            Err(format!("{} expects {} argument(s)", name, count))
+        }
+    };
+    match name {
+        "COALESCE" | "IFNULL" => Ok(args
+            .into_iter()
+            .find(|arg| !arg.is_null())
+            .unwrap_or(Value::Null)),
+        "NULLIF" => {
+            expect_args(2)?;
+            if args[0].same_key(&args[1]) {
+                Ok(Value::Null)
+            } else {
+                Ok(args[0].clone())
+            }
+        }
+        "UPPER" | "LOWER" | "LENGTH" | "ABS" => {
+            expect_args(1)?;
+            match (name, &args[0]) {
+                (_, Value::Null) => Ok(Value::Null),
+                ("UPPER", value) => Ok(Value::Text(value.to_string().to_uppercase())),
+                ("LOWER", value) => Ok(Value::Text(value.to_string().to_lowercase())),
+                ("LENGTH", value) => Ok(Value::Integer(value.to_string().chars().count() as i64)),
+                (_, Value::Integer(n)) => Ok(Value::Integer(n.abs())),
+                (_, Value::Float(f)) => Ok(Value::Float(f.abs())),
+                (_, value) => Err(format!("Cannot apply ABS to {}", value)),
+            }
+        }
+        "ROUND" => {
+            let digits = match args.get(1) {
+                Some(Value::Integer(digits)) => *digits as i32,
+                None => 0,
+                Some(other) => return Err(format!("Invalid precision for ROUND: {}", other)),
+            };
+            match args.first() {
+                Some(Value::Integer(n)) => Ok(Value::Integer(*n)),
+                Some(Value::Float(f)) => {
+                    let factor = 10f64.powi(digits);
+                    Ok(Value::Float((f * factor).round() / factor))
+                }
+                Some(Value::Null) => Ok(Value::Null),
+                _ => Err("ROUND expects a number".to_string()),
+            }
+        }
+        _ => Err(format!("Unknown function: {}", name)),
+    }
+}
@@ -0,0 +1,297 @@
+use super::database::quote_identifier;
+use super::value::Value;
+use std::io::Write;
+
+/// The version of the database file format, given on its first line as
+/// `Format: 3`. Files without that line are of the unversioned format 1, and
+/// those of format 2 have no checksums.
+pub const FORMAT_VERSION: u32 = 3;
+
+/// Writes a `Tag: payload` line.
+pub fn write_line(file: &mut impl Write, tag: &str, payload: &str) -> std::io::Result<()> {
+    file.write_all(format_line(tag, payload).as_bytes())
+}
+
+/// A `Tag: payload` line, ending with a line break. Backslashes and line
+/// breaks in the payload are escaped, so that any payload takes one line.
+pub fn format_line(tag: &str, payload: &str) -> String {
+    let mut line = String::with_capacity(tag.len() + payload.len() + 3);
+    line.push_str(tag);
+    line.push_str(": ");
+    for ch in payload.chars() {
+        match ch {
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            ch => line.push(ch),
+        }
+    }
+    line.push('\n');
+    line
+}
+
+/// The CRC-32 of the lines of a table, which its `Checksum:` line records so
+/// that a damaged file is noticed when it is read.
+pub struct Checksum(u32);
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Checksum(!0)
+    }
+}
+
+impl Checksum {
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = CRC_TABLE[((self.0 ^ *byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn value(&self) -> String {
+        format!("{:08x}", !self.0)
+    }
+}
+
+/// The remainders of CRC-32 with the IEEE polynomial, one per byte value.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut remainder = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            remainder = if remainder & 1 == 1 {
+                0xedb8_8320 ^ (remainder >> 1)
+            } else {
+                remainder >> 1
+            };
+            bit += 1;
+        }
+        table[n] = remainder;
+        n += 1;
+    }
+    table
+};
+
+/// Splits a line written by `write_line` into its tag and payload.
+pub fn read_line(line: &str) -> Result<(&str, String), String> {
+    let (tag, escaped) = line
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid line: {}", line))?;
+    let escaped = escaped.strip_prefix(' ').unwrap_or(escaped);
+    let mut payload = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            payload.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => payload.push('\\'),
+            Some('n') => payload.push('\n'),
+            Some('r') => payload.push('\r'),
+            _ => return Err(format!("Invalid escape in line: {}", line)),
+        }
+    }
+    Ok((tag, payload))
+}
+
+/// The payload of a `Row:` line: each value written so that its type can be
+/// told from it. Text is quoted with quotes doubled, NULL is spelled out and
+/// floats always have a decimal point or exponent.
+pub fn encode_row(row: &[Value]) -> String {
+    let fields: Vec<String> = row
+        .iter()
+        .map(|value| match value {
+            Value::Null => "NULL".to_string(),
+            Value::Integer(n) => n.to_string(),
+            Value::Float(x) => format!("{:?}", x),
+            Value::Boolean(b) => b.to_string().to_uppercase(),
+            Value::Text(text) => format!("'{}'", text.replace('\'', "''")),
+        })
+        .collect();
+    fields.join(", ")
+}
+
+pub fn decode_row(payload: &str) -> Result<Vec<Value>, String> {
+    split_fields(payload)?
+        .into_iter()
+        .map(|field| match field {
+            "NULL" => Ok(Value::Null),
+            "TRUE" => Ok(Value::Boolean(true)),
+            "FALSE" => Ok(Value::Boolean(false)),
+            quoted if quoted.starts_with('\'') => match closing_quote(&quoted[1..], '\'') {
+                Some(end) if end == quoted.len() - 2 => {
+                    Ok(Value::Text(quoted[1..=end].replace("''", "'")))
+                }
+                _ => Err(format!("Invalid value: {}", quoted)),
+            },
+            number => number
+                .parse::<i64>()
+                .map(Value::Integer)
+                .or_else(|_| number.parse::<f64>().map(Value::Float))
+                .map_err(|_| format!("Invalid value: {}", number)),
+        })
+        .collect()
+}
+
+/// The payload of a `Columns:` line: the name and type of each column. Names
+/// that are not plain identifiers are double-quoted.
+pub fn encode_columns(columns: &[(String, String)]) -> String {
+    let fields: Vec<String> = columns
+        .iter()
+        .map(|(name, data_type)| format!("{} {}", quote_identifier(name), data_type))
+        .collect();
+    fields.join(", ")
+}
+
+pub fn decode_columns(payload: &str) -> Result<Vec<(String, String)>, String> {
+    split_fields(payload)?
+        .into_iter()
+        .map(|field| {
+            let (name, data_type) = match field.strip_prefix('"') {
+                Some(quoted) => {
+                    let end = closing_quote(quoted, '"')
+                        .ok_or_else(|| format!("Invalid column: {}", field))?;
+                    (quoted[..end].replace("\"\"", "\""), &quoted[end + 1..])
+                }
+                None => match field.split_once(' ') {
+                    Some((name, data_type)) => (name.to_string(), data_type),
+                    None => (field.to_string(), ""),
+                },
+            };
+            match data_type.trim() {
+                "" => Err(format!("Column '{}' has no type", name)),
+                data_type => Ok((name, data_type.to_string())),
+            }
+        })
+        .collect()
+}
+
+/// Splits a payload at the commas outside of quotes and parentheses, which
+/// types such as `DECIMAL(10,2)` contain, and trims each field. An empty
+/// payload has no fields, as for a table without columns.
+fn split_fields(payload: &str) -> Result<Vec<&str>, String> {
+    if payload.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut quote = None;
+    for (index, ch) in payload.char_indices() {
+        match (quote, ch) {
+            // A doubled quote closes the quoted part and opens it again
+            (Some(open), ch) if ch == open => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(ch),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                fields.push(payload[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if quote.is_some() {
+        return Err(format!("Unterminated quote in: {}", payload));
+    }
+    fields.push(payload[start..].trim());
+    Ok(fields)
+}
+
+/// The byte offset of the quote closing a quoted part that starts right
+/// before `text`. A doubled quote stands for the quote itself.
+fn closing_quote(text: &str, quote: char) -> Option<usize> {
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, ch)) = chars.next() {
+        if ch == quote {
+            if chars.peek().is_some_and(|(_, next)| *next == quote) {
+                chars.next();
+            } else {
+                return Some(index);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_keep_their_values_and_types() {
+        let row = vec![
+            Value::Null,
+            Value::Integer(-7),
+            Value::Float(2.0),
+            Value::Float(1e300),
+            Value::Boolean(true),
+            Value::Text("it's, (a) \"test\"".to_string()),
+            Value::Text("NULL".to_string()),
+            Value::Text(String::new()),
+        ];
+        let payload = encode_row(&row);
+        assert_eq!(
+            payload,
+            "NULL, -7, 2.0, 1e300, TRUE, 'it''s, (a) \"test\"', 'NULL', ''"
+        );
+        assert_eq!(decode_row(&payload).unwrap(), row);
+    }
+
+    #[test]
+    fn invalid_values_are_errors() {
+        assert!(decode_row("'open").is_err());
+        assert!(decode_row("'a'b'").is_err());
+        assert!(decode_row("1, two").is_err());
+    }
+
+    #[test]
+    fn columns_keep_quoted_names_and_types_with_commas() {
+        let columns = vec![
+            ("id".to_string(), "INT".to_string()),
+            ("unit price".to_string(), "DECIMAL(10,2)".to_string()),
+            ("say \"hi\"".to_string(), "TEXT".to_string()),
+        ];
+        let payload = encode_columns(&columns);
+        assert_eq!(
+            payload,
+            "id INT, \"unit price\" DECIMAL(10,2), \"say \"\"hi\"\"\" TEXT"
+        );
+        assert_eq!(decode_columns(&payload).unwrap(), columns);
+        assert!(decode_columns("id").is_err());
+    }
+
+    #[test]
+    fn empty_payloads_have_no_fields() {
+        assert_eq!(encode_columns(&[]), "");
+        assert_eq!(decode_columns("").unwrap(), vec![]);
+        assert_eq!(encode_row(&[]), "");
+        assert_eq!(decode_row("").unwrap(), vec![]);
+        assert_eq!(decode_row("''").unwrap(), vec![Value::Text(String::new())]);
+    }
+
+    #[test]
+    fn lines_escape_line_breaks_and_backslashes() {
+        let payload = "'a\nb\r\\n'";
+        let line = format_line("Row", payload);
+        assert_eq!(line, "Row: 'a\\nb\\r\\\\n'\n");
+        assert_eq!(
+            read_line(line.trim_end()).unwrap(),
+            ("Row", payload.to_string())
+        );
+        assert!(read_line("Row: \\x").is_err());
+        assert!(read_line("no tag").is_err());
+    }
+
+    #[test]
+    fn checksum_is_crc32() {
+        let mut checksum = Checksum::default();
+        assert_eq!(checksum.value(), "00000000");
+        checksum.update(b"1234");
+        checksum.update(b"56789");
+        assert_eq!(checksum.value(), "cbf43926");
+    }
+}
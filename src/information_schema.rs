@@ -0,0 +1,418 @@
+use super::database::{constraint_name, split_name, Database, Table};
+use super::query_execution::QueryResult;
+use super::select_execution::execute_query;
+use super::value::Value;
+use sqlparser::ast::TableConstraint;
+
+/// The read-only tables of `information_schema`, generated from the catalog
+/// each time they are read.
+const SYSTEM_TABLES: [&str; 9] = [
+    "schemata",
+    "tables",
+    "columns",
+    "views",
+    "table_constraints",
+    "key_column_usage",
+    "check_constraints",
+    "indexes",
+    "sequences",
+];
+
+pub fn is_system_table(name: &str) -> bool {
+    system_table_name(name).is_some()
+}
+
+fn system_table_name(name: &str) -> Option<&'static str> {
+    let (schema, table) = name.split_once('.')?;
+    if !schema.eq_ignore_ascii_case("information_schema") {
+        return None;
+    }
+    SYSTEM_TABLES
+        .into_iter()
+        .find(|system_table| system_table.eq_ignore_ascii_case(table))
+}
+
+/// The contents of the system table called `name`, or `None` if `name` is
+/// not one. Rows come sorted by table and object name.
+pub fn system_table(database: &Database, name: &str) -> Option<Result<QueryResult, String>> {
+    let result = match system_table_name(name)? {
+        "schemata" => Ok(schemata(database)),
+        "tables" => Ok(tables(database)),
+        "columns" => columns(database),
+        "views" => Ok(views(database)),
+        "table_constraints" => Ok(table_constraints(database)),
+        "key_column_usage" => Ok(key_column_usage(database)),
+        "check_constraints" => Ok(check_constraints(database)),
+        "indexes" => Ok(indexes(database)),
+        _ => Ok(sequences(database)),
+    };
+    Some(result.map(|mut result| {
+        result.rows.sort_by(|a, b| {
+            a.iter()
+                .zip(b)
+                .map(|(a, b)| a.sort_cmp(b))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        result
+    }))
+}
+
+fn text(value: &str) -> Value {
+    Value::Text(value.to_string())
+}
+
+fn yes_no(flag: bool) -> Value {
+    text(if flag { "YES" } else { "NO" })
+}
+
+fn result(columns: &[&str], rows: Vec<Vec<Value>>) -> QueryResult {
+    QueryResult {
+        columns: columns.iter().map(|col| col.to_string()).collect(),
+        rows,
+    }
+}
+
+/// User tables, excluding the storage of materialized views.
+fn user_tables(database: &Database) -> impl Iterator<Item = &Table> {
+    database
+        .tables
+        .values()
+        .filter(|table| !database.is_materialized_view(&table.name))
+}
+
+fn schemata(database: &Database) -> QueryResult {
+    let rows = database
+        .schemas
+        .iter()
+        .map(|schema| schema.as_str())
+        .chain(["information_schema"])
+        .map(|schema| vec![text(schema)])
+        .collect();
+    result(&["schema_name"], rows)
+}
+
+fn tables(database: &Database) -> QueryResult {
+    let tables = user_tables(database).map(|table| (&table.name, "BASE TABLE"));
+    let views = database.views.values().map(|view| {
+        let table_type = if view.materialized {
+            "MATERIALIZED VIEW"
+        } else {
+            "VIEW"
+        };
+        (&view.name, table_type)
+    });
+    let rows = tables
+        .chain(views)
+        .map(|(key, table_type)| {
+            let (schema, name) = split_name(key);
+            vec![text(&schema), text(&name), text(table_type)]
+        })
+        .collect();
+    result(&["table_schema", "table_name", "table_type"], rows)
+}
+
+/// Columns of tables and views. View columns are found by running the view
+/// over tables without rows, and only materialized views know their column
+/// types.
+fn columns(database: &Database) -> Result<QueryResult, String> {
+    let mut rows = Vec::new();
+    for table in database.tables.values() {
+        let primary_key = table.primary_key().unwrap_or_default();
+        for (index, column) in table.columns.iter().enumerate() {
+            rows.push(vec![
+                text(&split_name(&table.name).0),
+                text(&split_name(&table.name).1),
+                text(&column.name),
+                Value::Integer(index as i64 + 1),
+                column
+                    .default
+                    .as_ref()
+                    .map_or(Value::Null, |default| text(&default.to_string())),
+                yes_no(!column.not_null && !primary_key.contains(&index)),
+                text(&column.data_type.to_string()),
+            ]);
+        }
+    }
+    // The columns of a query do not depend on its rows. A view that fails,
+    // such as one over a detached table, has no columns to show
+    let empty = database.without_rows();
+    for view in database.views.values().filter(|view| !view.materialized) {
+        let Ok(result) = execute_query(&empty, &view.query) else {
+            continue;
+        };
+        let mut names = result.columns;
+        for (name, alias) in names.iter_mut().zip(&view.columns) {
+            *name = alias.value.clone();
+        }
+        for (index, name) in names.iter().enumerate() {
+            rows.push(vec![
+                text(&split_name(&view.name).0),
+                text(&split_name(&view.name).1),
+                text(name),
+                Value::Integer(index as i64 + 1),
+                Value::Null,
+                yes_no(true),
+                Value::Null,
+            ]);
+        }
+    }
+    Ok(result(
+        &[
+            "table_schema",
+            "table_name",
+            "column_name",
+            "ordinal_position",
+            "column_default",
+            "is_nullable",
+            "data_type",
+        ],
+        rows,
+    ))
+}
+
+fn views(database: &Database) -> QueryResult {
+    let rows = database
+        .views
+        .values()
+        .map(|view| {
+            vec![
+                text(&split_name(&view.name).0),
+                text(&split_name(&view.name).1),
+                text(&view.query.to_string()),
+                yes_no(view.materialized),
+            ]
+        })
+        .collect();
+    result(
+        &[
+            "table_schema",
+            "table_name",
+            "view_definition",
+            "is_materialized",
+        ],
+        rows,
+    )
+}
+
+fn constraint_type(constraint: &TableConstraint) -> &'static str {
+    match constraint {
+        TableConstraint::Unique {
+            is_primary: true, ..
+        } => "PRIMARY KEY",
+        TableConstraint::Unique { .. } => "UNIQUE",
+        TableConstraint::ForeignKey { .. } => "FOREIGN KEY",
+        _ => "CHECK",
+    }
+}
+
+fn constraints(database: &Database) -> impl Iterator<Item = (&Table, &TableConstraint, String)> {
+    user_tables(database).flat_map(|table| {
+        table.constraints.iter().map(move |constraint| {
+            let name =
+                constraint_name(constraint).map_or_else(String::new, |name| name.value.clone());
+            (table, constraint, name)
+        })
+    })
+}
+
+fn table_constraints(database: &Database) -> QueryResult {
+    let rows = constraints(database)
+        .map(|(table, constraint, name)| {
+            vec![
+                text(&split_name(&table.name).0),
+                text(&name),
+                text(&split_name(&table.name).0),
+                text(&split_name(&table.name).1),
+                text(constraint_type(constraint)),
+            ]
+        })
+        .collect();
+    result(
+        &[
+            "constraint_schema",
+            "constraint_name",
+            "table_schema",
+            "table_name",
+            "constraint_type",
+        ],
+        rows,
+    )
+}
+
+/// The columns of primary keys, unique constraints and foreign keys, with the
+/// column a foreign key column refers to as in MySQL.
+fn key_column_usage(database: &Database) -> QueryResult {
+    let mut rows = Vec::new();
+    for (table, constraint, name) in constraints(database) {
+        let (columns, referenced) = match constraint {
+            TableConstraint::Unique { columns, .. } => (columns, None),
+            TableConstraint::ForeignKey {
+                columns,
+                foreign_table,
+                referred_columns,
+                ..
+            } => (columns, Some((foreign_table.to_string(), referred_columns))),
+            _ => continue,
+        };
+        for (index, column) in columns.iter().enumerate() {
+            let (referenced_table, referenced_column) = match &referenced {
+                Some((foreign_table, referred_columns)) => (
+                    text(foreign_table),
+                    referred_columns
+                        .get(index)
+                        .map_or(Value::Null, |col| text(&col.value)),
+                ),
+                None => (Value::Null, Value::Null),
+            };
+            rows.push(vec![
+                text(&split_name(&table.name).0),
+                text(&name),
+                text(&split_name(&table.name).0),
+                text(&split_name(&table.name).1),
+                text(&column.value),
+                Value::Integer(index as i64 + 1),
+                referenced_table,
+                referenced_column,
+            ]);
+        }
+    }
+    result(
+        &[
+            "constraint_schema",
+            "constraint_name",
+            "table_schema",
+            "table_name",
+            "column_name",
+            "ordinal_position",
+            "referenced_table_name",
+            "referenced_column_name",
+        ],
+        rows,
+    )
+}
+
+fn check_constraints(database: &Database) -> QueryResult {
+    let rows = constraints(database)
+        .filter_map(|(table, constraint, name)| match constraint {
+            TableConstraint::Check { expr, .. } => Some(vec![
+                text(&split_name(&table.name).0),
+                text(&name),
+                text(&expr.to_string()),
+            ]),
+            _ => None,
+        })
+        .collect();
+    result(
+        &["constraint_schema", "constraint_name", "check_clause"],
+        rows,
+    )
+}
+
+/// One row per column of each index, like MySQL's `information_schema.statistics`.
+fn indexes(database: &Database) -> QueryResult {
+    let mut rows = Vec::new();
+    for table in database.tables.values() {
+        for index in &table.indexes {
+            for (position, column) in index.columns.iter().enumerate() {
+                rows.push(vec![
+                    text(&split_name(&table.name).0),
+                    text(&split_name(&table.name).1),
+                    text(&index.name),
+                    yes_no(index.unique),
+                    text(column),
+                    Value::Integer(position as i64 + 1),
+                ]);
+            }
+        }
+    }
+    result(
+        &[
+            "table_schema",
+            "table_name",
+            "index_name",
+            "is_unique",
+            "column_name",
+            "ordinal_position",
+        ],
+        rows,
+    )
+}
+
+fn sequences(database: &Database) -> QueryResult {
+    let rows = database
+        .sequences
+        .values()
+        .map(|sequence| {
+            vec![
+                text(&split_name(&sequence.name).0),
+                text(&split_name(&sequence.name).1),
+                Value::Integer(sequence.start),
+                Value::Integer(sequence.min_value),
+                Value::Integer(sequence.max_value),
+                Value::Integer(sequence.increment),
+                yes_no(sequence.cycle),
+            ]
+        })
+        .collect();
+    result(
+        &[
+            "sequence_schema",
+            "sequence_name",
+            "start_value",
+            "minimum_value",
+            "maximum_value",
+            "increment",
+            "cycle_option",
+        ],
+        rows,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query_execution::StatementOutput;
+    use crate::sql_parsing::parse_statements;
+    use crate::transactions::Session;
+
+    fn run(database: &mut Database, sql: &str) -> Vec<StatementOutput> {
+        Session::default()
+            .execute(database, parse_statements(sql).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn views_that_fail_have_no_columns() {
+        let other = std::env::temp_dir().join(format!("dbms-{}-other.db", std::process::id()));
+        let other = other.to_string_lossy().to_string();
+        let _ = std::fs::remove_file(&other);
+        let mut database = Database::new("test.db");
+        run(
+            &mut database,
+            &format!(
+                "CREATE TABLE t (a INT); INSERT INTO t VALUES (1);
+                CREATE VIEW v AS SELECT a + 1 AS n, a FROM t;
+                CREATE SEQUENCE s; CREATE VIEW q AS SELECT nextval('s') AS id FROM t;
+                ATTACH DATABASE '{}' AS o; CREATE TABLE o.t2 (x INT);
+                CREATE VIEW w AS SELECT x FROM o.t2; DETACH DATABASE o",
+                other
+            ),
+        );
+        let last_value = database.sequences["s"].last_value.get();
+        let result = columns(&database).unwrap();
+        // Finding the columns of a view does not run it over the rows
+        assert_eq!(database.sequences["s"].last_value.get(), last_value);
+        let names: Vec<(String, String)> = result
+            .rows
+            .iter()
+            .map(|row| (row[1].to_string(), row[2].to_string()))
+            .collect();
+        let expected = [("t", "a"), ("q", "id"), ("v", "n"), ("v", "a")];
+        let expected: Vec<(String, String)> = expected
+            .iter()
+            .map(|(table, column)| (table.to_string(), column.to_string()))
+            .collect();
+        assert_eq!(names, expected);
+    }
+}
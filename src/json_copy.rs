@@ -0,0 +1,191 @@
+use super::database::{Database, Table};
+use super::query_execution::{build_insert_row, check_row};
+use serde_json::{Map, Value as JsonValue};
+use sqlparser::ast::{ColumnDef, DataType, Expr, Ident, Value as Literal};
+
+/// A JSON object to import, with where it was found for error messages.
+struct Record {
+    position: String,
+    object: Map<String, JsonValue>,
+}
+
+/// Appends the objects of a JSON array, or of NDJSON with one object per
+/// line, to a table and returns how many there were. Keys are matched to
+/// column names, and columns without a key get their default. With
+/// `create_table`, a table that does not exist yet is created, with columns
+/// for every key and types inferred from that many of the first records.
+pub fn copy_json_from(
+    database: &mut Database,
+    table_name: &str,
+    columns: &[Ident],
+    filename: &str,
+    text: &str,
+    ndjson: bool,
+    create_table: Option<usize>,
+) -> Result<usize, String> {
+    let records = read_json(text, ndjson).map_err(|err| format!("{}: {}", filename, err))?;
+    let mut table = match database.tables.get(table_name) {
+        Some(table) if !database.is_materialized_view(table_name) => table.clone(),
+        _ if database.views.contains_key(table_name) => {
+            return Err(format!("Cannot copy into view '{}'", table_name))
+        }
+        _ => {
+            let Some(sample_size) = create_table else {
+                return Err(format!("Table '{}' does not exist", table_name));
+            };
+            let table = infer_table(table_name, &records, sample_size)?;
+            database.report(format!("Created table: {}", table_name));
+            table
+        }
+    };
+    for column in columns {
+        if table.column_index(&column.value).is_none() {
+            return Err(format!(
+                "Column '{}' does not exist in table '{}'",
+                column.value, table_name
+            ));
+        }
+    }
+
+    let count = records.len();
+    for record in records {
+        let at = |err: String| format!("{}: {}: {}", filename, record.position, err);
+        let mut column_indexes = Vec::new();
+        let mut exprs = Vec::new();
+        for (key, value) in &record.object {
+            // With a column list, the other keys are left out
+            if !columns.is_empty() && !columns.iter().any(|column| column.value == *key) {
+                continue;
+            }
+            let index = table.column_index(key).ok_or_else(|| {
+                at(format!(
+                    "key '{}' is not a column of table '{}'",
+                    key, table_name
+                ))
+            })?;
+            column_indexes.push(index);
+            exprs.push(json_expr(value));
+        }
+        // Without any known key the row would be made of defaults alone
+        if column_indexes.is_empty() {
+            return Err(at("no key matches a column".to_string()));
+        }
+        let row = build_insert_row(&table, &column_indexes, &exprs, database).map_err(at)?;
+        check_row(&table, &row, None, database).map_err(at)?;
+        table.push_row(row);
+    }
+    database.tables.insert(table_name.to_string(), table);
+    Ok(count)
+}
+
+fn read_json(text: &str, ndjson: bool) -> Result<Vec<Record>, String> {
+    let record = |position: String, value: JsonValue| match value {
+        JsonValue::Object(object) => Ok(Record { position, object }),
+        other => Err(format!(
+            "{}: expected an object, found {}",
+            position,
+            json_type(&other)
+        )),
+    };
+    if ndjson {
+        let mut records = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let position = format!("line {}", index + 1);
+            let value = serde_json::from_str(line)
+                .map_err(|err| format!("{}: invalid JSON: {}", position, err))?;
+            records.push(record(position, value)?);
+        }
+        return Ok(records);
+    }
+    match serde_json::from_str(text).map_err(|err| format!("invalid JSON: {}", err))? {
+        JsonValue::Array(values) => values
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| record(format!("record {}", index + 1), value))
+            .collect(),
+        other => Err(format!(
+            "expected an array of objects, found {}",
+            json_type(&other)
+        )),
+    }
+}
+
+fn json_type(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "a boolean",
+        JsonValue::Number(_) => "a number",
+        JsonValue::String(_) => "a string",
+        JsonValue::Array(_) => "an array",
+        JsonValue::Object(_) => "an object",
+    }
+}
+
+/// A JSON value as a literal to insert. Arrays and objects are kept as
+/// their JSON text.
+fn json_expr(value: &JsonValue) -> Expr {
+    Expr::Value(match value {
+        JsonValue::Null => Literal::Null,
+        JsonValue::Bool(b) => Literal::Boolean(*b),
+        JsonValue::Number(n) => Literal::Number(n.to_string(), false),
+        JsonValue::String(s) => Literal::SingleQuotedString(s.clone()),
+        other => Literal::SingleQuotedString(other.to_string()),
+    })
+}
+
+/// A table with a column for each key of the records, in the order the keys
+/// first appear. A column is INTEGER, FLOAT or BOOLEAN when every non-null
+/// value of the first `sample_size` records is of that kind, and TEXT
+/// otherwise.
+fn infer_table(name: &str, records: &[Record], sample_size: usize) -> Result<Table, String> {
+    let mut columns: Vec<(String, Option<DataType>)> = Vec::new();
+    for (index, record) in records.iter().enumerate() {
+        for (key, value) in &record.object {
+            let position = match columns.iter().position(|(name, _)| name == key) {
+                Some(position) => position,
+                None => {
+                    columns.push((key.clone(), None));
+                    columns.len() - 1
+                }
+            };
+            if index >= sample_size {
+                continue;
+            }
+            let data_type = match value {
+                JsonValue::Null => continue,
+                JsonValue::Bool(_) => DataType::Boolean,
+                JsonValue::Number(n) if n.is_i64() => DataType::Integer(None),
+                JsonValue::Number(_) => DataType::Float(None),
+                _ => DataType::Text,
+            };
+            let column_type = &mut columns[position].1;
+            *column_type = Some(match (column_type.take(), data_type) {
+                (None, data_type) => data_type,
+                (Some(a), b) if a == b => a,
+                // Integers and floats mix into floats
+                (Some(DataType::Integer(_)), DataType::Float(_))
+                | (Some(DataType::Float(_)), DataType::Integer(_)) => DataType::Float(None),
+                _ => DataType::Text,
+            });
+        }
+    }
+    if columns.is_empty() {
+        return Err(format!(
+            "Cannot create table '{}' without any record to infer its columns from",
+            name
+        ));
+    }
+    let column_defs: Vec<ColumnDef> = columns
+        .into_iter()
+        .map(|(column_name, data_type)| ColumnDef {
+            name: Ident::new(column_name),
+            data_type: data_type.unwrap_or(DataType::Text),
+            collation: None,
+            options: vec![],
+        })
+        .collect();
+    Table::from_definition(name, &column_defs, &[])
+}
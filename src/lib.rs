@@ -0,0 +1,42 @@
+//! A small SQL database kept in a text file.
+//!
+//! Threads share a database through a [`SharedDatabase`], each working
+//! through a [`Connection`] of its own:
+//!
+//! ```no_run
+//! let database = dbms::SharedDatabase::open("db.txt").unwrap();
+//! let mut connection = database.connect();
+//! connection.execute("CREATE TABLE t (id INT PRIMARY KEY, name TEXT)").unwrap();
+//! connection.execute("INSERT INTO t VALUES (1, 'a')").unwrap();
+//! let result = connection.query("SELECT name FROM t").unwrap();
+//! assert_eq!(result.rows[0][0], dbms::Value::Text("a".to_string()));
+//! database.save().unwrap();
+//! ```
+
+mod attachments;
+pub mod cli;
+mod connection;
+mod copy;
+mod database;
+mod dump;
+mod expression_evaluation;
+mod file_format;
+mod information_schema;
+mod json_copy;
+mod output;
+mod query_execution;
+mod schemas;
+mod select_execution;
+mod sequences;
+mod shell;
+mod show_statements;
+mod sql_parsing;
+mod table_alteration;
+mod transactions;
+mod value;
+mod views;
+use database::Table;
+
+pub use connection::{Connection, SharedDatabase};
+pub use query_execution::{QueryResult, StatementOutput};
+pub use value::Value;
@@ -0,0 +1,286 @@
+use super::query_execution::QueryResult;
+use super::value::Value;
+use unicode_width::UnicodeWidthStr;
+
+/// How query results are printed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputMode {
+    /// Aligned columns inside a box, with a header and a row count.
+    Table,
+    Csv,
+    Tsv,
+    /// One array holding an object per row.
+    Json,
+    /// One object per line.
+    Ndjson,
+    Markdown,
+    /// One block per row, with a line per column.
+    Vertical,
+}
+
+impl OutputMode {
+    pub const NAMES: [&'static str; 7] = [
+        "table", "csv", "tsv", "json", "ndjson", "markdown", "vertical",
+    ];
+
+    /// Modes whose output other programs read, into which the reports of
+    /// what statements did must not be mixed.
+    pub fn is_data(self) -> bool {
+        matches!(
+            self,
+            OutputMode::Csv | OutputMode::Tsv | OutputMode::Json | OutputMode::Ndjson
+        )
+    }
+
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "table" | "box" => Ok(OutputMode::Table),
+            "csv" => Ok(OutputMode::Csv),
+            "tsv" | "tabs" => Ok(OutputMode::Tsv),
+            "json" => Ok(OutputMode::Json),
+            "ndjson" | "jsonl" => Ok(OutputMode::Ndjson),
+            "markdown" | "md" => Ok(OutputMode::Markdown),
+            "vertical" | "line" => Ok(OutputMode::Vertical),
+            _ => Err(format!(
+                "Unknown output mode '{}', expected one of: {}",
+                name,
+                OutputMode::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// The text of a result in the given mode, ending with a newline.
+pub fn render(result: &QueryResult, mode: OutputMode) -> String {
+    match mode {
+        OutputMode::Table => render_table(result),
+        OutputMode::Csv => render_delimited(result, ',', csv_field),
+        OutputMode::Tsv => render_delimited(result, '\t', tsv_field),
+        OutputMode::Json => {
+            let objects: Vec<String> = result
+                .rows
+                .iter()
+                .map(|row| json_object(&result.columns, row))
+                .collect();
+            if objects.is_empty() {
+                "[]\n".to_string()
+            } else {
+                format!("[{}]\n", objects.join(",\n"))
+            }
+        }
+        OutputMode::Ndjson => result
+            .rows
+            .iter()
+            .map(|row| json_object(&result.columns, row) + "\n")
+            .collect(),
+        OutputMode::Markdown => render_markdown(result),
+        OutputMode::Vertical => render_vertical(result),
+    }
+}
+
+/// A value as shown in a table: NULL spelled out, and line breaks and tabs
+/// escaped so that each row stays on one line.
+fn display(value: &Value) -> String {
+    match value {
+        Value::Text(text) => text
+            .replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+            .replace('\t', "\\t"),
+        value => value.to_string(),
+    }
+}
+
+fn is_number(value: &Value) -> bool {
+    matches!(value, Value::Integer(_) | Value::Float(_))
+}
+
+/// Columns whose values are all numbers, or NULL, are aligned to the right.
+fn numeric_columns(result: &QueryResult) -> Vec<bool> {
+    (0..result.columns.len())
+        .map(|index| {
+            result.rows.iter().any(|row| is_number(&row[index]))
+                && result
+                    .rows
+                    .iter()
+                    .all(|row| is_number(&row[index]) || matches!(row[index], Value::Null))
+        })
+        .collect()
+}
+
+/// `text` padded with spaces to `width` terminal columns.
+fn pad(text: &str, width: usize, right_align: bool) -> String {
+    let padding = " ".repeat(width.saturating_sub(text.width()));
+    if right_align {
+        padding + text
+    } else {
+        text.to_string() + &padding
+    }
+}
+
+fn render_table(result: &QueryResult) -> String {
+    let cells: Vec<Vec<String>> = result
+        .rows
+        .iter()
+        .map(|row| row.iter().map(display).collect())
+        .collect();
+    let widths: Vec<usize> = result
+        .columns
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            cells
+                .iter()
+                .map(|row| row[index].width())
+                .chain([name.width()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let numeric = numeric_columns(result);
+    let border = format!(
+        "+{}+\n",
+        widths
+            .iter()
+            .map(|width| "-".repeat(width + 2))
+            .collect::<Vec<String>>()
+            .join("+")
+    );
+    let line = |row: &[String], align: &[bool]| {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .zip(align)
+            .map(|((cell, width), right)| pad(cell, *width, *right))
+            .collect();
+        format!("| {} |\n", cells.join(" | "))
+    };
+
+    let mut output = border.clone();
+    output += &line(&result.columns, &vec![false; widths.len()]);
+    output += &border;
+    for row in &cells {
+        output += &line(row, &numeric);
+    }
+    if !cells.is_empty() {
+        output += &border;
+    }
+    let count = result.rows.len();
+    output += &format!("({} row{})\n", count, if count == 1 { "" } else { "s" });
+    output
+}
+
+fn render_delimited(result: &QueryResult, delimiter: char, field: fn(&Value) -> String) -> String {
+    let header = result
+        .columns
+        .iter()
+        .map(|name| field(&Value::Text(name.clone())))
+        .collect::<Vec<String>>();
+    std::iter::once(header)
+        .chain(
+            result
+                .rows
+                .iter()
+                .map(|row| row.iter().map(field).collect()),
+        )
+        .map(|fields| fields.join(&delimiter.to_string()) + "\n")
+        .collect()
+}
+
+/// A CSV field as in RFC 4180: quoted when it holds a comma, a quote or a
+/// line break, with quotes doubled. NULL is an empty field.
+pub fn csv_field(value: &Value) -> String {
+    let text = match value {
+        Value::Null => return String::new(),
+        value => value.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+/// A TSV field, with tabs, line breaks and backslashes escaped by a
+/// backslash. NULL is an empty field.
+fn tsv_field(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        value => display(value),
+    }
+}
+
+/// A row as a JSON object keyed by column name.
+pub fn json_object(columns: &[String], row: &[Value]) -> String {
+    let members: Vec<String> = columns
+        .iter()
+        .zip(row)
+        .map(|(name, value)| format!("{}:{}", json_string(name), json_value(value)))
+        .collect();
+    format!("{{{}}}", members.join(","))
+}
+
+pub fn json_value(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Integer(n) => n.to_string(),
+        // JSON has no NaN or infinity
+        Value::Float(x) if !x.is_finite() => "null".to_string(),
+        Value::Float(x) if x.fract() == 0.0 && x.abs() < 1e15 => format!("{:.1}", x),
+        Value::Float(x) => x.to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Text(text) => json_string(text),
+    }
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for ch in text.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn render_markdown(result: &QueryResult) -> String {
+    let escape = |text: String| text.replace('|', "\\|");
+    let numeric = numeric_columns(result);
+    let line = |cells: Vec<String>| format!("| {} |\n", cells.join(" | "));
+    let mut output = line(result.columns.iter().cloned().map(escape).collect());
+    output += &line(
+        numeric
+            .iter()
+            .map(|right| if *right { "---:" } else { "---" }.to_string())
+            .collect(),
+    );
+    for row in &result.rows {
+        output += &line(row.iter().map(|value| escape(display(value))).collect());
+    }
+    output
+}
+
+fn render_vertical(result: &QueryResult) -> String {
+    let name_width = result
+        .columns
+        .iter()
+        .map(|name| name.width())
+        .max()
+        .unwrap_or(0);
+    let mut output = String::new();
+    for (index, row) in result.rows.iter().enumerate() {
+        output += &format!("{:*^59}\n", format!(" {}. row ", index + 1));
+        for (name, value) in result.columns.iter().zip(row) {
+            output += &format!("{}: {}\n", pad(name, name_width, true), display(value));
+        }
+    }
+    output
+}
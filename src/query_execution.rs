@@ -1,691 +1,1190 @@
-use super::database::Database;
+use super::attachments::{attach_database, backup_database, detach_database};
+use super::copy::copy;
+use super::database::{constraint_name, split_name, Database};
+use super::expression_evaluation::{evaluate, evaluate_predicate, evaluate_row_subquery};
+use super::expression_evaluation::{ColumnRef, Scope};
+use super::schemas::{create_schema, drop_schemas, qualify_names, resolve_name};
+use super::schemas::{set_search_path, show_search_path};
+use super::select_execution::{execute_query, project, resolve_from};
+use super::sequences::drop_sequences;
+use super::sequences::{advance_auto_increment, auto_increment_columns, create_sequence};
+use super::show_statements::{show_columns, show_create, show_tables};
+use super::sql_parsing::ParsedStatement;
+use super::table_alteration::{alter_table, create_index, drop_indexes, drop_tables};
+use super::table_alteration::{set_generated, truncate_table};
+use super::transactions::vacuum;
+use super::value::Value;
+use super::views::{create_view, drop_views, refresh_materialized_view, rewrite_view_write};
 use super::Table;
-use sqlparser::ast::Statement;
-use sqlparser::ast::{ColumnDef, DataType};
-use std::collections::HashMap;
+use sqlparser::ast::{Assignment, ConflictTarget, Expr, OnConflictAction, OnInsert, Statement};
+use sqlparser::ast::{Ident, SelectItem, SqliteOnConflict, TableConstraint};
 
-pub fn execute_queries(database: &mut Database, ast: Vec<Statement>) {
+/// The rows produced by a query or by a statement with a RETURNING clause.
+#[derive(Debug, Clone)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// What running a statement gives back: its rows, the text COPY ... TO
+/// STDOUT writes, or a report of what it did.
+#[derive(Debug, Clone)]
+pub enum StatementOutput {
+    Rows(QueryResult),
+    Text(String),
+    Message(String),
+}
+
+/// Runs one statement and returns what it reports, followed by its rows.
+pub fn execute_statement(
+    database: &mut Database,
+    statement: ParsedStatement,
+) -> Result<Vec<StatementOutput>, String> {
+    let results = execute_queries(database, vec![statement]);
+    let mut output = std::mem::take(&mut database.output);
+    output.extend(results?.into_iter().map(StatementOutput::Rows));
+    Ok(output)
+}
+
+pub fn execute_queries(
+    database: &mut Database,
+    ast: Vec<ParsedStatement>,
+) -> Result<Vec<QueryResult>, String> {
+    let mut results = Vec::new();
     // Execute the parsed SQL statements on the database
     for statement in ast {
-        match statement {
-            Statement::CreateTable { name, columns, .. } => {
-                let table_name = name.to_string();
-                let columns_info: Vec<(String, DataType)> = columns
+        let statement = match statement {
+            ParsedStatement::Sql(statement) => {
+                let mut statement = *statement;
+                qualify_names(database, &mut statement)?;
+                rewrite_view_write(database, statement)?
+            }
+            ParsedStatement::CreateMaterializedView {
+                statement,
+                with_data,
+            } => {
+                let mut statement = *statement;
+                qualify_names(database, &mut statement)?;
+                let Statement::CreateView {
+                    materialized: true,
+                    name,
+                    columns,
+                    query,
+                    ..
+                } = statement
+                else {
+                    return Err("WITH [NO] DATA only applies to materialized views".to_string());
+                };
+                create_view(database, false, true, &name, columns, *query, with_data)?;
+                continue;
+            }
+            ParsedStatement::RefreshMaterializedView { name } => {
+                refresh_materialized_view(database, &resolve_name(database, &name)?)?;
+                continue;
+            }
+            ParsedStatement::DropMaterializedView {
+                names,
+                if_exists,
+                cascade,
+            } => {
+                let names = names
                     .iter()
-                    .map(|col| {
-                        let ColumnDef {
-                            name, data_type, ..
-                        } = col;
-                        (name.to_string(), data_type.clone())
-                    })
-                    .collect();
-                let column_names: Vec<String> =
-                    columns_info.iter().map(|(name, _)| name.clone()).collect();
-                let new_table = Table {
-                    name: table_name.clone(),
-                    columns: column_names,
-                    data: vec![],
+                    .map(|name| resolve_name(database, name))
+                    .collect::<Result<Vec<_>, String>>()?;
+                drop_views(database, &names, true, if_exists, cascade)?;
+                continue;
+            }
+            ParsedStatement::AttachDatabase { file_path, name } => {
+                attach_database(database, &file_path, &name)?;
+                continue;
+            }
+            ParsedStatement::DetachDatabase { name } => {
+                detach_database(database, &name)?;
+                continue;
+            }
+            ParsedStatement::BackupDatabase { name, file_path } => {
+                backup_database(database, name.as_ref(), &file_path)?;
+                continue;
+            }
+            ParsedStatement::SetGenerated {
+                table_name,
+                column_name,
+                always,
+            } => {
+                let table_name = resolve_name(database, &table_name)?.to_string();
+                set_generated(database, &table_name, &column_name, always)?;
+                continue;
+            }
+            ParsedStatement::Vacuum { table_name } => {
+                vacuum(database, table_name.as_ref())?;
+                continue;
+            }
+            ParsedStatement::CopyCreatingTable {
+                statement,
+                sample_size,
+            } => {
+                let mut statement = *statement;
+                qualify_names(database, &mut statement)?;
+                let Statement::Copy {
+                    source,
+                    to,
+                    target,
+                    options,
+                    legacy_options,
+                    ..
+                } = statement
+                else {
+                    return Err("CREATE_TABLE only applies to COPY".to_string());
                 };
+                let create_table = Some(sample_size);
+                copy(
+                    database,
+                    &source,
+                    to,
+                    &target,
+                    &options,
+                    &legacy_options,
+                    create_table,
+                )?;
+                continue;
+            }
+        };
+        match statement {
+            Statement::CreateTable {
+                or_replace,
+                if_not_exists,
+                name,
+                mut columns,
+                constraints,
+                ..
+            } => {
+                let table_name = name.to_string();
+                if database.views.contains_key(&table_name) {
+                    return Err(format!("Relation '{}' already exists", table_name));
+                }
+                if database.tables.contains_key(&table_name) && !or_replace {
+                    if if_not_exists {
+                        continue;
+                    }
+                    return Err(format!("Table '{}' already exists", table_name));
+                }
+                let sequences = auto_increment_columns(database, &table_name, &mut columns)?;
+                let mut new_table = Table::from_definition(&table_name, &columns, &constraints)?;
+                database.resolve_foreign_keys(&mut new_table)?;
+                // A replaced table takes its sequences with it
+                database.sequences.retain(|_, sequence| {
+                    !matches!(&sequence.owned_by, Some((table, _)) if *table == table_name)
+                });
+                for sequence in sequences {
+                    database.sequences.insert(sequence.name.clone(), sequence);
+                }
                 database.tables.insert(table_name, new_table);
 
                 // Display the database and table info
             }
 
             Statement::Insert {
-                table_name, source, ..
+                or,
+                table_name,
+                columns,
+                source,
+                on,
+                returning,
+                ..
             } => {
                 let table_name = table_name.to_string();
-                let table = database
+                let mut table = database
                     .tables
-                    .get_mut(&table_name)
-                    .expect("Table does not exist");
-
-                if let sqlparser::ast::Query { body, .. } = *source {
-                    if let sqlparser::ast::SetExpr::Values(values) = *body {
-                        for row in values.rows {
-                            let row_values: Vec<String> =
-                                row.into_iter()
-                                    .map(|val| match val {
-                                        sqlparser::ast::Expr::Value(
-                                            sqlparser::ast::Value::Number(n, _),
-                                        ) => n.to_string(),
-                                        sqlparser::ast::Expr::Value(
-                                            sqlparser::ast::Value::SingleQuotedString(s),
-                                        ) => s,
-                                        _ => panic!("Unsupported value type"),
-                                    })
-                                    .collect();
-                            table.data.push(row_values);
-                        }
+                    .get(&table_name)
+                    .ok_or_else(|| format!("Table '{}' does not exist", table_name))?
+                    .clone();
+                let resolution = conflict_resolution(&table, or, &on)?;
+
+                let column_indexes = columns
+                    .iter()
+                    .map(|col| {
+                        table.column_index(&col.value).ok_or_else(|| {
+                            format!(
+                                "Column '{}' does not exist in table '{}'",
+                                col.value, table_name
+                            )
+                        })
+                    })
+                    .collect::<Result<Vec<usize>, String>>()?;
+
+                let mut affected_rows = Vec::new();
+                let mut replaced = Vec::new();
+                if let sqlparser::ast::SetExpr::Values(values) = *source.body {
+                    for row in values.rows {
+                        let new_row = build_insert_row(&table, &column_indexes, &row, database)?;
+                        affected_rows.extend(insert_row(
+                            &mut table,
+                            new_row,
+                            &resolution,
+                            &mut replaced,
+                            database,
+                        )?);
                     }
+                } else {
+                    return Err("Only INSERT ... VALUES is supported".to_string());
+                }
+                check_referencing_rows(&table, &replaced, database)?;
+                if let Some(returning) = returning {
+                    results.push(returning_rows(
+                        &returning,
+                        &table,
+                        &affected_rows,
+                        database,
+                    )?);
                 }
+                // Replace the table only once every row went in, so a failing
+                // statement leaves the table untouched
+                database.tables.insert(table_name, table);
             }
+            Statement::Copy {
+                source,
+                to,
+                target,
+                options,
+                legacy_options,
+                ..
+            } => copy(
+                database,
+                &source,
+                to,
+                &target,
+                &options,
+                &legacy_options,
+                None,
+            )?,
             //select * from my_table,email_table where my_table.id = email_table.id
-            Statement::Query(ref query) => {
-                if let sqlparser::ast::Query { body, .. } = query.as_ref() {
-                    if let sqlparser::ast::SetExpr::Select(select) = &**body {
-                        // Ensure there are tables to select from
-                        if !select.from.is_empty() {
-                            // Get the first table
-                            if let sqlparser::ast::TableWithJoins { relation, .. } = &select.from[0]
-                            {
-                                if let sqlparser::ast::TableFactor::Table { name, .. } = relation {
-                                    let mut table_name = name.to_string();
-                                    let mut selected_columns: Vec<String> = Vec::new();
-
-                                    // Get the columns for the first table
-                                    if let Some(first_table) = database.tables.get(&table_name) {
-                                        // Get all columns as selected_columns for the Cartesian product
-                                        selected_columns.extend(
-                                            first_table
-                                                .columns
-                                                .iter()
-                                                .map(|col| format!("{}.{}", table_name, col)),
-                                        );
-                                    } else {
-                                        panic!("Table not found in the database");
-                                    }
-
-                                    // Get the data of the first table
-                                    let mut joined_table_data: Vec<Vec<String>> =
-                                        database.tables[&table_name].data.clone();
-
-                                    // Iterate over the remaining tables in the FROM clause
-                                    for table_with_joins in &select.from[1..] {
-                                        if let sqlparser::ast::TableWithJoins { relation, .. } =
-                                            table_with_joins
-                                        {
-                                            if let sqlparser::ast::TableFactor::Table {
-                                                name: second_table_name,
-                                                ..
-                                            } = relation
-                                            {
-                                                // Get the data of the second table
-                                                let second_table_data = &database.tables
-                                                    [second_table_name.to_string().as_str()]
-                                                .data;
-
-                                                // Perform a Cartesian product with the second table
-                                                joined_table_data = joined_table_data
-                                                    .into_iter()
-                                                    .flat_map(|row| {
-                                                        second_table_data.iter().map(
-                                                            move |second_row| {
-                                                                row.iter()
-                                                                    .cloned()
-                                                                    .chain(
-                                                                        second_row.iter().cloned(),
-                                                                    )
-                                                                    .collect()
-                                                            },
-                                                        )
-                                                    })
-                                                    .collect();
-
-                                                // Add the columns from the second table with a prefix
-                                                let second_table_columns: Vec<String> = database
-                                                    .tables[second_table_name.to_string().as_str()]
-                                                .columns
-                                                .iter()
-                                                .map(|col| format!("{}.{}", second_table_name, col))
-                                                .collect();
-                                                selected_columns.extend(second_table_columns);
-                                            } else {
-                                                panic!(
-                                                    "Table in FROM clause is not a regular table"
-                                                );
-                                            }
-                                        }
-                                    }
-
-                                    println!("Table name: {}", table_name);
-                                    println!("Selected columns: {:?}", selected_columns);
-                                    println!("table:{:#?}", joined_table_data);
-                                    // Filter the joined data based on the WHERE clause
-                                    let filtered_table_data: Vec<Vec<String>> = joined_table_data
-                                        .iter()
-                                        .filter(|row| {
-                                            if let Some(selection_condition) = &select.selection {
-                                                match evaluate_condition(
-                                                    selection_condition,
-                                                    row,
-                                                    &selected_columns,
-                                                    database,
-                                                ) {
-                                                    Ok(result) => result,
-                                                    Err(err) => {
-                                                        eprintln!(
-                                                            "Error evaluating WHERE clause: {}",
-                                                            err
-                                                        );
-                                                        false
-                                                    }
-                                                }
-                                            } else {
-                                                // If there is no WHERE clause, include all rows
-                                                true
-                                            }
-                                        })
-                                        .map(|row| {
-                                            let mut selected_row_data: Vec<String> = Vec::new();
-                                            for col_name in selected_columns.iter() {
-                                                // Check if the column name is in the format "table_name.column_name"
-                                                if col_name.contains('.') {
-                                                    // If it is in the format "table_name.column_name", split it
-                                                    if let Some((table, column)) =
-                                                        split_table_column(col_name)
-                                                    {
-                                                        table_name = table.to_string();
-                                                        // Find the index of the column in the row
-                                                        if let Some(col_index) = database.tables
-                                                            [&table_name]
-                                                            .columns
-                                                            .iter()
-                                                            .position(|col| col == column)
-                                                        {
-                                                            // Add the value to the selected row data
-                                                            selected_row_data
-                                                                .push(row[col_index].clone());
-                                                        } else {
-                                                            eprintln!(
-                                                                "Column not found in the row"
-                                                            );
-                                                        }
-                                                    } else {
-                                                        eprintln!(
-                                                            "Invalid column name format: {}",
-                                                            col_name
-                                                        );
-                                                    }
-                                                } else {
-                                                    // If it is not in the format "table_name.column_name", assume it's a column name without a table prefix
-                                                    if let Some(col_index) = database.tables
-                                                        [&table_name]
-                                                        .columns
-                                                        .iter()
-                                                        .position(|col| col == col_name)
-                                                    {
-                                                        // Add the value to the selected row data
-                                                        selected_row_data
-                                                            .push(row[col_index].clone());
-                                                    } else {
-                                                        eprintln!("Column not found in the row");
-                                                    }
-                                                }
-                                            }
-                                            selected_row_data
-                                        })
-                                        .collect();
-                                    println!("Selected table data: {:?}", filtered_table_data);
-                                } else {
-                                    panic!("No table name provided");
-                                }
-                            } else {
-                                panic!("Invalid table structure in FROM clause");
-                            }
-                        } else {
-                            panic!("No tables provided in FROM clause");
-                        }
-                    } else {
-                        panic!("Unsupported query type");
-                    }
-                }
+            Statement::Query(query) => {
+                results.push(execute_query(database, &query)?);
             }
             Statement::Delete {
-                from, selection, ..
+                tables,
+                from,
+                using,
+                selection,
+                returning,
             } => {
-                if let sqlparser::ast::TableWithJoins {
-                    relation, joins, ..
-                } = &from[0]
-                {
-                    if let sqlparser::ast::TableFactor::Table { name, .. } = relation {
-                        let table_name = name.to_string();
-                        println!("Table name: {}", table_name);
-
-                        match selection {
-                            Some(select_condition) => {
-                                if let sqlparser::ast::Expr::BinaryOp { left, op, right } =
-                                    select_condition
-                                {
-                                    match (*left, op, *right) {
-                                        (
-                                            sqlparser::ast::Expr::Identifier(ident),
-                                            sqlparser::ast::BinaryOperator::Eq,
-                                            sqlparser::ast::Expr::Value(value),
-                                        ) => match ident.value.as_str() {
-                                            "id" => {
-                                                if let sqlparser::ast::Value::Number(id, _) = value
-                                                {
-                                                    if let Some(table) =
-                                                        database.tables.get_mut(&table_name)
-                                                    {
-                                                        let condition_column = "id";
-                                                        if let Some(index) = table
-                                                            .columns
-                                                            .iter()
-                                                            .position(|col| col == condition_column)
-                                                        {
-                                                            table.data.retain(|row| {
-                                                                if let Some(val) = row.get(index) {
-                                                                    *val != id
-                                                                } else {
-                                                                    true
-                                                                }
-                                                            });
-                                                            println!(
-                                                                "Deleted data from table: {:?}",
-                                                                &table_name
-                                                            );
-                                                        } else {
-                                                            panic!("Condition column does not exist in the table");
-                                                        }
-                                                    } else {
-                                                        panic!("Table not found in the database");
-                                                    }
-                                                } else {
-                                                    panic!(
-                                                        "Unsupported condition value for deletion"
-                                                    );
-                                                }
-                                            }
-                                            "name" => {
-                                                if let sqlparser::ast::Value::SingleQuotedString(
-                                                    name,
-                                                ) = value
-                                                {
-                                                    if let Some(table) =
-                                                        database.tables.get_mut(&table_name)
-                                                    {
-                                                        let condition_column = "name";
-                                                        if let Some(index) = table
-                                                            .columns
-                                                            .iter()
-                                                            .position(|col| col == condition_column)
-                                                        {
-                                                            table.data.retain(|row| {
-                                                                if let Some(val) = row.get(index) {
-                                                                    *val != name
-                                                                } else {
-                                                                    true
-                                                                }
-                                                            });
-                                                            println!(
-                                                                "Deleted data from table: {:?}",
-                                                                &table_name
-                                                            );
-                                                        } else {
-                                                            panic!("Condition column does not exist in the table");
-                                                        }
-                                                    } else {
-                                                        panic!("Table not found in the database");
-                                                    }
-                                                } else {
-                                                    panic!(
-                                                        "Unsupported condition value for deletion"
-                                                    );
-                                                }
-                                            }
-                                            "age" => {
-                                                if let sqlparser::ast::Value::Number(age, _) = value
-                                                {
-                                                    if let Some(table) =
-                                                        database.tables.get_mut(&table_name)
-                                                    {
-                                                        let condition_column = "age";
-                                                        if let Some(index) = table
-                                                            .columns
-                                                            .iter()
-                                                            .position(|col| col == condition_column)
-                                                        {
-                                                            table.data.retain(|row| {
-                                                                if let Some(val) = row.get(index) {
-                                                                    *val != age
-                                                                } else {
-                                                                    true
-                                                                }
-                                                            });
-                                                            println!(
-                                                                "Deleted data from table: {:?}",
-                                                                &table_name
-                                                            );
-                                                        } else {
-                                                            panic!("Condition column does not exist in the table");
-                                                        }
-                                                    } else {
-                                                        panic!("Table not found in the database");
-                                                    }
-                                                } else {
-                                                    panic!(
-                                                        "Unsupported condition value for deletion"
-                                                    );
-                                                }
-                                            }
-                                            _ => panic!("Unsupported column for deletion"),
-                                        },
-                                        _ => panic!("Unsupported condition structure for deletion"),
-                                    }
-                                } else {
-                                    panic!("Unsupported condition for deletion");
-                                }
-                            }
-                            None => {
-                                if let Some(table) = database.tables.get_mut(&table_name) {
-                                    // Remove all rows
-                                    table.data.clear();
-                                    println!("Deleted all data from table: {:?}", &table_name);
-                                } else {
-                                    panic!("Table not found in the database");
-                                }
+                let (table_name, alias) = match from.as_slice() {
+                    [sqlparser::ast::TableWithJoins {
+                        relation: sqlparser::ast::TableFactor::Table { name, alias, .. },
+                        joins,
+                    }] if tables.is_empty() && joins.is_empty() => (
+                        name.to_string(),
+                        alias.as_ref().map(|a| a.name.value.clone()),
+                    ),
+                    _ => return Err("DELETE supports a single target table".to_string()),
+                };
+                let target = database
+                    .tables
+                    .get(&table_name)
+                    .ok_or_else(|| format!("Table '{}' does not exist", table_name))?;
+                let qualifier = alias.unwrap_or_else(|| table_name.clone());
+
+                // Rows of DELETE ... USING are matched against every row of the
+                // USING tables; without USING each row is matched on its own
+                let mut columns = ColumnRef::for_table(&qualifier, &target.column_names());
+                let source = resolve_from(database, using.as_deref().unwrap_or_default())?;
+                columns.extend(source.columns.iter().cloned());
+
+                let mut kept = vec![true; target.data.len()];
+                let mut deleted_rows = Vec::new();
+                for (index, row) in target.data.iter().enumerate() {
+                    for source_row in &source.rows {
+                        let joined: Vec<Value> = row.iter().chain(source_row).cloned().collect();
+                        if let Some(selection) = &selection {
+                            let scope = Scope::new(&columns, &joined);
+                            if !evaluate_predicate(selection, &scope, database)? {
+                                continue;
                             }
                         }
-                    } else {
-                        panic!("No table name provided for deletion");
+                        kept[index] = false;
+                        deleted_rows.push(joined);
+                        break;
                     }
-                } else {
-                    panic!("No table specified for deletion");
                 }
+                if let Some(returning) = returning {
+                    results.push(project(
+                        &returning,
+                        &columns,
+                        &deleted_rows,
+                        None,
+                        database,
+                    )?);
+                }
+
+                let deleted: Vec<Vec<Value>> = target
+                    .data
+                    .iter()
+                    .zip(&kept)
+                    .filter(|(_, kept)| !**kept)
+                    .map(|(row, _)| row.clone())
+                    .collect();
+                let mut table = target.clone();
+                table.retain_rows(&kept);
+                check_referencing_rows(&table, &deleted, database)?;
+                database.tables.insert(table_name.clone(), table);
+                database.report(format!(
+                    "Deleted {} row(s) from table: {:?}",
+                    deleted_rows.len(),
+                    &table_name
+                ));
             }
 
             Statement::Update {
                 table,
                 assignments,
+                from,
                 selection,
-                ..
+                returning,
             } => {
-                let table_name = match table {
-                    sqlparser::ast::TableWithJoins {
-                        relation:
-                            sqlparser::ast::TableFactor::Table {
-                                name: sqlparser::ast::ObjectName(ident),
-                                ..
-                            },
-                        joins,
-                    } => ident
-                        .iter()
-                        .map(|ident| ident.value.to_string())
-                        .collect::<String>(),
-                    _ => panic!("Expected a table name"),
-                };
-                if let Some(table) = database.tables.get_mut(&table_name) {
-                    let mut column_updates: HashMap<String, String> = HashMap::new();
-                    let mut update_ids: Vec<String> = Vec::new();
-                    for assignment in assignments {
-                        if let sqlparser::ast::Assignment { id, value } = assignment {
-                            if let [sqlparser::ast::Ident { value: column, .. }] = id.as_slice() {
-                                match value {
-                                    sqlparser::ast::Expr::Value(sqlparser::ast::Value::Number(
-                                        val,
-                                        _,
-                                    )) => {
-                                        // Record the assignment: column -> val
-                                        column_updates.insert(column.clone(), val.to_string());
-                                    }
-                                    sqlparser::ast::Expr::Value(
-                                        sqlparser::ast::Value::SingleQuotedString(val),
-                                    ) => {
-                                        // Record the assignment: column -> val
-                                        column_updates.insert(column.clone(), val.clone());
-                                    }
-                                    _ => panic!("Unsupported value type for assignment"),
-                                }
-                            }
-                        }
+                let (table_name, alias) = match &table.relation {
+                    sqlparser::ast::TableFactor::Table { name, alias, .. }
+                        if table.joins.is_empty() =>
+                    {
+                        (
+                            name.to_string(),
+                            alias.as_ref().map(|a| a.name.value.clone()),
+                        )
                     }
+                    _ => return Err("Expected a table name".to_string()),
+                };
+                let mut target = database
+                    .tables
+                    .get(&table_name)
+                    .ok_or_else(|| format!("Table '{}' does not exist", table_name))?
+                    .clone();
+                let qualifier = alias.unwrap_or_else(|| table_name.clone());
+                let column_updates = assignment_targets(&target, &qualifier, &assignments)?;
 
-                    if let Some(select_condition) = selection {
-                        match select_condition {
-                            sqlparser::ast::Expr::BinaryOp { left, op, right } => {
-                                match (left.as_ref(), op, right.as_ref()) {
-                                    (
-                                        sqlparser::ast::Expr::Identifier(ident),
-                                        sqlparser::ast::BinaryOperator::Eq,
-                                        sqlparser::ast::Expr::Value(value),
-                                    ) => {
-                                        match ident.value.as_str() {
-                                            "id" => {
-                                                if let sqlparser::ast::Value::Number(id, _) = value
-                                                {
-                                                    for row in &mut table.data {
-                                                        if let Some(row_id) =
-                                                            row.get(0).map(|id| id.clone())
-                                                        {
-                                                            if row_id == *id {
-                                                                update_ids.push(row_id);
-                                                            }
-                                                        }
-                                                    }
-                                                    println!(
-                                                        "Updated data in table: {:?}",
-                                                        &table_name
-                                                    );
-                                                } else {
-                                                    panic!("Unsupported condition value for 'id'");
-                                                }
-                                            }
-                                            // Handle other condition columns if needed
-                                            _ => {
-                                                panic!("Unsupported condition column");
-                                            }
-                                        }
-                                    }
-                                    // Handle other possible condition structures
-                                    _ => panic!("Unsupported condition structure"),
-                                }
+                // Rows of UPDATE ... FROM are matched against every row of the
+                // FROM relation; without FROM each row is matched on its own
+                let mut columns = ColumnRef::for_table(&qualifier, &target.column_names());
+                let source = resolve_from(database, from.as_slice())?;
+                columns.extend(source.columns.iter().cloned());
+
+                let mut updates = Vec::new();
+                for (index, row) in target.data.iter().enumerate() {
+                    for source_row in &source.rows {
+                        let joined: Vec<Value> = row.iter().chain(source_row).cloned().collect();
+                        let scope = Scope::new(&columns, &joined);
+                        if let Some(selection) = &selection {
+                            if !evaluate_predicate(selection, &scope, database)? {
+                                continue;
                             }
-                            // Handle other possible expression structures
-                            _ => panic!("Unsupported expression structure for selection condition"),
                         }
+                        let new_row = assign(&target, row, &column_updates, &scope, database)?;
+                        updates.push((index, new_row, source_row.clone()));
+                        // A row joined to several FROM rows is only updated once
+                        break;
                     }
-                    if update_ids.is_empty() {
-                        update_ids = table.data.iter().map(|row| row[0].clone()).collect();
+                }
+
+                // Constraints are checked once every row is updated, so that
+                // e.g. `SET id = id + 1` does not collide with the next row
+                let mut changed = Vec::new();
+                for (index, new_row, _) in &updates {
+                    if target.data[*index] != *new_row {
+                        changed.push(target.data[*index].clone());
                     }
-                    apply_updates(&column_updates, update_ids, table);
-                } else {
-                    panic!("Table not found in the database)");
+                    target.update_row(*index, new_row.clone());
+                }
+                for (index, _, _) in &updates {
+                    check_row(&target, &target.data[*index], Some(*index), database)?;
+                }
+                check_referencing_rows(&target, &changed, database)?;
+                database.report(format!(
+                    "Updated {} row(s) in table: {:?}",
+                    updates.len(),
+                    &table_name
+                ));
+                if let Some(returning) = returning {
+                    let rows: Vec<Vec<Value>> = updates
+                        .into_iter()
+                        .map(|(_, new_row, source_row)| {
+                            new_row.into_iter().chain(source_row).collect()
+                        })
+                        .collect();
+                    results.push(project(&returning, &columns, &rows, None, database)?);
                 }
+                database.tables.insert(table_name, target);
             }
             Statement::AlterTable {
                 name,
                 if_exists,
-                only,
                 operations,
+                ..
             } => {
-                let table_name = match name {
-                    sqlparser::ast::ObjectName(ident) => ident
-                        .iter()
-                        .map(|ident| ident.value.to_string())
-                        .collect::<String>(),
-                    _ => panic!("Expected a table name"),
-                };
-
-                if let Some(table) = database.tables.get_mut(&table_name) {
-                    for operation in operations {
-                        match operation {
-                            sqlparser::ast::AlterTableOperation::AddColumn {
-                                column_keyword,
-                                if_not_exists,
-                                column_def,
-                            } => {
-                                if column_keyword {
-                                    let column_name = &column_def.name.value;
-                                    if !table.columns.contains(column_name) {
-                                        let data_type = match &column_def.data_type {
-                                            sqlparser::ast::DataType::Varchar(Some(length)) => {
-                                                format!("VARCHAR({})", length.length)
-                                            }
-                                            // Handle other data types if needed
-                                            _ => panic!("Unsupported data type"),
-                                        };
-
-                                        // Add the new column to the table
-                                        table.columns.push(column_name.to_string());
-                                        for row in &mut table.data {
-                                            row.push("".to_string()); // You may initialize with a default value
-                                        }
-
-                                        // Print a message indicating the column addition
-                                        println!(
-                                            "Added column '{}' to table '{}'",
-                                            column_name, table_name
-                                        );
-                                    } else if !if_not_exists {
-                                        // If the column already exists and if_not_exists is not set, panic
-                                        panic!(
-                                            "Column '{}' already exists in table '{}'",
-                                            column_name, table_name
-                                        );
-                                    }
-                                } else {
-                                    // Handle other alter table operations if needed
-                                    panic!("Unsupported ALTER TABLE operation");
-                                }
-                            }
-                            sqlparser::ast::AlterTableOperation::DropColumn {
-                                column_name,
-                                if_exists,
-                                ..
-                            } => {
-                                let column_to_drop = column_name.value.clone();
-
-                                // TODO: Implement the logic to drop the column from the table
-                                if !if_exists || table.columns.contains(&column_to_drop) {
-                                    table.columns.retain(|col| col != &column_to_drop);
-
-                                    // Drop the corresponding data in each row
-                                    for row in &mut table.data {
-                                        let index = table
-                                            .columns
-                                            .iter()
-                                            .position(|col| col == &column_to_drop);
-                                        if let Some(index) = index {
-                                            row.remove(index);
-                                        }
-                                    }
-
-                                    println!(
-                                        "Dropped column '{}' from table: {:?}",
-                                        column_to_drop, &table_name
-                                    );
-                                } else {
-                                    println!(
-                                        "Column '{}' does not exist in table: {:?}",
-                                        column_to_drop, &table_name
-                                    );
-                                }
-                            }
-                            // Handle other alter table operations if needed
-                            _ => panic!("Unsupported ALTER TABLE operation"),
-                        }
+                let mut table_name = name.to_string();
+                if !database.tables.contains_key(&table_name) {
+                    if if_exists {
+                        continue;
+                    }
+                    return Err(format!("Table '{}' does not exist", table_name));
+                }
+                if database.is_materialized_view(&table_name) {
+                    return Err(format!("'{}' is a materialized view", table_name));
+                }
+                // Either every operation of the statement is applied or none is
+                let snapshot = (database.tables.clone(), database.sequences.clone());
+                for operation in operations {
+                    if let Err(error) = alter_table(database, &mut table_name, operation) {
+                        (database.tables, database.sequences) = snapshot;
+                        return Err(error);
                     }
-                } else {
-                    panic!("Table not found in the database");
                 }
             }
             Statement::Drop {
                 object_type,
                 if_exists,
                 names,
+                cascade,
+                ..
+            } => match object_type {
+                sqlparser::ast::ObjectType::Table => {
+                    drop_tables(database, &names, if_exists, cascade)?
+                }
+                sqlparser::ast::ObjectType::Index => drop_indexes(database, &names, if_exists)?,
+                sqlparser::ast::ObjectType::View => {
+                    drop_views(database, &names, false, if_exists, cascade)?
+                }
+                sqlparser::ast::ObjectType::Sequence => {
+                    drop_sequences(database, &names, if_exists, cascade)?
+                }
+                sqlparser::ast::ObjectType::Schema => {
+                    drop_schemas(database, &names, if_exists, cascade)?
+                }
+                other => {
+                    return Err(format!(
+                        "Unsupported object type in DROP statement: {}",
+                        other
+                    ))
+                }
+            },
+            Statement::CreateView {
+                or_replace,
+                materialized,
+                name,
+                columns,
+                query,
                 ..
+            } => create_view(
+                database,
+                or_replace,
+                materialized,
+                &name,
+                columns,
+                *query,
+                true,
+            )?,
+            Statement::Truncate { table_name, .. } => truncate_table(database, &table_name)?,
+            Statement::CreateSequence {
+                temporary,
+                if_not_exists,
+                name,
+                data_type,
+                sequence_options,
+                owned_by,
             } => {
-                match object_type {
-                    sqlparser::ast::ObjectType::Table => {
-                        // Assuming names is a Vec of ObjectName
-                        if let Some(sqlparser::ast::ObjectName(identifiers)) = names.first() {
-                            let table_name = identifiers
-                                .iter()
-                                .map(|ident| ident.value.to_string())
-                                .collect::<String>();
-
-                            if if_exists || database.tables.contains_key(&table_name) {
-                                // Drop the table if it exists or if if_exists is set
-                                database.tables.remove(&table_name);
-                                println!("Dropped table: {}", table_name);
-                            } else {
-                                println!("Table '{}' does not exist.", table_name);
-                            }
-                        } else {
-                            println!("Invalid DROP TABLE statement: no table name provided");
-                        }
-                    }
-                    // Handle other object types if needed
-                    _ => {
-                        println!("Unsupported object type in DROP statement");
-                    }
+                if temporary {
+                    return Err("Temporary sequences are not supported".to_string());
+                }
+                create_sequence(
+                    database,
+                    &name,
+                    if_not_exists,
+                    data_type.as_ref(),
+                    &sequence_options,
+                    owned_by.as_ref(),
+                )?
+            }
+            Statement::CreateIndex {
+                name,
+                table_name,
+                columns,
+                unique,
+                if_not_exists,
+                predicate,
+                ..
+            } => {
+                if predicate.is_some() {
+                    return Err("Partial indexes are not supported".to_string());
+                }
+                create_index(
+                    database,
+                    name.as_ref(),
+                    &table_name,
+                    &columns,
+                    unique,
+                    if_not_exists,
+                )?
+            }
+            Statement::ShowTables {
+                full,
+                db_name,
+                filter,
+                ..
+            } => {
+                let schema = db_name.map(|schema| schema.value);
+                results.push(show_tables(
+                    database,
+                    full,
+                    schema.as_deref(),
+                    filter.as_ref(),
+                )?);
+            }
+            Statement::ShowColumns {
+                table_name, filter, ..
+            } => results.push(show_columns(database, &table_name, filter.as_ref())?),
+            Statement::ExplainTable { table_name, .. } => {
+                results.push(show_columns(database, &table_name, None)?)
+            }
+            Statement::ShowCreate { obj_type, obj_name } => {
+                results.push(show_create(database, &obj_type, &obj_name)?)
+            }
+            Statement::CreateSchema {
+                schema_name,
+                if_not_exists,
+            } => create_schema(database, &schema_name, if_not_exists)?,
+            Statement::SetVariable {
+                variable, value, ..
+            } => {
+                if !variable.to_string().eq_ignore_ascii_case("search_path") {
+                    return Err(format!("Unknown setting: {}", variable));
                 }
+                set_search_path(database, &value)?
             }
-            _ => panic!("Unsupported SQL statement"),
+            Statement::ShowVariable { variable } => match &variable[..] {
+                [name] if name.value.eq_ignore_ascii_case("search_path") => {
+                    results.push(show_search_path(database))
+                }
+                _ => {
+                    let name: Vec<String> = variable.iter().map(|i| i.to_string()).collect();
+                    return Err(format!("Unknown setting: {}", name.join(" ")));
+                }
+            },
+            _ => return Err("Unsupported SQL statement".to_string()),
         }
     }
+    Ok(results)
+}
+
+/// What INSERT does when a new row collides with an existing row on a
+/// primary key or unique constraint.
+enum ConflictResolution<'a> {
+    Fail,
+    DoNothing {
+        key: Option<Vec<usize>>,
+    },
+    DoUpdate {
+        key: Option<Vec<usize>>,
+        assignments: &'a [Assignment],
+        selection: Option<&'a Expr>,
+    },
+    Replace,
 }
-fn apply_updates(
-    column_updates: &HashMap<String, String>,
-    update_ids: Vec<String>,
+
+fn conflict_resolution<'a>(
+    table: &Table,
+    or: Option<SqliteOnConflict>,
+    on: &'a Option<OnInsert>,
+) -> Result<ConflictResolution<'a>, String> {
+    match (or, on) {
+        (Some(SqliteOnConflict::Replace), _) => Ok(ConflictResolution::Replace),
+        (Some(SqliteOnConflict::Ignore), _) => Ok(ConflictResolution::DoNothing { key: None }),
+        (_, Some(OnInsert::OnConflict(on_conflict))) => {
+            let key = match &on_conflict.conflict_target {
+                Some(target) => Some(conflict_key(table, target)?),
+                None => None,
+            };
+            match &on_conflict.action {
+                OnConflictAction::DoNothing => Ok(ConflictResolution::DoNothing { key }),
+                OnConflictAction::DoUpdate(do_update) => Ok(ConflictResolution::DoUpdate {
+                    key,
+                    assignments: &do_update.assignments,
+                    selection: do_update.selection.as_ref(),
+                }),
+            }
+        }
+        (_, Some(OnInsert::DuplicateKeyUpdate(_))) => {
+            Err("ON DUPLICATE KEY UPDATE is not supported, use ON CONFLICT".to_string())
+        }
+        _ => Ok(ConflictResolution::Fail),
+    }
+}
+
+/// Finds the primary key or unique constraint named by an ON CONFLICT target.
+fn conflict_key(table: &Table, target: &ConflictTarget) -> Result<Vec<usize>, String> {
+    let keys = table.unique_keys();
+    let found = match target {
+        ConflictTarget::Columns(columns) => {
+            let mut wanted = columns
+                .iter()
+                .map(|col| {
+                    table.column_index(&col.value).ok_or_else(|| {
+                        format!(
+                            "Column '{}' does not exist in table '{}'",
+                            col.value, table.name
+                        )
+                    })
+                })
+                .collect::<Result<Vec<usize>, String>>()?;
+            wanted.sort_unstable();
+            keys.into_iter().find(|(_, key, _)| {
+                let mut key = key.clone();
+                key.sort_unstable();
+                key == wanted
+            })
+        }
+        ConflictTarget::OnConstraint(name) => keys
+            .into_iter()
+            .find(|(key_name, _, _)| key_name.as_deref() == Some(name.to_string().as_str())),
+    };
+    found.map(|(_, key, _)| key).ok_or_else(|| {
+        "There is no unique or primary key constraint matching the ON CONFLICT specification"
+            .to_string()
+    })
+}
+
+/// Evaluates one VALUES tuple into a full table row, filling omitted columns
+/// with their defaults and converting every value to its column type.
+pub fn build_insert_row(
+    table: &Table,
+    column_indexes: &[usize],
+    exprs: &[Expr],
+    database: &Database,
+) -> Result<Vec<Value>, String> {
+    let targets: Vec<usize> = if column_indexes.is_empty() {
+        (0..table.columns.len()).collect()
+    } else {
+        column_indexes.to_vec()
+    };
+    if exprs.len() != targets.len() {
+        return Err(format!(
+            "INSERT has {} values but {} target columns",
+            exprs.len(),
+            targets.len()
+        ));
+    }
+    let mut row = vec![None; table.columns.len()];
+    for (expr, index) in exprs.iter().zip(targets) {
+        if row[index].is_some() {
+            return Err(format!(
+                "Column '{}' specified more than once",
+                table.columns[index].name
+            ));
+        }
+        row[index] = match expr {
+            Expr::Identifier(ident)
+                if ident.quote_style.is_none() && ident.value.eq_ignore_ascii_case("DEFAULT") =>
+            {
+                None
+            }
+            expr if table.columns[index].generated_always => {
+                return Err(format!(
+                    "Cannot insert a non-DEFAULT value into identity column '{}': {}",
+                    table.columns[index].name, expr
+                ))
+            }
+            expr => Some(evaluate(expr, &Scope::empty(), database)?),
+        };
+    }
+    row.into_iter()
+        .zip(&table.columns)
+        .map(|(value, column)| {
+            let explicit = value.is_some();
+            let value = match (value, &column.default) {
+                (Some(value), _) => value,
+                (None, Some(default)) => evaluate(default, &Scope::empty(), database)?,
+                (None, None) => Value::Null,
+            };
+            let value = value.cast(&column.data_type)?;
+            if explicit && column.auto_increment {
+                advance_auto_increment(database, &table.name, &column.name, &value);
+            }
+            Ok(value)
+        })
+        .collect()
+}
+
+/// Adds a row to the table, resolving primary key and unique conflicts as
+/// requested. Returns the row as stored, or `None` when nothing was written.
+/// The rows that conflicts removed or changed are added to `replaced`.
+fn insert_row(
     table: &mut Table,
-) {
-    for row in &mut table.data {
-        if let Some(row_id) = row.get(0).map(|id| id.clone()) {
-            if update_ids.contains(&row_id) {
-                for (col, val) in row.iter_mut().enumerate() {
-                    if let Some(update_val) = column_updates.get(&table.columns[col]) {
-                        *val = update_val.clone();
-                    }
+    row: Vec<Value>,
+    resolution: &ConflictResolution,
+    replaced: &mut Vec<Vec<Value>>,
+    database: &Database,
+) -> Result<Option<Vec<Value>>, String> {
+    match resolution {
+        ConflictResolution::Fail => {}
+        ConflictResolution::DoNothing { key } => {
+            if find_conflict(table, &row, key.as_ref(), None).is_some() {
+                return Ok(None);
+            }
+        }
+        ConflictResolution::DoUpdate {
+            key,
+            assignments,
+            selection,
+        } => {
+            if let Some(index) = find_conflict(table, &row, key.as_ref(), None) {
+                let excluded_columns = ColumnRef::for_table("excluded", &table.column_names());
+                let excluded = Scope::new(&excluded_columns, &row);
+                let updated =
+                    updated_row(table, index, assignments, *selection, &excluded, database)?;
+                if let Some(updated) = &updated {
+                    check_row(table, updated, Some(index), database)?;
+                    replaced.push(table.data[index].clone());
+                    table.update_row(index, updated.clone());
                 }
+                return Ok(updated);
+            }
+        }
+        ConflictResolution::Replace => {
+            check_row_values(table, &row, database)?;
+            while let Some(index) = find_conflict(table, &row, None, None) {
+                replaced.push(table.data[index].clone());
+                table.remove_row(index);
             }
         }
     }
+    check_row(table, &row, None, database)?;
+    table.push_row(row.clone());
+    Ok(Some(row))
 }
-fn evaluate_condition(
-    condition: &sqlparser::ast::Expr,
-    row: &[String],
-    selected_columns: &[String],
+
+/// Computes the DO UPDATE result for an existing row, or `None` when the
+/// DO UPDATE ... WHERE condition rejects it.
+fn updated_row(
+    table: &Table,
+    index: usize,
+    assignments: &[Assignment],
+    selection: Option<&Expr>,
+    excluded: &Scope,
     database: &Database,
-) -> Result<bool, &'static str> {
-    match condition {
-        sqlparser::ast::Expr::BinaryOp { left, op, right } => {
-            match (left.as_ref(), op, right.as_ref()) {
-                (
-                    sqlparser::ast::Expr::CompoundIdentifier(left_cols),
-                    sqlparser::ast::BinaryOperator::Eq,
-                    sqlparser::ast::Expr::CompoundIdentifier(right_cols),
-                ) => {
-                    // Assuming both sides are of the form "table_name.column_name"
-                    let (left_table, left_col) = (
-                        &left_cols[0].value.to_string(),
-                        &left_cols[1].value.to_string(),
-                    );
-                    let (right_table, right_col) = (
-                        &right_cols[0].value.to_string(),
-                        &right_cols[1].value.to_string(),
-                    );
-
-                    let left_col_index = selected_columns
-                        .iter()
-                        .position(|col| col == &format!("{}.{}", left_table, left_col));
-                    let right_col_index = selected_columns
-                        .iter()
-                        .position(|col| col == &format!("{}.{}", right_table, right_col));
-
-                    if let (Some(left_index), Some(right_index)) = (left_col_index, right_col_index)
-                    {
-                        let left_row_val = row.get(left_index).unwrap_or(&"".to_string()).clone();
-                        let right_row_val = row.get(right_index).unwrap_or(&"".to_string()).clone();
+) -> Result<Option<Vec<Value>>, String> {
+    let columns = ColumnRef::for_table(&table.name, &table.column_names());
+    let existing = &table.data[index];
+    let scope = Scope::new(&columns, existing).with_parent(excluded);
+    if let Some(selection) = selection {
+        if !evaluate_predicate(selection, &scope, database)? {
+            return Ok(None);
+        }
+    }
+    let column_updates = assignment_targets(table, &table.name, assignments)?;
+    assign(table, existing, &column_updates, &scope, database).map(Some)
+}
 
-                        Ok(left_row_val == right_row_val)
-                    } else {
-                        Err("Column not found in the selected columns")
-                    }
+/// Resolves the columns written by each SET item: `col`, `table.col` or a
+/// `(col, col, ...)` tuple.
+fn assignment_targets<'a>(
+    table: &Table,
+    qualifier: &str,
+    assignments: &'a [Assignment],
+) -> Result<Vec<(Vec<usize>, &'a Expr)>, String> {
+    let mut assigned: Vec<usize> = Vec::new();
+    let mut targets = Vec::new();
+    for Assignment { id, value } in assignments {
+        let names = match (id.as_slice(), value) {
+            ([column], _) => vec![column],
+            (columns, Expr::Tuple(_) | Expr::Subquery(_)) => columns.iter().collect(),
+            ([table_name, column], _) if table_name.value == split_name(qualifier).1 => {
+                vec![column]
+            }
+            _ => {
+                return Err(format!(
+                    "Invalid assignment target: {}",
+                    id.iter()
+                        .map(|ident| ident.value.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(".")
+                ))
+            }
+        };
+        let mut indexes = Vec::new();
+        for name in names {
+            let index = table.column_index(&name.value).ok_or_else(|| {
+                format!(
+                    "Column '{}' does not exist in table '{}'",
+                    name.value, table.name
+                )
+            })?;
+            if assigned.contains(&index) {
+                return Err(format!(
+                    "Multiple assignments to the same column '{}'",
+                    name.value
+                ));
+            }
+            if table.columns[index].generated_always {
+                return Err(format!(
+                    "Column '{}' is an identity column defined as GENERATED ALWAYS",
+                    name.value
+                ));
+            }
+            assigned.push(index);
+            indexes.push(index);
+        }
+        targets.push((indexes, value));
+    }
+    Ok(targets)
+}
+
+/// Applies SET assignments to a copy of `row`. Every expression sees the old
+/// values of the row, so `SET a = b, b = a` swaps the two columns.
+fn assign(
+    table: &Table,
+    row: &[Value],
+    column_updates: &[(Vec<usize>, &Expr)],
+    scope: &Scope,
+    database: &Database,
+) -> Result<Vec<Value>, String> {
+    let mut new_row = row.to_vec();
+    for (columns, value) in column_updates {
+        let values = match value {
+            Expr::Tuple(exprs) if columns.len() > 1 || exprs.len() > 1 => {
+                if exprs.len() != columns.len() {
+                    return Err(format!(
+                        "Assignment sets {} columns but provides {} values",
+                        columns.len(),
+                        exprs.len()
+                    ));
                 }
-                _ => Err("Unsupported condition structure in WHERE clause"),
+                exprs
+                    .iter()
+                    .map(|expr| evaluate(expr, scope, database))
+                    .collect::<Result<Vec<Value>, String>>()?
             }
+            Expr::Subquery(subquery) if columns.len() > 1 => {
+                evaluate_row_subquery(subquery, columns.len(), scope, database)?
+            }
+            expr => vec![evaluate(expr, scope, database)?],
+        };
+        for (&index, value) in columns.iter().zip(values) {
+            new_row[index] = value.cast(&table.columns[index].data_type)?;
         }
-        _ => Err("Unsupported expression structure in WHERE clause"),
     }
+    Ok(new_row)
 }
-fn split_table_column(col_name: &str) -> Option<(&str, &str)> {
-    let parts: Vec<&str> = col_name.split('.').collect();
-    if parts.len() == 2 {
-        Some((parts[0], parts[1]))
-    } else {
-        None
+
+/// Returns the index of an existing row that has the same values as `row` on
+/// `key`, or on any primary key or unique constraint when no key is given.
+/// Rows with a NULL in the key never conflict.
+fn find_conflict(
+    table: &Table,
+    row: &[Value],
+    key: Option<&Vec<usize>>,
+    skip: Option<usize>,
+) -> Option<usize> {
+    let keys = match key {
+        Some(key) => vec![key.clone()],
+        None => table
+            .unique_keys()
+            .into_iter()
+            .map(|(_, columns, _)| columns)
+            .collect(),
+    };
+    table.data.iter().enumerate().position(|(index, existing)| {
+        Some(index) != skip
+            && keys
+                .iter()
+                .any(|key| key.iter().all(|&col| existing[col].same_key(&row[col])))
+    })
+}
+
+/// Validates a new or changed row against every constraint of the table.
+/// `skip` is the index of the row being replaced, which is not a duplicate of itself.
+pub fn check_row(
+    table: &Table,
+    row: &[Value],
+    skip: Option<usize>,
+    database: &Database,
+) -> Result<(), String> {
+    check_row_values(table, row, database)?;
+    check_foreign_keys(table, row, database)?;
+    for (name, key, is_primary) in table.unique_keys() {
+        if find_conflict(table, row, Some(&key), skip).is_some() {
+            let name = name.unwrap_or_else(|| {
+                let suffix = if is_primary { "pkey" } else { "key" };
+                format!("{}_{}", table.name, suffix)
+            });
+            let values: Vec<String> = key.iter().map(|&col| row[col].to_string()).collect();
+            return Err(format!(
+                "Duplicate key value ({}) violates unique constraint '{}'",
+                values.join(", "),
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The positions in `table` of the columns of a key.
+fn key_columns(table: &Table, columns: &[Ident]) -> Result<Vec<usize>, String> {
+    columns
+        .iter()
+        .map(|column| {
+            table.column_index(&column.value).ok_or_else(|| {
+                format!(
+                    "Column '{}' does not exist in table '{}'",
+                    column.value, table.name
+                )
+            })
+        })
+        .collect()
+}
+
+/// Whether `row` holds at the `key` columns the values `other` holds at the
+/// `other_key` ones.
+fn key_matches(row: &[Value], key: &[usize], other: &[Value], other_key: &[usize]) -> bool {
+    key.iter()
+        .zip(other_key)
+        .all(|(&col, &other_col)| row[col].same_key(&other[other_col]))
+}
+
+fn key_text(table: &Table, key: &[usize], row: &[Value]) -> String {
+    let names: Vec<&str> = key
+        .iter()
+        .map(|&col| table.columns[col].name.as_str())
+        .collect();
+    let values: Vec<String> = key.iter().map(|&col| row[col].to_string()).collect();
+    format!("({})=({})", names.join(", "), values.join(", "))
+}
+
+/// Checks that each foreign key of the row refers to an existing row. A key
+/// with a NULL in it refers to nothing and is not checked.
+fn check_foreign_keys(table: &Table, row: &[Value], database: &Database) -> Result<(), String> {
+    for constraint in &table.constraints {
+        let TableConstraint::ForeignKey {
+            columns,
+            foreign_table,
+            referred_columns,
+            ..
+        } = constraint
+        else {
+            continue;
+        };
+        let key = key_columns(table, columns)?;
+        if key.iter().any(|&col| row[col].is_null()) {
+            continue;
+        }
+        let foreign_name = foreign_table.to_string();
+        let foreign = if foreign_name == table.name {
+            table
+        } else {
+            database
+                .tables
+                .get(&foreign_name)
+                .ok_or_else(|| format!("Table '{}' does not exist", foreign_name))?
+        };
+        let referred = key_columns(foreign, referred_columns)?;
+        // A row of a table that references itself may refer to itself
+        let refers_to_itself = foreign_name == table.name && key_matches(row, &key, row, &referred);
+        if !refers_to_itself
+            && !foreign
+                .data
+                .iter()
+                .any(|existing| key_matches(existing, &referred, row, &key))
+        {
+            return Err(format!(
+                "Key {} violates foreign key constraint '{}', table '{}' has no such row",
+                key_text(table, &key, row),
+                constraint_name(constraint).map_or("", |name| &name.value),
+                foreign_name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that no foreign key refers to one of `old_rows`, the rows that a
+/// statement deleted from `table` or changed, unless `table` still has a
+/// row with the same key. `table` is the table as the statement leaves it.
+pub fn check_referencing_rows(
+    table: &Table,
+    old_rows: &[Vec<Value>],
+    database: &Database,
+) -> Result<(), String> {
+    if old_rows.is_empty() {
+        return Ok(());
+    }
+    for (referencing, constraint) in database.foreign_keys_referencing(&table.name) {
+        let TableConstraint::ForeignKey {
+            columns,
+            referred_columns,
+            ..
+        } = constraint
+        else {
+            continue;
+        };
+        let referencing = if referencing.name == table.name {
+            table
+        } else {
+            referencing
+        };
+        let referred = key_columns(table, referred_columns)?;
+        let key = key_columns(referencing, columns)?;
+        for old_row in old_rows {
+            if referred.iter().any(|&col| old_row[col].is_null())
+                || table
+                    .data
+                    .iter()
+                    .any(|row| key_matches(row, &referred, old_row, &referred))
+            {
+                continue;
+            }
+            if referencing
+                .data
+                .iter()
+                .any(|row| key_matches(row, &key, old_row, &referred))
+            {
+                return Err(format!(
+                    "Key {} of table '{}' is still referenced from table '{}' by foreign key constraint '{}'",
+                    key_text(table, &referred, old_row),
+                    table.name,
+                    referencing.name,
+                    constraint_name(constraint).map_or("", |name| &name.value),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks the NOT NULL and CHECK constraints, which only depend on the row itself.
+fn check_row_values(table: &Table, row: &[Value], database: &Database) -> Result<(), String> {
+    let primary_key = table.primary_key().unwrap_or_default();
+    for (index, column) in table.columns.iter().enumerate() {
+        if row[index].is_null() && (column.not_null || primary_key.contains(&index)) {
+            return Err(format!(
+                "Null value in column '{}' of table '{}' violates not-null constraint",
+                column.name, table.name
+            ));
+        }
+    }
+    let columns = ColumnRef::for_table(&table.name, &table.column_names());
+    let scope = Scope::new(&columns, row);
+    for constraint in &table.constraints {
+        if let TableConstraint::Check { name, expr } = constraint {
+            if evaluate(expr, &scope, database)?.as_bool()? == Some(false) {
+                return Err(match name {
+                    Some(name) => format!("New row violates check constraint '{}'", name.value),
+                    None => format!("New row violates check constraint: CHECK ({})", expr),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Evaluates a RETURNING list against the rows a statement inserted, updated or deleted.
+fn returning_rows(
+    returning: &[SelectItem],
+    table: &Table,
+    rows: &[Vec<Value>],
+    database: &Database,
+) -> Result<QueryResult, String> {
+    let columns = ColumnRef::for_table(&table.name, &table.column_names());
+    project(returning, &columns, rows, None, database)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_parsing::parse_statements;
+    use crate::transactions::Session;
+
+    fn run(database: &mut Database, sql: &str) -> Result<Vec<StatementOutput>, String> {
+        Session::default().execute(database, parse_statements(sql)?)
+    }
+
+    fn parent_and_child() -> Database {
+        let mut database = Database::new("test.db");
+        run(
+            &mut database,
+            "CREATE TABLE p (id INT PRIMARY KEY);
+            CREATE TABLE c (pid INT REFERENCES p (id));
+            INSERT INTO p VALUES (1), (2);
+            INSERT INTO c VALUES (1), (NULL)",
+        )
+        .unwrap();
+        database
+    }
+
+    #[test]
+    fn rows_must_refer_to_existing_rows() {
+        let mut database = parent_and_child();
+        let err = run(&mut database, "INSERT INTO c VALUES (7)").unwrap_err();
+        assert_eq!(
+            err,
+            "Key (pid)=(7) violates foreign key constraint 'c_pid_fkey', table 'p' has no such row"
+        );
+        assert!(run(&mut database, "UPDATE c SET pid = 3 WHERE pid = 1").is_err());
+        run(&mut database, "UPDATE c SET pid = 2 WHERE pid = 1").unwrap();
+        assert_eq!(database.tables["c"].data.len(), 2);
+    }
+
+    #[test]
+    fn referenced_rows_cannot_go() {
+        let mut database = parent_and_child();
+        let err = run(&mut database, "DELETE FROM p WHERE id = 1").unwrap_err();
+        assert_eq!(
+            err,
+            "Key (id)=(1) of table 'p' is still referenced from table 'c' by foreign key constraint 'c_pid_fkey'"
+        );
+        assert!(run(&mut database, "UPDATE p SET id = id + 10").is_err());
+        assert!(run(&mut database, "TRUNCATE p").is_err());
+        // Replacing a row with one of the same key keeps what refers to it
+        run(&mut database, "INSERT OR REPLACE INTO p VALUES (1)").unwrap();
+        run(&mut database, "DELETE FROM p WHERE id = 2").unwrap();
+        assert_eq!(database.tables["p"].data, vec![vec![Value::Integer(1)]]);
+    }
+
+    #[test]
+    fn added_foreign_keys_check_existing_rows() {
+        let mut database = parent_and_child();
+        run(
+            &mut database,
+            "CREATE TABLE c2 (x INT); INSERT INTO c2 VALUES (99)",
+        )
+        .unwrap();
+        let sql = "ALTER TABLE c2 ADD CONSTRAINT fk FOREIGN KEY (x) REFERENCES p (id)";
+        assert!(run(&mut database, sql).is_err());
+        run(&mut database, "UPDATE c2 SET x = 2").unwrap();
+        run(&mut database, sql).unwrap();
+    }
+
+    #[test]
+    fn rows_may_refer_to_their_own_table() {
+        let mut database = Database::new("test.db");
+        run(
+            &mut database,
+            "CREATE TABLE t (id INT PRIMARY KEY, parent INT REFERENCES t (id));
+            INSERT INTO t VALUES (1, 1), (2, 1), (3, 2)",
+        )
+        .unwrap();
+        assert!(run(&mut database, "DELETE FROM t WHERE id = 2").is_err());
+        run(&mut database, "DELETE FROM t WHERE id >= 2").unwrap();
+        run(&mut database, "DELETE FROM t").unwrap();
+    }
+
+    #[test]
+    fn referential_actions_are_rejected() {
+        let mut database = parent_and_child();
+        let err = run(
+            &mut database,
+            "CREATE TABLE d (x INT REFERENCES p (id) ON DELETE CASCADE)",
+        )
+        .unwrap_err();
+        assert_eq!(err, "ON DELETE CASCADE is not supported");
+        run(
+            &mut database,
+            "CREATE TABLE d (x INT REFERENCES p (id) ON UPDATE RESTRICT)",
+        )
+        .unwrap();
     }
 }
@@ -0,0 +1,353 @@
+use super::database::{identifier, qualified_name, split_name, Database};
+use super::database::{DEFAULT_SCHEMA, MAIN_DATABASE};
+use super::query_execution::QueryResult;
+use super::sequences::drop_sequences;
+use super::table_alteration::drop_tables;
+use super::value::Value;
+use super::views::drop_views;
+use sqlparser::ast::Value as Literal;
+use sqlparser::ast::{visit_relations_mut, AlterTableOperation, ColumnOption, CopySource};
+use sqlparser::ast::{Expr, Ident};
+use sqlparser::ast::{ObjectName, ObjectType, SchemaName, Statement, TableConstraint};
+use std::ops::ControlFlow;
+
+const INFORMATION_SCHEMA: &str = "information_schema";
+
+/// Rewrites the names of tables, views, sequences and indexes in a statement
+/// to their catalog keys: unqualified names are looked up along the search
+/// path, names being created go into the first schema of the path, and
+/// `public.t` becomes plain `t`. Everything after this sees keys only.
+pub fn qualify_names(database: &Database, statement: &mut Statement) -> Result<(), String> {
+    // The name of a new table must not be looked up like the names it uses
+    let created = match &*statement {
+        Statement::CreateTable { name, .. } => Some(creation_name(database, name)?),
+        _ => None,
+    };
+    let mut error = None;
+    let _ = visit_relations_mut(statement, |name| match resolve_name(database, name) {
+        Ok(resolved) => {
+            *name = resolved;
+            ControlFlow::Continue(())
+        }
+        Err(err) => {
+            error = Some(err);
+            ControlFlow::Break(())
+        }
+    });
+    if let Some(error) = error {
+        return Err(error);
+    }
+
+    match statement {
+        Statement::CreateTable {
+            name,
+            columns,
+            constraints,
+            ..
+        } => {
+            *name = created.unwrap();
+            for option_def in columns.iter_mut().flat_map(|col| col.options.iter_mut()) {
+                if let ColumnOption::ForeignKey { foreign_table, .. } = &mut option_def.option {
+                    *foreign_table = resolve_name(database, foreign_table)?;
+                }
+            }
+            for constraint in constraints {
+                qualify_constraint(database, constraint)?;
+            }
+        }
+        Statement::AlterTable {
+            name, operations, ..
+        } => {
+            for operation in operations {
+                match operation {
+                    AlterTableOperation::AddConstraint(constraint) => {
+                        qualify_constraint(database, constraint)?
+                    }
+                    AlterTableOperation::AddColumn { column_def, .. } => {
+                        for option_def in &mut column_def.options {
+                            if let ColumnOption::ForeignKey { foreign_table, .. } =
+                                &mut option_def.option
+                            {
+                                *foreign_table = resolve_name(database, foreign_table)?;
+                            }
+                        }
+                    }
+                    // A table is renamed within its schema
+                    AlterTableOperation::RenameTable { table_name } => {
+                        let schema = split_name(&name.to_string()).0;
+                        let new_name = match &table_name.0[..] {
+                            [new_name] => new_name.clone(),
+                            [new_schema, new_name] if new_schema.value == schema => {
+                                new_name.clone()
+                            }
+                            _ => {
+                                return Err(format!(
+                                    "Cannot rename table '{}' into another schema",
+                                    name
+                                ))
+                            }
+                        };
+                        *table_name = object_name(&schema, &new_name.value);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Statement::Drop {
+            object_type:
+                ObjectType::Table | ObjectType::View | ObjectType::Index | ObjectType::Sequence,
+            names,
+            ..
+        } => {
+            for name in names {
+                *name = resolve_name(database, name)?;
+            }
+        }
+        Statement::CreateView { name, .. } => *name = creation_name(database, name)?,
+        Statement::CreateIndex {
+            name: Some(name),
+            table_name,
+            ..
+        } => {
+            // An index lives in the schema of its table
+            if let [index_name] = &name.0[..] {
+                let schema = split_name(&table_name.to_string()).0;
+                *name = object_name(&schema, &index_name.value);
+            }
+        }
+        Statement::CreateSequence { name, owned_by, .. } => {
+            *name = creation_name(database, name)?;
+            if let Some(owner) = owned_by {
+                if let Some(column) = owner.0.pop() {
+                    *owner = resolve_name(database, owner)?;
+                    owner.0.push(column);
+                }
+            }
+        }
+        Statement::ShowCreate { obj_name, .. } => *obj_name = resolve_name(database, obj_name)?,
+        Statement::Copy {
+            source: CopySource::Table { table_name, .. },
+            ..
+        } => *table_name = resolve_name(database, table_name)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn qualify_constraint(database: &Database, constraint: &mut TableConstraint) -> Result<(), String> {
+    if let TableConstraint::ForeignKey { foreign_table, .. } = constraint {
+        *foreign_table = resolve_name(database, foreign_table)?;
+    }
+    Ok(())
+}
+
+/// The catalog key, as an object name, of an existing object. An unqualified
+/// name that is not found anywhere on the search path is placed in the
+/// schema it would be created in, so that the error names it.
+pub fn resolve_name(database: &Database, name: &ObjectName) -> Result<ObjectName, String> {
+    match &name.0[..] {
+        [object] => {
+            for schema in search_schemas(database) {
+                if database.object_exists(&qualified_name(schema, &object.value)) {
+                    return Ok(object_name(schema, &object.value));
+                }
+            }
+            Ok(creation_name(database, name).unwrap_or_else(|_| name.clone()))
+        }
+        // Attached files name the tables of the main database like this
+        [database, object] if database.value == MAIN_DATABASE => {
+            Ok(object_name(DEFAULT_SCHEMA, &object.value))
+        }
+        [schema, object] if schema.value == INFORMATION_SCHEMA => Ok(ObjectName(vec![
+            Ident::new(INFORMATION_SCHEMA),
+            identifier(&object.value),
+        ])),
+        [schema, object] => {
+            if !database.schemas.contains(&schema.value) {
+                return Err(format!("Schema '{}' does not exist", schema.value));
+            }
+            Ok(object_name(&schema.value, &object.value))
+        }
+        _ => Err(format!(
+            "Improper qualified name (too many dotted names): {}",
+            name
+        )),
+    }
+}
+
+/// The catalog key, as an object name, of an object being created.
+fn creation_name(database: &Database, name: &ObjectName) -> Result<ObjectName, String> {
+    match &name.0[..] {
+        [object] => {
+            let schema = search_schemas(database)
+                .next()
+                .ok_or("No schema on the search path exists to create in")?;
+            Ok(object_name(schema, &object.value))
+        }
+        [schema, _] if schema.value == INFORMATION_SCHEMA => Err(format!(
+            "Cannot create '{}', schema '{}' is read-only",
+            name, INFORMATION_SCHEMA
+        )),
+        _ => resolve_name(database, name),
+    }
+}
+
+/// The schemas of the search path that exist, in order.
+fn search_schemas(database: &Database) -> impl Iterator<Item = &String> + Clone {
+    database
+        .search_path
+        .iter()
+        .filter(|schema| database.schemas.contains(schema))
+}
+
+/// The catalog key of `name` in `schema` as an object name, which displays
+/// as `qualified_name` does.
+fn object_name(schema: &str, name: &str) -> ObjectName {
+    if schema == DEFAULT_SCHEMA {
+        ObjectName(vec![identifier(name)])
+    } else {
+        ObjectName(vec![identifier(schema), identifier(name)])
+    }
+}
+
+pub fn create_schema(
+    database: &mut Database,
+    schema_name: &SchemaName,
+    if_not_exists: bool,
+) -> Result<(), String> {
+    let name = match schema_name {
+        SchemaName::Simple(name) | SchemaName::NamedAuthorization(name, _) => name,
+        SchemaName::UnnamedAuthorization(_) => {
+            return Err("CREATE SCHEMA needs a schema name".to_string())
+        }
+    };
+    let [schema] = &name.0[..] else {
+        return Err(format!("Invalid schema name: {}", name));
+    };
+    let schema = schema.value.clone();
+    if database.schemas.contains(&schema) || schema == INFORMATION_SCHEMA || schema == MAIN_DATABASE
+    {
+        if if_not_exists {
+            database.report(format!("Schema '{}' already exists, skipping", schema));
+            return Ok(());
+        }
+        return Err(format!("Schema '{}' already exists", schema));
+    }
+    database.report(format!("Created schema: {}", schema));
+    database.schemas.push(schema);
+    Ok(())
+}
+
+/// Drops schemas. A schema that still holds objects can only be dropped with
+/// CASCADE, which drops the objects and whatever depends on them.
+pub fn drop_schemas(
+    database: &mut Database,
+    names: &[ObjectName],
+    if_exists: bool,
+    cascade: bool,
+) -> Result<(), String> {
+    let mut dropped: Vec<String> = Vec::new();
+    for name in names {
+        let schema = match &name.0[..] {
+            [schema] => schema.value.clone(),
+            _ => name.to_string(),
+        };
+        if schema == DEFAULT_SCHEMA || schema == INFORMATION_SCHEMA {
+            return Err(format!("Cannot drop schema '{}'", schema));
+        }
+        if database.attachments.iter().any(|a| a.name == schema) {
+            return Err(format!(
+                "'{}' is an attached database, use DETACH instead",
+                schema
+            ));
+        }
+        if database.schemas.contains(&schema) {
+            if !dropped.contains(&schema) {
+                dropped.push(schema);
+            }
+        } else if if_exists {
+            database.report(format!("Schema '{}' does not exist, skipping", schema));
+        } else {
+            return Err(format!("Schema '{}' does not exist", schema));
+        }
+    }
+    for schema in &dropped {
+        let in_schema = |key: &&String| split_name(key).0 == *schema;
+        let mut tables: Vec<String> = database
+            .tables
+            .keys()
+            .filter(in_schema)
+            .filter(|key| !database.is_materialized_view(key))
+            .cloned()
+            .collect();
+        let mut views: Vec<String> = database.views.keys().filter(in_schema).cloned().collect();
+        let mut sequences: Vec<String> = database
+            .sequences
+            .keys()
+            .filter(in_schema)
+            .cloned()
+            .collect();
+        if let Some(object) = tables.iter().chain(&views).chain(&sequences).next() {
+            if !cascade {
+                return Err(format!(
+                    "Cannot drop schema '{}' because '{}' depends on it",
+                    schema, object
+                ));
+            }
+        }
+        tables.sort();
+        views.sort();
+        sequences.sort();
+        let names = |keys: &[String]| -> Vec<ObjectName> {
+            keys.iter()
+                .map(|key| {
+                    let (schema, name) = split_name(key);
+                    object_name(&schema, &name)
+                })
+                .collect()
+        };
+        drop_tables(database, &names(&tables), true, true)?;
+        // Views of the schema that did not go with its tables
+        let (materialized, plain): (Vec<String>, Vec<String>) = views
+            .into_iter()
+            .filter(|view| database.views.contains_key(view))
+            .partition(|view| database.views[view].materialized);
+        drop_views(database, &names(&plain), false, true, true)?;
+        drop_views(database, &names(&materialized), true, true, true)?;
+        sequences.retain(|sequence| database.sequences.contains_key(sequence));
+        drop_sequences(database, &names(&sequences), true, true)?;
+        database.schemas.retain(|s| s != schema);
+        database.report(format!("Dropped schema: {}", schema));
+    }
+    Ok(())
+}
+
+/// `SET search_path TO a, b` or `= 'a, b'`; `DEFAULT` restores the default schema.
+pub fn set_search_path(database: &mut Database, values: &[Expr]) -> Result<(), String> {
+    let mut search_path = Vec::new();
+    for value in values {
+        match value {
+            Expr::Identifier(ident)
+                if ident.quote_style.is_none() && ident.value.eq_ignore_ascii_case("DEFAULT") =>
+            {
+                search_path.push(DEFAULT_SCHEMA.to_string())
+            }
+            Expr::Identifier(ident) => search_path.push(ident.value.clone()),
+            Expr::Value(Literal::SingleQuotedString(list)) => search_path.extend(
+                list.split(',')
+                    .map(|schema| schema.trim().to_string())
+                    .filter(|schema| !schema.is_empty()),
+            ),
+            other => return Err(format!("Invalid value for search_path: {}", other)),
+        }
+    }
+    database.search_path = search_path;
+    Ok(())
+}
+
+pub fn show_search_path(database: &Database) -> QueryResult {
+    QueryResult {
+        columns: vec!["search_path".to_string()],
+        rows: vec![vec![Value::Text(database.search_path.join(", "))]],
+    }
+}
@@ -0,0 +1,491 @@
+use super::database::Database;
+use super::expression_evaluation::{evaluate, evaluate_predicate, ColumnRef, Scope};
+use super::information_schema::system_table;
+use super::query_execution::QueryResult;
+use super::value::Value;
+use sqlparser::ast::{Expr, GroupByExpr, JoinConstraint, JoinOperator, OrderByExpr, Query};
+use sqlparser::ast::{Select, SelectItem};
+use sqlparser::ast::{SetExpr, Value as Literal};
+use sqlparser::ast::{TableAlias, TableFactor, TableWithJoins};
+use std::cmp::Ordering;
+
+/// The rows of a FROM item together with the columns they expose.
+pub struct Relation {
+    pub columns: Vec<ColumnRef>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl Relation {
+    /// Every combination of a row of `self` with a row of `other`.
+    fn cross_join(self, other: &Relation) -> Relation {
+        let rows = self
+            .rows
+            .into_iter()
+            .flat_map(|row| {
+                other
+                    .rows
+                    .iter()
+                    .map(move |other_row| row.iter().chain(other_row).cloned().collect())
+            })
+            .collect();
+        let mut columns = self.columns;
+        columns.extend(other.columns.iter().cloned());
+        Relation { columns, rows }
+    }
+}
+
+pub fn execute_query(database: &Database, query: &Query) -> Result<QueryResult, String> {
+    run_query(database, query, None)
+}
+
+/// Runs a subquery whose expressions may refer to columns of the enclosing row.
+pub fn execute_subquery(
+    database: &Database,
+    query: &Query,
+    outer: &Scope,
+) -> Result<QueryResult, String> {
+    run_query(database, query, Some(outer))
+}
+
+fn run_query(
+    database: &Database,
+    query: &Query,
+    outer: Option<&Scope>,
+) -> Result<QueryResult, String> {
+    let mut result = match query.body.as_ref() {
+        SetExpr::Select(select) => {
+            unsupported_clause(select)?;
+            let relation = resolve_from(database, &select.from)?;
+
+            // Filter the joined data based on the WHERE clause
+            let mut filtered_table_data: Vec<Vec<Value>> = Vec::new();
+            for row in relation.rows {
+                if let Some(selection_condition) = &select.selection {
+                    let scope = Scope::new(&relation.columns, &row).with_outer(outer);
+                    if !evaluate_predicate(selection_condition, &scope, database)? {
+                        continue;
+                    }
+                }
+                filtered_table_data.push(row);
+            }
+            let mut result = project(
+                &select.projection,
+                &relation.columns,
+                &filtered_table_data,
+                outer,
+                database,
+            )?;
+            let input = (relation.columns.as_slice(), filtered_table_data.as_slice());
+            sort_rows(&query.order_by, &mut result, input, outer, database)?;
+            result
+        }
+        SetExpr::Query(inner) => {
+            let mut result = run_query(database, inner, outer)?;
+            let no_input = vec![vec![]; result.rows.len()];
+            sort_rows(
+                &query.order_by,
+                &mut result,
+                (&[], &no_input),
+                outer,
+                database,
+            )?;
+            result
+        }
+        _ => return Err("Unsupported query type".to_string()),
+    };
+    limit_rows(query, &mut result, outer, database)?;
+    Ok(result)
+}
+
+/// Fails on the clauses of a SELECT that are not implemented, which would
+/// otherwise be ignored and give wrong rows.
+fn unsupported_clause(select: &Select) -> Result<(), String> {
+    let clause = if select.distinct.is_some() {
+        "DISTINCT"
+    } else if !matches!(&select.group_by, GroupByExpr::Expressions(exprs) if exprs.is_empty()) {
+        "GROUP BY"
+    } else if select.having.is_some() {
+        "HAVING"
+    } else {
+        return Ok(());
+    };
+    Err(format!("Unsupported clause in SELECT: {}", clause))
+}
+
+/// Sorts the result of a query by its ORDER BY. A key is an output column
+/// given by position (`ORDER BY 2`) or name, or else an expression over the
+/// input row the output row was computed from. NULLs sort last ascending and
+/// first descending unless NULLS FIRST / LAST says otherwise.
+fn sort_rows(
+    order_by: &[OrderByExpr],
+    result: &mut QueryResult,
+    input: (&[ColumnRef], &[Vec<Value>]),
+    outer: Option<&Scope>,
+    database: &Database,
+) -> Result<(), String> {
+    if order_by.is_empty() {
+        return Ok(());
+    }
+    let output_indexes = order_by
+        .iter()
+        .map(|item| match &item.expr {
+            Expr::Value(Literal::Number(position, _)) => position
+                .parse::<usize>()
+                .ok()
+                .filter(|&position| position >= 1 && position <= result.columns.len())
+                .map(|position| Some(position - 1))
+                .ok_or_else(|| format!("ORDER BY position {} is not in select list", position)),
+            Expr::Identifier(ident) => Ok(result.columns.iter().position(|c| *c == ident.value)),
+            _ => Ok(None),
+        })
+        .collect::<Result<Vec<Option<usize>>, String>>()?;
+    let (input_columns, input_rows) = input;
+    let mut keyed = Vec::with_capacity(result.rows.len());
+    for (output, row) in result.rows.drain(..).zip(input_rows) {
+        let scope = Scope::new(input_columns, row).with_outer(outer);
+        let keys = order_by
+            .iter()
+            .zip(&output_indexes)
+            .map(|(item, output_index)| match output_index {
+                Some(index) => Ok(output[*index].clone()),
+                None => evaluate(&item.expr, &scope, database),
+            })
+            .collect::<Result<Vec<Value>, String>>()?;
+        keyed.push((keys, output));
+    }
+    keyed.sort_by(|(a, _), (b, _)| {
+        a.iter()
+            .zip(b)
+            .zip(order_by)
+            .map(|((a, b), item)| {
+                let asc = item.asc.unwrap_or(true);
+                let nulls_first = item.nulls_first.unwrap_or(!asc);
+                match (a.is_null(), b.is_null()) {
+                    (true, true) => Ordering::Equal,
+                    (true, false) if nulls_first => Ordering::Less,
+                    (true, false) => Ordering::Greater,
+                    (false, true) if nulls_first => Ordering::Greater,
+                    (false, true) => Ordering::Less,
+                    _ if asc => a.sort_cmp(b),
+                    _ => a.sort_cmp(b).reverse(),
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+    result.rows = keyed.into_iter().map(|(_, output)| output).collect();
+    Ok(())
+}
+
+/// Applies OFFSET, then LIMIT or FETCH FIRST.
+fn limit_rows(
+    query: &Query,
+    result: &mut QueryResult,
+    outer: Option<&Scope>,
+    database: &Database,
+) -> Result<(), String> {
+    let scope = Scope::empty().with_outer(outer);
+    let count = |expr: &Expr, clause: &str| match evaluate(expr, &scope, database)? {
+        Value::Integer(n) if n >= 0 => Ok(n as usize),
+        other => Err(format!(
+            "{} must be a non-negative integer, found {}",
+            clause, other
+        )),
+    };
+    if let Some(offset) = &query.offset {
+        let skip = count(&offset.value, "OFFSET")?;
+        result.rows.drain(..skip.min(result.rows.len()));
+    }
+    let limit = match (&query.limit, &query.fetch) {
+        (Some(limit), _) => Some(count(limit, "LIMIT")?),
+        (None, Some(fetch)) => {
+            if fetch.with_ties || fetch.percent {
+                return Err("FETCH FIRST ... PERCENT / WITH TIES is not supported".to_string());
+            }
+            match &fetch.quantity {
+                Some(quantity) => Some(count(quantity, "FETCH FIRST")?),
+                None => Some(1),
+            }
+        }
+        (None, None) => None,
+    };
+    if let Some(limit) = limit {
+        result.rows.truncate(limit);
+    }
+    Ok(())
+}
+
+/// Joins the comma-separated items of a FROM clause. Without a FROM clause
+/// the query runs against a single empty row, so `SELECT 1 + 1` works.
+pub fn resolve_from(database: &Database, from: &[TableWithJoins]) -> Result<Relation, String> {
+    let mut relation = Relation {
+        columns: vec![],
+        rows: vec![vec![]],
+    };
+    for table_with_joins in from {
+        relation = relation.cross_join(&resolve_table_with_joins(database, table_with_joins)?);
+    }
+    Ok(relation)
+}
+
+pub fn resolve_table_with_joins(
+    database: &Database,
+    table_with_joins: &TableWithJoins,
+) -> Result<Relation, String> {
+    let mut relation = resolve_table_factor(database, &table_with_joins.relation)?;
+    for join in &table_with_joins.joins {
+        let right = resolve_table_factor(database, &join.relation)?;
+        relation = match &join.join_operator {
+            JoinOperator::Inner(constraint) => {
+                join_relations(relation, right, constraint, false, false, database)?
+            }
+            JoinOperator::LeftOuter(constraint) => {
+                join_relations(relation, right, constraint, true, false, database)?
+            }
+            JoinOperator::RightOuter(constraint) => {
+                join_relations(relation, right, constraint, false, true, database)?
+            }
+            JoinOperator::FullOuter(constraint) => {
+                join_relations(relation, right, constraint, true, true, database)?
+            }
+            JoinOperator::CrossJoin => relation.cross_join(&right),
+            other => return Err(format!("Unsupported join: {:?}", other)),
+        };
+    }
+    Ok(relation)
+}
+
+fn resolve_table_factor(database: &Database, factor: &TableFactor) -> Result<Relation, String> {
+    match factor {
+        TableFactor::Table { name, alias, .. } => {
+            let table_name = name.to_string();
+            let qualifier = alias
+                .as_ref()
+                .map_or(table_name.clone(), |a| a.name.value.clone());
+            let (column_names, rows) = if let Some(table) = database.tables.get(&table_name) {
+                (table.column_names(), table.data.clone())
+            } else if let Some(view) = database.views.get(&table_name) {
+                // Views are expanded by running their query each time they are used
+                let result = execute_query(database, &view.query)?;
+                let view_alias = TableAlias {
+                    name: sqlparser::ast::Ident::new(&view.name),
+                    columns: view.columns.clone(),
+                };
+                (
+                    aliased_columns(result.columns, &Some(view_alias))?,
+                    result.rows,
+                )
+            } else if let Some(result) = system_table(database, &table_name) {
+                let result = result?;
+                (result.columns, result.rows)
+            } else {
+                return Err(format!("Table '{}' does not exist", table_name));
+            };
+            let column_names = aliased_columns(column_names, alias)?;
+            Ok(Relation {
+                columns: ColumnRef::for_table(&qualifier, &column_names),
+                rows,
+            })
+        }
+        TableFactor::Derived {
+            subquery, alias, ..
+        } => {
+            let result = execute_query(database, subquery)?;
+            let alias = alias
+                .as_ref()
+                .ok_or("Subquery in FROM must have an alias")?;
+            let column_names = aliased_columns(result.columns, &Some(alias.clone()))?;
+            Ok(Relation {
+                columns: ColumnRef::for_table(&alias.name.value, &column_names),
+                rows: result.rows,
+            })
+        }
+        TableFactor::NestedJoin {
+            table_with_joins,
+            alias,
+        } => {
+            let mut relation = resolve_table_with_joins(database, table_with_joins)?;
+            if let Some(alias) = alias {
+                let names = relation.columns.iter().map(|c| c.name.clone()).collect();
+                let column_names = aliased_columns(names, &Some(alias.clone()))?;
+                relation.columns = ColumnRef::for_table(&alias.name.value, &column_names);
+            }
+            Ok(relation)
+        }
+        _ => Err(format!("Unsupported table in FROM clause: {}", factor)),
+    }
+}
+
+/// Applies the column list of `AS alias (a, b, ...)`, if any.
+fn aliased_columns(
+    column_names: Vec<String>,
+    alias: &Option<TableAlias>,
+) -> Result<Vec<String>, String> {
+    match alias {
+        Some(TableAlias { name, columns }) if !columns.is_empty() => {
+            if columns.len() > column_names.len() {
+                return Err(format!(
+                    "Table '{}' has {} columns available but {} columns specified",
+                    name.value,
+                    column_names.len(),
+                    columns.len()
+                ));
+            }
+            let mut column_names = column_names;
+            for (column_name, alias) in column_names.iter_mut().zip(columns) {
+                *column_name = alias.value.clone();
+            }
+            Ok(column_names)
+        }
+        _ => Ok(column_names),
+    }
+}
+
+/// Nested-loop join. `keep_left` / `keep_right` add the unmatched rows of
+/// that side padded with NULLs, giving LEFT, RIGHT and FULL outer joins.
+fn join_relations(
+    left: Relation,
+    right: Relation,
+    constraint: &JoinConstraint,
+    keep_left: bool,
+    keep_right: bool,
+    database: &Database,
+) -> Result<Relation, String> {
+    let mut columns = left.columns.clone();
+    columns.extend(right.columns.iter().cloned());
+    let shared_columns: Vec<(usize, usize)> = match constraint {
+        JoinConstraint::Using(names) => names
+            .iter()
+            .map(|name| {
+                let find = |columns: &[ColumnRef]| {
+                    columns
+                        .iter()
+                        .position(|col| col.name == name.value)
+                        .ok_or_else(|| {
+                            format!("Column '{}' specified in USING clause does not exist", name)
+                        })
+                };
+                Ok((find(&left.columns)?, find(&right.columns)?))
+            })
+            .collect::<Result<_, String>>()?,
+        JoinConstraint::Natural => left
+            .columns
+            .iter()
+            .enumerate()
+            .filter_map(|(index, col)| {
+                right
+                    .columns
+                    .iter()
+                    .position(|other| other.name == col.name)
+                    .map(|other| (index, other))
+            })
+            .collect(),
+        _ => vec![],
+    };
+
+    let mut rows = Vec::new();
+    let mut right_matched = vec![false; right.rows.len()];
+    for left_row in &left.rows {
+        let mut matched = false;
+        for (right_index, right_row) in right.rows.iter().enumerate() {
+            let row: Vec<Value> = left_row.iter().chain(right_row).cloned().collect();
+            let accepted = match constraint {
+                JoinConstraint::On(expr) => {
+                    evaluate_predicate(expr, &Scope::new(&columns, &row), database)?
+                }
+                JoinConstraint::Using(_) | JoinConstraint::Natural => shared_columns
+                    .iter()
+                    .all(|&(l, r)| left_row[l].same_key(&right_row[r])),
+                JoinConstraint::None => true,
+            };
+            if accepted {
+                matched = true;
+                right_matched[right_index] = true;
+                rows.push(row);
+            }
+        }
+        if keep_left && !matched {
+            let mut row = left_row.clone();
+            row.resize(columns.len(), Value::Null);
+            rows.push(row);
+        }
+    }
+    if keep_right {
+        for (right_row, _) in right.rows.iter().zip(&right_matched).filter(|(_, m)| !**m) {
+            let mut row = vec![Value::Null; left.columns.len()];
+            row.extend(right_row.iter().cloned());
+            rows.push(row);
+        }
+    }
+    Ok(Relation { columns, rows })
+}
+
+/// Evaluates a SELECT list for every row, expanding `*` and `table.*`.
+pub fn project(
+    items: &[SelectItem],
+    columns: &[ColumnRef],
+    rows: &[Vec<Value>],
+    outer: Option<&Scope>,
+    database: &Database,
+) -> Result<QueryResult, String> {
+    enum Output<'a> {
+        Column(usize),
+        Expr(&'a Expr),
+    }
+    let mut headers = Vec::new();
+    let mut outputs = Vec::new();
+    for item in items {
+        match item {
+            SelectItem::Wildcard(_) => {
+                for (index, col) in columns.iter().enumerate() {
+                    headers.push(col.name.clone());
+                    outputs.push(Output::Column(index));
+                }
+            }
+            SelectItem::QualifiedWildcard(name, _) => {
+                let qualifier = name.0.last().map(|ident| ident.value.as_str());
+                let before = outputs.len();
+                for (index, col) in columns.iter().enumerate() {
+                    if col.table.as_deref() == qualifier {
+                        headers.push(col.name.clone());
+                        outputs.push(Output::Column(index));
+                    }
+                }
+                if outputs.len() == before {
+                    return Err(format!("Missing FROM-clause entry for table '{}'", name));
+                }
+            }
+            SelectItem::UnnamedExpr(expr) => {
+                headers.push(match expr {
+                    Expr::Identifier(ident) => ident.value.clone(),
+                    Expr::CompoundIdentifier(idents) => idents
+                        .last()
+                        .map_or_else(|| expr.to_string(), |ident| ident.value.clone()),
+                    expr => expr.to_string(),
+                });
+                outputs.push(Output::Expr(expr));
+            }
+            SelectItem::ExprWithAlias { expr, alias } => {
+                headers.push(alias.value.clone());
+                outputs.push(Output::Expr(expr));
+            }
+        }
+    }
+    let rows = rows
+        .iter()
+        .map(|row| {
+            let scope = Scope::new(columns, row).with_outer(outer);
+            outputs
+                .iter()
+                .map(|output| match output {
+                    Output::Column(index) => Ok(row[*index].clone()),
+                    Output::Expr(expr) => evaluate(expr, &scope, database),
+                })
+                .collect::<Result<Vec<Value>, String>>()
+        })
+        .collect::<Result<Vec<Vec<Value>>, String>>()?;
+    Ok(QueryResult {
+        columns: headers,
+        rows,
+    })
+}
@@ -0,0 +1,371 @@
+use super::database::{name_parts, qualified_name, split_name, Database, Sequence};
+use super::schemas::resolve_name;
+use super::value::Value;
+use sqlparser::ast::{visit_expressions, ColumnDef, ColumnOption, ColumnOptionDef, DataType};
+use sqlparser::ast::{Expr, Function, FunctionArg, FunctionArgExpr, Ident, ObjectName};
+use sqlparser::ast::{SequenceOptions, Value as Literal};
+use sqlparser::keywords::Keyword;
+use sqlparser::tokenizer::Token;
+use std::ops::ControlFlow;
+
+/// Turns SERIAL, AUTOINCREMENT and identity columns of a new table into plain
+/// NOT NULL columns defaulting to `nextval` of a sequence named like
+/// `users_id_seq`. Returns the sequences, owned by their columns, which the
+/// caller adds once the table is created.
+pub fn auto_increment_columns(
+    database: &Database,
+    table_name: &str,
+    column_defs: &mut [ColumnDef],
+) -> Result<Vec<Sequence>, String> {
+    let mut sequences: Vec<Sequence> = Vec::new();
+    for column_def in column_defs {
+        let mut options: Option<Vec<SequenceOptions>> = None;
+        if let Some(data_type) = serial_type(&column_def.data_type) {
+            column_def.data_type = data_type;
+            options = Some(vec![]);
+        }
+        let (markers, rest): (Vec<_>, Vec<_>) = column_def
+            .options
+            .drain(..)
+            .partition(|option_def| is_auto_increment(&option_def.option));
+        column_def.options = rest;
+        if !markers.is_empty() {
+            // A saved definition already draws from its sequence
+            if column_def.options.iter().any(|option_def| {
+                matches!(&option_def.option, ColumnOption::Default(expr) if is_nextval_call(expr))
+            }) {
+                column_def.options.extend(markers);
+                continue;
+            }
+            options = Some(vec![]);
+        }
+        for option_def in &column_def.options {
+            if let ColumnOption::Generated {
+                sequence_options,
+                generation_expr: None,
+                ..
+            } = &option_def.option
+            {
+                options = Some(sequence_options.clone().unwrap_or_default());
+            }
+        }
+        let Some(options) = options else {
+            continue;
+        };
+        let column_name = &column_def.name.value;
+        if column_def
+            .options
+            .iter()
+            .any(|option_def| matches!(option_def.option, ColumnOption::Default(_)))
+        {
+            return Err(format!(
+                "Both default and auto-increment specified for column '{}' of table '{}'",
+                column_name, table_name
+            ));
+        }
+
+        // The sequence goes into the schema of its table
+        let (schema, table) = split_name(table_name);
+        let base = format!("{}_{}_seq", table, column_name);
+        let mut name = qualified_name(&schema, &base);
+        let mut counter = 1;
+        while database.sequences.contains_key(&name) || sequences.iter().any(|s| s.name == name) {
+            name = qualified_name(&schema, &format!("{}{}", base, counter));
+            counter += 1;
+        }
+        let mut sequence = Sequence::from_options(&name, Some(&column_def.data_type), &options)?;
+        sequence.owned_by = Some((table_name.to_string(), column_name.clone()));
+        sequences.push(sequence);
+
+        column_def.options.push(ColumnOptionDef {
+            name: None,
+            option: ColumnOption::NotNull,
+        });
+        column_def.options.push(ColumnOptionDef {
+            name: None,
+            option: ColumnOption::Default(nextval_call(&name)),
+        });
+        column_def.options.extend(markers);
+    }
+    Ok(sequences)
+}
+
+/// The integer type behind SERIAL, SMALLSERIAL and BIGSERIAL.
+fn serial_type(data_type: &DataType) -> Option<DataType> {
+    let DataType::Custom(name, modifiers) = data_type else {
+        return None;
+    };
+    if !modifiers.is_empty() {
+        return None;
+    }
+    match name.to_string().to_uppercase().as_str() {
+        "SERIAL" | "SERIAL4" => Some(DataType::Int(None)),
+        "SMALLSERIAL" | "SERIAL2" => Some(DataType::SmallInt(None)),
+        "BIGSERIAL" | "SERIAL8" => Some(DataType::BigInt(None)),
+        _ => None,
+    }
+}
+
+/// SQLite's `AUTOINCREMENT` and MySQL's `AUTO_INCREMENT`.
+pub fn is_auto_increment(option: &ColumnOption) -> bool {
+    match option {
+        ColumnOption::DialectSpecific(tokens) => tokens.iter().any(|token| {
+            matches!(token, Token::Word(word)
+                if word.keyword == Keyword::AUTOINCREMENT || word.keyword == Keyword::AUTO_INCREMENT)
+        }),
+        _ => false,
+    }
+}
+
+fn is_nextval_call(expr: &Expr) -> bool {
+    matches!(expr, Expr::Function(function) if function.name.to_string().eq_ignore_ascii_case("nextval"))
+}
+
+fn nextval_call(sequence_name: &str) -> Expr {
+    Expr::Function(Function {
+        name: ObjectName(vec![Ident::new("nextval")]),
+        args: vec![FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+            Literal::SingleQuotedString(sequence_name.to_string()),
+        )))],
+        over: None,
+        distinct: false,
+        special: false,
+        order_by: vec![],
+    })
+}
+
+pub fn create_sequence(
+    database: &mut Database,
+    name: &ObjectName,
+    if_not_exists: bool,
+    data_type: Option<&DataType>,
+    options: &[SequenceOptions],
+    owned_by: Option<&ObjectName>,
+) -> Result<(), String> {
+    let sequence_name = name.to_string();
+    if database.sequences.contains_key(&sequence_name) {
+        if if_not_exists {
+            database.report(format!(
+                "Sequence '{}' already exists, skipping",
+                sequence_name
+            ));
+            return Ok(());
+        }
+        return Err(format!("Sequence '{}' already exists", sequence_name));
+    }
+    let mut sequence = Sequence::from_options(&sequence_name, data_type, options)?;
+    if let Some(owner) = owned_by {
+        let Some((column_name, table_name)) = owner.0.split_last().filter(|(_, t)| !t.is_empty())
+        else {
+            return Err(format!("Invalid OWNED BY column: {}", owner));
+        };
+        let table_name = ObjectName(table_name.to_vec()).to_string();
+        let table = database
+            .tables
+            .get(&table_name)
+            .ok_or_else(|| format!("Table '{}' does not exist", table_name))?;
+        if table.column_index(&column_name.value).is_none() {
+            return Err(format!(
+                "Column '{}' does not exist in table '{}'",
+                column_name.value, table_name
+            ));
+        }
+        sequence.owned_by = Some((table_name, column_name.value.clone()));
+    }
+    database.sequences.insert(sequence_name.clone(), sequence);
+    database.report(format!("Created sequence: {}", sequence_name));
+    Ok(())
+}
+
+/// Drops sequences. A column whose default calls `nextval` on a dropped
+/// sequence blocks the drop, or loses its default with CASCADE.
+pub fn drop_sequences(
+    database: &mut Database,
+    names: &[ObjectName],
+    if_exists: bool,
+    cascade: bool,
+) -> Result<(), String> {
+    let mut dropped: Vec<String> = Vec::new();
+    for name in names {
+        let sequence_name = name.to_string();
+        if database.sequences.contains_key(&sequence_name) {
+            if !dropped.contains(&sequence_name) {
+                dropped.push(sequence_name);
+            }
+        } else if if_exists {
+            database.report(format!(
+                "Sequence '{}' does not exist, skipping",
+                sequence_name
+            ));
+        } else {
+            return Err(format!("Sequence '{}' does not exist", sequence_name));
+        }
+    }
+    for sequence_name in &dropped {
+        let users = sequence_users(database, sequence_name);
+        if let Some((table_name, column_name)) = users.first() {
+            if !cascade {
+                return Err(format!(
+                    "Cannot drop sequence '{}' because the default of column '{}' of table '{}' depends on it",
+                    sequence_name, column_name, table_name
+                ));
+            }
+        }
+        for (table_name, column_name) in users {
+            let table = database.tables.get_mut(&table_name).unwrap();
+            let index = table.column_index(&column_name).unwrap();
+            table.columns[index].default = None;
+            table.columns[index].generated_always = false;
+            table.columns[index].auto_increment = false;
+        }
+    }
+    for sequence_name in dropped {
+        database.sequences.remove(&sequence_name);
+        database.report(format!("Dropped sequence: {}", sequence_name));
+    }
+    Ok(())
+}
+
+/// Columns, as (table, column), whose default calls `nextval` on the sequence.
+fn sequence_users(database: &Database, sequence_name: &str) -> Vec<(String, String)> {
+    let mut users = Vec::new();
+    for table in database.tables.values() {
+        for column in &table.columns {
+            let uses_sequence = visit_expressions(&column.default, |expr| match expr {
+                Expr::Function(function)
+                    if function.name.to_string().eq_ignore_ascii_case("nextval")
+                        && matches!(function.args.as_slice(),
+                            [FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+                                Literal::SingleQuotedString(name),
+                            )))] if name == sequence_name) =>
+                {
+                    ControlFlow::Break(())
+                }
+                _ => ControlFlow::Continue(()),
+            })
+            .is_break();
+            if uses_sequence {
+                users.push((table.name.clone(), column.name.clone()));
+            }
+        }
+    }
+    users
+}
+
+/// The sequence behind an identity, SERIAL or AUTOINCREMENT column: the one
+/// owned by the column that its default draws from.
+pub fn identity_sequence<'a>(
+    database: &'a Database,
+    table_name: &str,
+    column_name: &str,
+) -> Option<&'a Sequence> {
+    let owner = (table_name.to_string(), column_name.to_string());
+    database.sequences.values().find(|sequence| {
+        sequence.owned_by.as_ref() == Some(&owner)
+            && sequence_users(database, &sequence.name).contains(&owner)
+    })
+}
+
+/// Moves the sequence of an AUTOINCREMENT column past a value inserted
+/// explicitly, so that it never hands out a value that was already used.
+pub fn advance_auto_increment(
+    database: &Database,
+    table_name: &str,
+    column_name: &str,
+    value: &Value,
+) {
+    let (Some(sequence), Value::Integer(value)) =
+        (identity_sequence(database, table_name, column_name), value)
+    else {
+        return;
+    };
+    let last = sequence
+        .last_value
+        .get()
+        .unwrap_or(sequence.start.saturating_sub(sequence.increment));
+    let passed = if sequence.increment > 0 {
+        *value > last
+    } else {
+        *value < last
+    };
+    if passed && (sequence.min_value..=sequence.max_value).contains(value) {
+        sequence.last_value.set(Some(*value));
+    }
+}
+
+/// Drops the sequences owned by a table, or by one of its columns.
+pub fn drop_owned_sequences(database: &mut Database, table_name: &str, column_name: Option<&str>) {
+    database.sequences.retain(|_, sequence| {
+        !matches!(&sequence.owned_by, Some((table, column))
+            if table == table_name && column_name.is_none_or(|name| name == column))
+    });
+}
+
+/// Makes the sequences owned by a table start over, as after TRUNCATE.
+pub fn restart_owned_sequences(database: &Database, table_name: &str) {
+    for sequence in database.sequences.values() {
+        if matches!(&sequence.owned_by, Some((table, _)) if table == table_name) {
+            sequence.last_value.set(None);
+        }
+    }
+}
+
+/// Points owned sequences at a renamed table or column.
+pub fn rename_sequence_owner(
+    database: &mut Database,
+    table_name: &str,
+    new_table_name: &str,
+    column_names: Option<(&str, &str)>,
+) {
+    for sequence in database.sequences.values_mut() {
+        if let Some((table, column)) = &mut sequence.owned_by {
+            if table != table_name {
+                continue;
+            }
+            *table = new_table_name.to_string();
+            if let Some((old_name, new_name)) = column_names {
+                if column == old_name {
+                    *column = new_name.to_string();
+                }
+            }
+        }
+    }
+}
+
+/// `nextval(name)`, `currval(name)` and `setval(name, value)`.
+pub fn sequence_function(
+    name: &str,
+    args: Vec<Value>,
+    database: &Database,
+) -> Result<Value, String> {
+    let sequence_name = match args.first() {
+        Some(Value::Text(sequence_name)) => sequence_name,
+        _ => return Err(format!("{} expects a sequence name", name)),
+    };
+    // The name may be qualified, and is otherwise looked up on the search path
+    let parts = name_parts(sequence_name)
+        .into_iter()
+        .map(Ident::new)
+        .collect();
+    let key = resolve_name(database, &ObjectName(parts))?.to_string();
+    let sequence = database
+        .sequences
+        .get(&key)
+        .ok_or_else(|| format!("Sequence '{}' does not exist", sequence_name))?;
+    match (name, &args[1..]) {
+        ("NEXTVAL", []) => sequence.next_value().map(Value::Integer),
+        ("CURRVAL", []) => sequence.current_value().map(Value::Integer),
+        ("SETVAL", [Value::Integer(value)]) => {
+            if *value < sequence.min_value || *value > sequence.max_value {
+                return Err(format!(
+                    "setval: value {} is out of bounds for sequence '{}' ({}..{})",
+                    value, sequence_name, sequence.min_value, sequence.max_value
+                ));
+            }
+            sequence.last_value.set(Some(*value));
+            Ok(Value::Integer(*value))
+        }
+        ("SETVAL", _) => Err("SETVAL expects a sequence name and an integer".to_string()),
+        _ => Err(format!("{} expects 1 argument(s)", name)),
+    }
+}
@@ -0,0 +1,388 @@
+use super::database::{quote_identifier, split_name, Database};
+use super::dump::dump;
+use super::output::{render, OutputMode};
+use super::query_execution::StatementOutput;
+use super::schemas::resolve_name;
+use super::show_statements::show_create;
+use super::sql_parsing::{is_complete, parse_statements, split_statements};
+use super::transactions::Session;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use sqlparser::ast::{Ident, ObjectName, ShowCreateObject};
+use std::path::PathBuf;
+use std::time::Instant;
+
+const PROMPT: &str = "dbms> ";
+const CONTINUATION_PROMPT: &str = "  ...> ";
+
+const DOT_COMMANDS: [&str; 9] = [
+    ".help", ".quit", ".exit", ".tables", ".schema", ".dump", ".read", ".timer", ".mode",
+];
+
+const HELP: &str = ".help              Show this help
+.quit, .exit       Leave the shell
+.tables [PATTERN]  List tables and views, optionally matching a LIKE pattern
+.schema [NAME]     Show the statements that create NAME, or every object
+.dump              Show the statements that rebuild the database and its rows
+.read FILE         Run the statements in FILE
+.timer on|off      Show how long each statement takes
+.mode [MODE]       Show or set how results are printed: table, csv, tsv,
+                   json, ndjson, markdown or vertical
+
+Statements end with a semicolon and may span several lines.";
+
+/// Runs the statements of `sql` one by one, stopping at the first error.
+/// Errors in a named source point at the line of the failing statement.
+pub fn run_script(
+    database: &mut Database,
+    session: &mut Session,
+    sql: &str,
+    source: Option<&str>,
+    mode: OutputMode,
+) -> Result<(), String> {
+    let mut line = 1;
+    for piece in split_statements(sql) {
+        let leading = &piece[..piece.len() - piece.trim_start().len()];
+        let statement_line = line + leading.matches('\n').count();
+        line += piece.matches('\n').count();
+        let result = parse_statements(piece).and_then(|ast| session.execute(database, ast));
+        match result {
+            Ok(output) => print_output(output, mode),
+            Err(err) => {
+                return Err(match source {
+                    Some(source) => format!("{}:{}: {}", source, statement_line, err),
+                    None => err,
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints the rows statements return in the given mode. What they report
+/// goes to stderr when the rows are data for another program.
+pub fn print_output(output: Vec<StatementOutput>, mode: OutputMode) {
+    for output in output {
+        match output {
+            StatementOutput::Rows(result) => print!("{}", render(&result, mode)),
+            StatementOutput::Text(text) => print!("{}", text),
+            StatementOutput::Message(message) if mode.is_data() => eprintln!("{}", message),
+            StatementOutput::Message(message) => println!("{}", message),
+        }
+    }
+}
+
+/// The interactive shell. Lines are collected until they end a statement
+/// with a semicolon, and lines starting with a dot are shell commands.
+pub fn repl(database: &mut Database, mode: OutputMode) {
+    let mut editor: Editor<NameCompleter, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("Cannot start the shell: {}", err);
+            return;
+        }
+    };
+    editor.set_helper(Some(NameCompleter::default()));
+    let history = history_path();
+    if let Some(path) = &history {
+        // There is no history yet on the first run
+        let _ = editor.load_history(path);
+    }
+    println!("Enter \".help\" for usage hints.");
+
+    let mut shell = Shell {
+        session: Session::default(),
+        timer: false,
+        mode,
+    };
+    let mut buffer = String::new();
+    loop {
+        if let Some(helper) = editor.helper_mut() {
+            helper.refresh(database);
+        }
+        let prompt = if buffer.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C abandons the statement being typed
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                break;
+            }
+        };
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+        if buffer.is_empty() && line.trim_start().starts_with('.') {
+            match shell.dot_command(database, line.trim()) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => eprintln!("Error: {}", err),
+            }
+            continue;
+        }
+        buffer.push_str(&line);
+        buffer.push('\n');
+        if !is_complete(&buffer) {
+            continue;
+        }
+        let sql = std::mem::take(&mut buffer);
+        if !sql.trim().is_empty() {
+            shell.execute(database, &sql);
+        }
+    }
+    if shell.session.close(database) {
+        println!("Rolled back the transaction that was left open");
+    }
+    if let Some(path) = &history {
+        if let Err(err) = editor.save_history(path) {
+            eprintln!("Failed to save history: {}", err);
+        }
+    }
+}
+
+/// The history is kept in `~/.dbms_history`.
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".dbms_history"))
+}
+
+/// The session of the shell, and settings changed by dot-commands.
+struct Shell {
+    session: Session,
+    timer: bool,
+    mode: OutputMode,
+}
+
+impl Shell {
+    fn execute(&mut self, database: &mut Database, sql: &str) {
+        let start = Instant::now();
+        if let Err(err) = run_script(database, &mut self.session, sql, None, self.mode) {
+            eprintln!("Error: {}", err);
+        }
+        if self.timer {
+            println!("Run time: {:.3} ms", start.elapsed().as_secs_f64() * 1000.0);
+        }
+        if let Err(err) = database.save() {
+            eprintln!("Failed to save database: {}", err);
+        }
+    }
+
+    /// Runs a dot-command. Returns false when the shell should exit.
+    fn dot_command(&mut self, database: &mut Database, line: &str) -> Result<bool, String> {
+        let (command, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let argument = argument.trim();
+        match command {
+            ".quit" | ".exit" => return Ok(false),
+            ".help" => println!("{}", HELP),
+            ".tables" => {
+                let mut names: Vec<&String> = database.tables.keys().collect();
+                names.retain(|name| !database.is_materialized_view(name));
+                names.extend(database.views.keys());
+                names.retain(|name| {
+                    argument.is_empty() || like(&name.to_lowercase(), &argument.to_lowercase())
+                });
+                names.sort();
+                for name in names {
+                    println!("{}", name);
+                }
+            }
+            ".schema" => {
+                for statement in schema_statements(database, argument)? {
+                    println!("{}", statement);
+                }
+            }
+            ".dump" => print!("{}", dump(&self.session.snapshot(database))),
+            ".read" if !argument.is_empty() => {
+                let sql = std::fs::read_to_string(argument)
+                    .map_err(|err| format!("Cannot read '{}': {}", argument, err))?;
+                let result =
+                    run_script(database, &mut self.session, &sql, Some(argument), self.mode);
+                if let Err(err) = database.save() {
+                    eprintln!("Failed to save database: {}", err);
+                }
+                result?;
+            }
+            ".timer" => match argument {
+                "on" => self.timer = true,
+                "off" => self.timer = false,
+                _ => return Err("Usage: .timer on|off".to_string()),
+            },
+            ".read" => return Err("Usage: .read FILE".to_string()),
+            ".mode" if argument.is_empty() => {
+                let name = OutputMode::NAMES
+                    .into_iter()
+                    .find(|name| OutputMode::from_name(name) == Ok(self.mode))
+                    .unwrap_or_default();
+                println!("{}", name);
+            }
+            ".mode" => self.mode = OutputMode::from_name(argument)?,
+            _ => {
+                return Err(format!(
+                    "Unknown command '{}', enter \".help\" for the list",
+                    command
+                ))
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// `LIKE` matching of a whole name, with `%` and `_` as wildcards.
+fn like(name: &str, pattern: &str) -> bool {
+    match pattern.chars().next() {
+        None => name.is_empty(),
+        Some('%') => {
+            let rest = &pattern[1..];
+            name.char_indices()
+                .map(|(index, _)| index)
+                .chain([name.len()])
+                .any(|index| like(&name[index..], rest))
+        }
+        Some(ch) => {
+            let mut chars = name.chars();
+            match chars.next() {
+                Some(first) if ch == '_' || ch == first => {
+                    like(chars.as_str(), &pattern[ch.len_utf8()..])
+                }
+                _ => false,
+            }
+        }
+    }
+}
+
+/// The statements that create the object called `name`, or every table,
+/// view and free-standing sequence when `name` is empty.
+fn schema_statements(database: &Database, name: &str) -> Result<Vec<String>, String> {
+    let mut keys: Vec<String> = if name.is_empty() {
+        database
+            .tables
+            .keys()
+            .filter(|key| !database.is_materialized_view(key))
+            .chain(database.views.keys())
+            .chain(
+                database
+                    .sequences
+                    .values()
+                    .filter(|sequence| sequence.owned_by.is_none())
+                    .map(|sequence| &sequence.name),
+            )
+            .cloned()
+            .collect()
+    } else {
+        let parts = name.split('.').map(Ident::new).collect();
+        vec![resolve_name(database, &ObjectName(parts))?.to_string()]
+    };
+    keys.sort();
+
+    let mut statements = Vec::new();
+    if name.is_empty() {
+        for schema in database.schemas.iter().skip(1) {
+            if !database.attachments.iter().any(|a| a.name == *schema) {
+                statements.push(format!("CREATE SCHEMA {};", schema));
+            }
+        }
+    }
+    for key in &keys {
+        let object_name = ObjectName(vec![Ident::new(key)]);
+        let object_type = if database.views.contains_key(key) {
+            ShowCreateObject::View
+        } else if database.tables.contains_key(key) {
+            ShowCreateObject::Table
+        } else if let Some(sequence) = database.sequences.get(key) {
+            statements.push(format!("{};", sequence.create_statement()));
+            continue;
+        } else {
+            return Err(format!("No table, view or sequence is called '{}'", key));
+        };
+        let result = show_create(database, &object_type, &object_name)?;
+        statements.push(result.rows[0][1].to_string());
+    }
+    Ok(statements)
+}
+
+/// Completes table, view and column names from the catalog, and dot-commands.
+#[derive(Default)]
+struct NameCompleter {
+    names: Vec<String>,
+}
+
+impl NameCompleter {
+    /// Takes in the names of the catalog, which change between statements.
+    fn refresh(&mut self, database: &Database) {
+        let mut names: Vec<String> = Vec::new();
+        for (key, table) in &database.tables {
+            let name = quote_identifier(&split_name(key).1);
+            names.push(key.clone());
+            for column in &table.columns {
+                names.push(column.name.clone());
+                names.push(format!("{}.{}", name, column.name));
+            }
+            names.push(name);
+        }
+        for key in database.views.keys() {
+            names.push(key.clone());
+            names.push(quote_identifier(&split_name(key).1));
+        }
+        names.sort();
+        names.dedup();
+        self.names = names;
+    }
+}
+
+impl Completer for NameCompleter {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        if before.starts_with('.') && !before.contains(char::is_whitespace) {
+            let commands = DOT_COMMANDS
+                .iter()
+                .filter(|command| command.starts_with(before))
+                .map(|command| command.to_string())
+                .collect();
+            return Ok((0, commands));
+        }
+        let start = before
+            .rfind(|ch: char| !(ch.is_alphanumeric() || ch == '_' || ch == '.'))
+            .map_or(0, |index| index + 1);
+        let word = before[start..].to_lowercase();
+        if word.is_empty() {
+            return Ok((pos, Vec::new()));
+        }
+        let candidates = self
+            .names
+            .iter()
+            .filter(|name| name.to_lowercase().starts_with(&word))
+            .cloned()
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for NameCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for NameCompleter {}
+
+impl Validator for NameCompleter {}
+
+impl Helper for NameCompleter {}
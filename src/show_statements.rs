@@ -0,0 +1,262 @@
+use super::database::{split_name, Database, Table};
+use super::expression_evaluation::{evaluate_predicate, ColumnRef, Scope};
+use super::query_execution::QueryResult;
+use super::select_execution::execute_query;
+use super::value::Value;
+use sqlparser::ast::{Expr, Ident, ObjectName, ShowCreateObject, ShowStatementFilter};
+use sqlparser::ast::{TableConstraint, Value as Literal};
+
+/// `SHOW [FULL] TABLES [FROM schema] [LIKE ... | WHERE ...]`: the tables and
+/// views, sorted by name. Without FROM, objects outside `public` are listed
+/// with their schema. FULL adds whether each one is a table or a view.
+pub fn show_tables(
+    database: &Database,
+    full: bool,
+    schema: Option<&str>,
+    filter: Option<&ShowStatementFilter>,
+) -> Result<QueryResult, String> {
+    if let Some(schema) = schema {
+        if !database.schemas.iter().any(|s| s == schema) {
+            return Err(format!("Schema '{}' does not exist", schema));
+        }
+    }
+    let listed_name = |key: &str| match schema {
+        Some(schema) => {
+            let (key_schema, name) = split_name(key);
+            (key_schema == schema).then(|| name.to_string())
+        }
+        None => Some(key.to_string()),
+    };
+    let mut rows: Vec<Vec<Value>> = database
+        .tables
+        .keys()
+        .filter(|name| !database.is_materialized_view(name))
+        .map(|name| (name, "BASE TABLE"))
+        .chain(database.views.values().map(|view| {
+            let table_type = if view.materialized {
+                "MATERIALIZED VIEW"
+            } else {
+                "VIEW"
+            };
+            (&view.name, table_type)
+        }))
+        .filter_map(|(key, table_type)| {
+            listed_name(key).map(|name| vec![text(&name), text(table_type)])
+        })
+        .collect();
+    rows.sort_by(|a, b| a[0].sort_cmp(&b[0]));
+    let mut result = QueryResult {
+        columns: vec!["table_name".to_string(), "table_type".to_string()],
+        rows,
+    };
+    filter_rows(&mut result, filter, database)?;
+    if !full {
+        result.columns.truncate(1);
+        for row in &mut result.rows {
+            row.truncate(1);
+        }
+    }
+    Ok(result)
+}
+
+/// `SHOW COLUMNS FROM t` and `DESCRIBE t`: one row per column with its type,
+/// nullability, key and default, in the spirit of MySQL. `key` is PRI for the
+/// primary key, UNI for a single-column unique key and MUL for the first
+/// column of another index or a foreign key.
+pub fn show_columns(
+    database: &Database,
+    name: &ObjectName,
+    filter: Option<&ShowStatementFilter>,
+) -> Result<QueryResult, String> {
+    let table_name = name.to_string();
+    let rows = if let Some(table) = database.tables.get(&table_name) {
+        table
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| {
+                let nullable = !column.not_null && column_key(table, index) != "PRI";
+                let owns_sequence = database.sequences.values().any(|sequence| {
+                    sequence.owned_by == Some((table.name.clone(), column.name.clone()))
+                });
+                let extra = if column.generated_always {
+                    "GENERATED ALWAYS AS IDENTITY"
+                } else if owns_sequence {
+                    "auto_increment"
+                } else {
+                    ""
+                };
+                vec![
+                    text(&column.name),
+                    text(&column.data_type.to_string()),
+                    text(if nullable { "YES" } else { "NO" }),
+                    text(column_key(table, index)),
+                    column
+                        .default
+                        .as_ref()
+                        .map_or(Value::Null, |default| text(&default.to_string())),
+                    text(extra),
+                ]
+            })
+            .collect()
+    } else if let Some(view) = database.views.get(&table_name) {
+        // Views have no declared types, only the names their query produces
+        let mut names = execute_query(database, &view.query)?.columns;
+        for (name, alias) in names.iter_mut().zip(&view.columns) {
+            *name = alias.value.clone();
+        }
+        names
+            .iter()
+            .map(|name| {
+                vec![
+                    text(name),
+                    Value::Null,
+                    text("YES"),
+                    text(""),
+                    Value::Null,
+                    text(""),
+                ]
+            })
+            .collect()
+    } else {
+        return Err(format!("Table '{}' does not exist", table_name));
+    };
+    let mut result = QueryResult {
+        columns: [
+            "column_name",
+            "data_type",
+            "nullable",
+            "key",
+            "default",
+            "extra",
+        ]
+        .iter()
+        .map(|col| col.to_string())
+        .collect(),
+        rows,
+    };
+    filter_rows(&mut result, filter, database)?;
+    Ok(result)
+}
+
+fn column_key(table: &Table, index: usize) -> &'static str {
+    if table.primary_key().unwrap_or_default().contains(&index) {
+        return "PRI";
+    }
+    let unique = table
+        .unique_keys()
+        .iter()
+        .any(|(_, columns, _)| columns == &[index]);
+    if unique {
+        return "UNI";
+    }
+    let column_name = &table.columns[index].name;
+    let indexed = table
+        .indexes
+        .iter()
+        .any(|index| index.columns.first() == Some(column_name));
+    let referencing = table.constraints.iter().any(|constraint| {
+        matches!(constraint, TableConstraint::ForeignKey { columns, .. }
+            if columns.first().is_some_and(|col| col.value == *column_name))
+    });
+    if indexed || referencing {
+        "MUL"
+    } else {
+        ""
+    }
+}
+
+/// `SHOW CREATE TABLE` and `SHOW CREATE VIEW`: the statement that recreates
+/// the object, regenerated from the catalog. A table's indexes follow its
+/// CREATE TABLE as separate statements.
+pub fn show_create(
+    database: &Database,
+    object_type: &ShowCreateObject,
+    name: &ObjectName,
+) -> Result<QueryResult, String> {
+    let object_name = name.to_string();
+    let statement = match object_type {
+        ShowCreateObject::Table if database.is_materialized_view(&object_name) => {
+            return Err(format!(
+                "'{}' is a materialized view, use SHOW CREATE VIEW",
+                object_name
+            ))
+        }
+        ShowCreateObject::Table => {
+            let table = database
+                .tables
+                .get(&object_name)
+                .ok_or_else(|| format!("Table '{}' does not exist", object_name))?;
+            let mut statements = vec![table.create_statement()];
+            statements.extend(
+                table
+                    .indexes
+                    .iter()
+                    .map(|index| index.create_statement(&table.name)),
+            );
+            statements.join(";\n")
+        }
+        ShowCreateObject::View => database
+            .views
+            .get(&object_name)
+            .ok_or_else(|| format!("View '{}' does not exist", object_name))?
+            .create_statement(),
+        other => return Err(format!("SHOW CREATE {} is not supported", other)),
+    };
+    Ok(QueryResult {
+        columns: vec!["name".to_string(), "create_statement".to_string()],
+        rows: vec![vec![text(&object_name), text(&format!("{};", statement))]],
+    })
+}
+
+fn text(value: &str) -> Value {
+    Value::Text(value.to_string())
+}
+
+/// Keeps the rows matching a SHOW filter. LIKE and ILIKE match the first
+/// column; WHERE can use any column of the result.
+fn filter_rows(
+    result: &mut QueryResult,
+    filter: Option<&ShowStatementFilter>,
+    database: &Database,
+) -> Result<(), String> {
+    let first_column = || Box::new(Expr::Identifier(Ident::new(&result.columns[0])));
+    let pattern = |pattern: &str| {
+        Box::new(Expr::Value(Literal::SingleQuotedString(
+            pattern.to_string(),
+        )))
+    };
+    let predicate = match filter {
+        None => return Ok(()),
+        Some(ShowStatementFilter::Like(like)) => Expr::Like {
+            negated: false,
+            expr: first_column(),
+            pattern: pattern(like),
+            escape_char: None,
+        },
+        Some(ShowStatementFilter::ILike(like)) => Expr::ILike {
+            negated: false,
+            expr: first_column(),
+            pattern: pattern(like),
+            escape_char: None,
+        },
+        Some(ShowStatementFilter::Where(expr)) => expr.clone(),
+    };
+    let columns: Vec<ColumnRef> = result
+        .columns
+        .iter()
+        .map(|name| ColumnRef {
+            schema: None,
+            table: None,
+            name: name.clone(),
+        })
+        .collect();
+    let mut rows = Vec::with_capacity(result.rows.len());
+    for row in result.rows.drain(..) {
+        if evaluate_predicate(&predicate, &Scope::new(&columns, &row), database)? {
+            rows.push(row);
+        }
+    }
+    result.rows = rows;
+    Ok(())
+}
@@ -1,8 +1,392 @@
-use sqlparser::dialect::GenericDialect;
-use sqlparser::parser::Parser;
+use sqlparser::ast::{Ident, ObjectName, Statement};
+use sqlparser::dialect::{Dialect, GenericDialect, PostgreSqlDialect, SQLiteDialect};
+use sqlparser::keywords::Keyword;
+use sqlparser::parser::{Parser, ParserError};
+use sqlparser::tokenizer::{Token, TokenWithLocation, Tokenizer};
 
-pub fn parse_sql_queries(sql_query: &str) -> Vec<sqlparser::ast::Statement> {
+/// A statement of the input: either one sqlparser understands, or one of the
+/// statements it does not know, which are recognised and parsed here.
+#[derive(Debug)]
+pub enum ParsedStatement {
+    Sql(Box<Statement>),
+    /// `CREATE MATERIALIZED VIEW ... WITH [NO] DATA`; the statement is the
+    /// CREATE VIEW that precedes the clause
+    CreateMaterializedView {
+        statement: Box<Statement>,
+        with_data: bool,
+    },
+    RefreshMaterializedView {
+        name: ObjectName,
+    },
+    DropMaterializedView {
+        names: Vec<ObjectName>,
+        if_exists: bool,
+        cascade: bool,
+    },
+    AttachDatabase {
+        file_path: String,
+        name: Ident,
+    },
+    DetachDatabase {
+        name: Ident,
+    },
+    BackupDatabase {
+        name: Option<Ident>,
+        file_path: String,
+    },
+    /// `ALTER TABLE t ALTER COLUMN c SET GENERATED {ALWAYS | BY DEFAULT}`
+    SetGenerated {
+        table_name: ObjectName,
+        column_name: Ident,
+        always: bool,
+    },
+    /// `VACUUM [table]`
+    Vacuum {
+        table_name: Option<ObjectName>,
+    },
+    /// A COPY statement with the `CREATE_TABLE [records]` option, which
+    /// creates a missing table from the first records of a JSON import
+    CopyCreatingTable {
+        statement: Box<Statement>,
+        sample_size: usize,
+    },
+}
+
+/// Parses every statement of the input, including the extensions of
+/// `ParsedStatement`.
+pub fn parse_statements(sql: &str) -> Result<Vec<ParsedStatement>, String> {
+    let mut statements = Vec::new();
+    for piece in split_statements(sql) {
+        match parse_extension(piece) {
+            Some(statement) => {
+                statements.push(statement.map_err(|err| format!("Failed to parse SQL: {}", err))?)
+            }
+            None => statements.extend(
+                parse_sql_queries(piece)?
+                    .into_iter()
+                    .map(|statement| ParsedStatement::Sql(Box::new(statement))),
+            ),
+        }
+    }
+    Ok(statements)
+}
+
+pub fn parse_sql_queries(sql_query: &str) -> Result<Vec<Statement>, String> {
     let dialect = GenericDialect {};
-    let ast = Parser::parse_sql(&dialect, sql_query).expect("Failed to parse SQL");
-    ast
+    // `INSERT OR REPLACE` / `INSERT OR IGNORE` are only understood by the SQLite
+    // dialect and `ALTER COLUMN ... TYPE ... USING` only by the PostgreSQL one
+    parse_with_dialect(&dialect, sql_query)
+        .or_else(|err| parse_with_dialect(&SQLiteDialect {}, sql_query).map_err(|_| err))
+        .or_else(|err| parse_with_dialect(&PostgreSqlDialect {}, sql_query).map_err(|_| err))
+        .map_err(|err| format!("Failed to parse SQL: {}", err))
+}
+
+fn parse_with_dialect(
+    dialect: &dyn Dialect,
+    sql_query: &str,
+) -> Result<Vec<Statement>, ParserError> {
+    let tokens = Tokenizer::new(dialect, sql_query).tokenize_with_location()?;
+    Parser::new(dialect)
+        .with_tokens_with_locations(expand_alter_column_type(tokens))
+        .parse_statements()
+}
+
+/// Splits the input after each semicolon outside of literals and comments.
+pub fn split_statements(sql: &str) -> Vec<&str> {
+    let Ok(tokens) = Tokenizer::new(&GenericDialect {}, sql).tokenize_with_location() else {
+        // Leave it to the parser to report the error
+        return vec![sql];
+    };
+    let mut semicolons = tokens
+        .iter()
+        .filter(|token| token.token == Token::SemiColon)
+        .map(|token| (token.location.line, token.location.column))
+        .peekable();
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    // Token locations count lines and characters from 1
+    let (mut line, mut column) = (1, 1);
+    for (offset, ch) in sql.char_indices() {
+        if semicolons.peek() == Some(&(line, column)) {
+            pieces.push(&sql[start..=offset]);
+            start = offset + 1;
+            semicolons.next();
+        }
+        if ch == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    pieces.push(&sql[start..]);
+    pieces
+}
+
+/// Whether the input ends with a complete statement: its last token other
+/// than whitespace and comments is a semicolon. An unterminated literal or
+/// comment does not end anything.
+pub fn is_complete(sql: &str) -> bool {
+    let Ok(tokens) = Tokenizer::new(&GenericDialect {}, sql).tokenize() else {
+        return false;
+    };
+    tokens
+        .iter()
+        .rev()
+        .find(|token| !matches!(token, Token::Whitespace(_)))
+        .is_none_or(|token| *token == Token::SemiColon)
+}
+
+/// Parses `piece` if it is one of the statements sqlparser does not know.
+fn parse_extension(piece: &str) -> Option<Result<ParsedStatement, ParserError>> {
+    let dialect = GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, piece)
+        .tokenize_with_location()
+        .ok()?;
+    let words: Vec<String> = tokens
+        .iter()
+        .filter(|token| !matches!(token.token, Token::Whitespace(_)))
+        .map(|token| match &token.token {
+            Token::Word(word) if word.quote_style.is_none() => word.value.to_uppercase(),
+            _ => String::new(),
+        })
+        .collect();
+    // The last words, before an optional semicolon
+    let ends_with = |tail: &[&str]| {
+        let words = words.strip_suffix(&[String::new()]).unwrap_or(&words);
+        words.len() >= tail.len() && words[words.len() - tail.len()..] == *tail
+    };
+    let mut parser = Parser::new(&dialect).with_tokens_with_locations(tokens.clone());
+    let statement = match words.iter().map(String::as_str).collect::<Vec<&str>>()[..] {
+        ["CREATE", "MATERIALIZED", ..]
+            if ends_with(&["WITH", "DATA"]) || ends_with(&["WITH", "NO", "DATA"]) =>
+        {
+            parse_create_materialized_view(&mut parser)
+        }
+        ["REFRESH", "MATERIALIZED", ..] => parse_refresh_materialized_view(&mut parser),
+        ["DROP", "MATERIALIZED", ..] => parse_drop_materialized_view(&mut parser),
+        ["ATTACH", ..] => parse_attach_database(&mut parser),
+        ["DETACH", ..] => parse_detach_database(&mut parser),
+        ["BACKUP", ..] => parse_backup_database(&mut parser),
+        ["VACUUM", ..] => parse_vacuum(&mut parser),
+        ["COPY", ..] if words.iter().any(|word| word == "CREATE_TABLE") => {
+            take_create_table_option(tokens).and_then(|(tokens, sample_size)| {
+                parser = Parser::new(&dialect).with_tokens_with_locations(tokens);
+                let statement = parser.parse_statement()?;
+                Ok(ParsedStatement::CopyCreatingTable {
+                    statement: Box::new(statement),
+                    sample_size,
+                })
+            })
+        }
+        // The rest of ALTER TABLE is left to sqlparser
+        ["ALTER", "TABLE", ..] if words.windows(2).any(|pair| pair == ["SET", "GENERATED"]) => {
+            parse_set_generated(&mut parser)
+        }
+        _ => return None,
+    };
+    Some(statement.and_then(|statement| {
+        let _ = parser.consume_token(&Token::SemiColon);
+        match parser.peek_token() {
+            TokenWithLocation {
+                token: Token::EOF, ..
+            } => Ok(statement),
+            found => parser.expected("end of statement", found),
+        }
+    }))
+}
+
+/// `CREATE MATERIALIZED VIEW name [(columns)] AS query WITH [NO] DATA`
+fn parse_create_materialized_view(parser: &mut Parser) -> Result<ParsedStatement, ParserError> {
+    let statement = parser.parse_statement()?;
+    parser.expect_keyword(Keyword::WITH)?;
+    let with_data = !parser.parse_keyword(Keyword::NO);
+    parser.expect_keyword(Keyword::DATA)?;
+    Ok(ParsedStatement::CreateMaterializedView {
+        statement: Box::new(statement),
+        with_data,
+    })
+}
+
+/// `REFRESH MATERIALIZED VIEW [CONCURRENTLY] name`
+fn parse_refresh_materialized_view(parser: &mut Parser) -> Result<ParsedStatement, ParserError> {
+    parser.next_token();
+    parser.expect_keywords(&[Keyword::MATERIALIZED, Keyword::VIEW])?;
+    // Refreshing never blocks readers here, so CONCURRENTLY changes nothing
+    let _ = parser.parse_keyword(Keyword::CONCURRENTLY);
+    let name = parser.parse_object_name()?;
+    Ok(ParsedStatement::RefreshMaterializedView { name })
+}
+
+/// `DROP MATERIALIZED VIEW [IF EXISTS] name [, ...] [CASCADE | RESTRICT]`
+fn parse_drop_materialized_view(parser: &mut Parser) -> Result<ParsedStatement, ParserError> {
+    parser.expect_keywords(&[Keyword::DROP, Keyword::MATERIALIZED, Keyword::VIEW])?;
+    let if_exists = parser.parse_keywords(&[Keyword::IF, Keyword::EXISTS]);
+    let names = parser.parse_comma_separated(|parser| parser.parse_object_name())?;
+    let cascade = parser.parse_keyword(Keyword::CASCADE);
+    if !cascade {
+        let _ = parser.parse_keyword(Keyword::RESTRICT);
+    }
+    Ok(ParsedStatement::DropMaterializedView {
+        names,
+        if_exists,
+        cascade,
+    })
+}
+
+/// `ATTACH [DATABASE] 'file' AS name`
+fn parse_attach_database(parser: &mut Parser) -> Result<ParsedStatement, ParserError> {
+    parser.next_token();
+    let _ = parser.parse_keyword(Keyword::DATABASE);
+    let file_path = parser.parse_literal_string()?;
+    parser.expect_keyword(Keyword::AS)?;
+    let name = parser.parse_identifier()?;
+    Ok(ParsedStatement::AttachDatabase { file_path, name })
+}
+
+/// `DETACH [DATABASE] name`
+fn parse_detach_database(parser: &mut Parser) -> Result<ParsedStatement, ParserError> {
+    parser.next_token();
+    let _ = parser.parse_keyword(Keyword::DATABASE);
+    let name = parser.parse_identifier()?;
+    Ok(ParsedStatement::DetachDatabase { name })
+}
+
+/// `BACKUP [DATABASE] [name] TO 'file'`
+fn parse_backup_database(parser: &mut Parser) -> Result<ParsedStatement, ParserError> {
+    parser.next_token();
+    let _ = parser.parse_keyword(Keyword::DATABASE);
+    let name = if parser.parse_keyword(Keyword::TO) {
+        None
+    } else {
+        let name = parser.parse_identifier()?;
+        parser.expect_keyword(Keyword::TO)?;
+        Some(name)
+    };
+    let file_path = parser.parse_literal_string()?;
+    Ok(ParsedStatement::BackupDatabase { name, file_path })
+}
+
+/// `ALTER TABLE name ALTER [COLUMN] column SET GENERATED {ALWAYS | BY DEFAULT}`
+fn parse_set_generated(parser: &mut Parser) -> Result<ParsedStatement, ParserError> {
+    parser.expect_keywords(&[Keyword::ALTER, Keyword::TABLE])?;
+    let table_name = parser.parse_object_name()?;
+    parser.expect_keyword(Keyword::ALTER)?;
+    let _ = parser.parse_keyword(Keyword::COLUMN);
+    let column_name = parser.parse_identifier()?;
+    parser.expect_keywords(&[Keyword::SET, Keyword::GENERATED])?;
+    let always = match parser.parse_one_of_keywords(&[Keyword::ALWAYS, Keyword::BY]) {
+        Some(Keyword::ALWAYS) => true,
+        Some(_) => {
+            parser.expect_keyword(Keyword::DEFAULT)?;
+            false
+        }
+        None => return parser.expected("ALWAYS or BY DEFAULT", parser.peek_token()),
+    };
+    Ok(ParsedStatement::SetGenerated {
+        table_name,
+        column_name,
+        always,
+    })
+}
+
+/// `VACUUM [TABLE] [name]`
+fn parse_vacuum(parser: &mut Parser) -> Result<ParsedStatement, ParserError> {
+    parser.next_token();
+    let _ = parser.parse_keyword(Keyword::TABLE);
+    let table_name = match parser.peek_token().token {
+        Token::EOF | Token::SemiColon => None,
+        _ => Some(parser.parse_object_name()?),
+    };
+    Ok(ParsedStatement::Vacuum { table_name })
+}
+
+/// How many records `CREATE_TABLE` infers column types from by default.
+const DEFAULT_SAMPLE_SIZE: usize = 100;
+
+/// Removes the `CREATE_TABLE [records]` option, which the parser does not
+/// know, from the options of a COPY statement, along with a comma that
+/// separates it from the others.
+fn take_create_table_option(
+    mut tokens: Vec<TokenWithLocation>,
+) -> Result<(Vec<TokenWithLocation>, usize), ParserError> {
+    let significant = |tokens: &[TokenWithLocation], from: usize| {
+        (from..tokens.len()).find(|&i| !matches!(tokens[i].token, Token::Whitespace(_)))
+    };
+    let start = tokens
+        .iter()
+        .position(|token| {
+            matches!(&token.token, Token::Word(word)
+                if word.quote_style.is_none() && word.value.eq_ignore_ascii_case("CREATE_TABLE"))
+        })
+        .ok_or_else(|| ParserError::ParserError("Expected CREATE_TABLE".to_string()))?;
+    let mut end = start + 1;
+    let mut sample_size = DEFAULT_SAMPLE_SIZE;
+    if let Some(next) = significant(&tokens, end) {
+        if let Token::Number(number, _) = &tokens[next].token {
+            sample_size = number
+                .parse()
+                .ok()
+                .filter(|&size| size > 0)
+                .ok_or_else(|| {
+                    ParserError::ParserError(format!(
+                        "CREATE_TABLE needs a positive number of records, found {}",
+                        number
+                    ))
+                })?;
+            end = next + 1;
+        }
+    }
+    let previous = (0..start)
+        .rev()
+        .find(|&i| !matches!(tokens[i].token, Token::Whitespace(_)));
+    match (previous, significant(&tokens, end)) {
+        (Some(previous), _) if tokens[previous].token == Token::Comma => {
+            tokens.drain(previous..end);
+        }
+        (_, Some(next)) if tokens[next].token == Token::Comma => {
+            tokens.drain(start..=next);
+        }
+        _ => {
+            tokens.drain(start..end);
+        }
+    }
+    Ok((tokens, sample_size))
+}
+
+/// The parser only accepts the long form `ALTER COLUMN c SET DATA TYPE t`, so
+/// the PostgreSQL shorthand `ALTER COLUMN c TYPE t` gets the missing keywords.
+fn expand_alter_column_type(tokens: Vec<TokenWithLocation>) -> Vec<TokenWithLocation> {
+    let keyword = |token: &Token| match token {
+        Token::Word(word) => Some(word.keyword),
+        _ => None,
+    };
+    let mut expanded: Vec<TokenWithLocation> = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        if keyword(&token.token) == Some(Keyword::TYPE) {
+            let previous: Vec<Option<Keyword>> = expanded
+                .iter()
+                .rev()
+                .filter(|t| !matches!(t.token, Token::Whitespace(_)))
+                .take(3)
+                .map(|t| keyword(&t.token))
+                .collect();
+            let after_column_name = matches!(
+                previous.as_slice(),
+                [Some(_), Some(Keyword::COLUMN), Some(Keyword::ALTER)]
+                    | [Some(_), Some(Keyword::ALTER), _]
+            );
+            if after_column_name {
+                for word in ["SET", "DATA"] {
+                    expanded.push(TokenWithLocation::new(
+                        Token::make_keyword(word),
+                        token.location.line,
+                        token.location.column,
+                    ));
+                }
+            }
+        }
+        expanded.push(token);
+    }
+    expanded
 }
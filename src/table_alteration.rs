@@ -0,0 +1,867 @@
+use super::database::{constraint_name, qualified_name, set_constraint_name, split_name};
+use super::database::{Database, Index, Table};
+use super::expression_evaluation::{evaluate, ColumnRef, Scope};
+use super::query_execution::check_row;
+use super::sequences::{auto_increment_columns, drop_owned_sequences, identity_sequence};
+use super::sequences::{rename_sequence_owner, restart_owned_sequences};
+use super::value::Value;
+use super::views::{dependent_views, remove_view, rename_view_column, rename_view_dependencies};
+use super::views::{view_kind, views_using_column};
+use sqlparser::ast::TableConstraint;
+use sqlparser::ast::{visit_expressions, visit_expressions_mut, AlterColumnOperation};
+use sqlparser::ast::{AlterTableOperation, ColumnDef, Expr, Ident, ObjectName, OrderByExpr};
+use std::ops::ControlFlow;
+
+/// Applies one ALTER TABLE operation. `table_name` is updated by RENAME TO so
+/// that later operations of the same statement find the renamed table.
+pub fn alter_table(
+    database: &mut Database,
+    table_name: &mut String,
+    operation: AlterTableOperation,
+) -> Result<(), String> {
+    match operation {
+        AlterTableOperation::AddColumn {
+            if_not_exists,
+            column_def,
+            ..
+        } => add_column(database, table_name, &column_def, if_not_exists),
+        AlterTableOperation::DropColumn {
+            column_name,
+            if_exists,
+            cascade,
+        } => drop_column(database, table_name, &column_name.value, if_exists, cascade),
+        AlterTableOperation::RenameTable {
+            table_name: new_name,
+        } => rename_table(database, table_name, &new_name),
+        AlterTableOperation::RenameColumn {
+            old_column_name,
+            new_column_name,
+        } => rename_column(database, table_name, &old_column_name, &new_column_name),
+        AlterTableOperation::AlterColumn { column_name, op } => {
+            alter_column(database, table_name, &column_name.value, op)
+        }
+        AlterTableOperation::AddConstraint(constraint) => {
+            add_constraint(database, table_name, constraint)
+        }
+        AlterTableOperation::DropConstraint {
+            if_exists,
+            name,
+            cascade,
+        } => drop_constraint(database, table_name, &name.value, if_exists, cascade),
+        AlterTableOperation::DropPrimaryKey => {
+            let table = &database.tables[table_name.as_str()];
+            let name = table
+                .constraints
+                .iter()
+                .find(|c| {
+                    matches!(
+                        c,
+                        TableConstraint::Unique {
+                            is_primary: true,
+                            ..
+                        }
+                    )
+                })
+                .and_then(constraint_name)
+                .map(|name| name.value.clone())
+                .ok_or_else(|| format!("Table '{}' has no primary key", table_name))?;
+            drop_constraint(database, table_name, &name, false, false)
+        }
+        AlterTableOperation::RenameConstraint { old_name, new_name } => {
+            let mut table = database.tables[table_name.as_str()].clone();
+            let index = table.find_constraint(&old_name.value).ok_or_else(|| {
+                format!(
+                    "Constraint '{}' of table '{}' does not exist",
+                    old_name.value, table_name
+                )
+            })?;
+            if table.find_constraint(&new_name.value).is_some() {
+                return Err(format!(
+                    "Constraint '{}' for table '{}' already exists",
+                    new_name.value, table_name
+                ));
+            }
+            set_constraint_name(&mut table.constraints[index], new_name);
+            database.tables.insert(table_name.clone(), table);
+            Ok(())
+        }
+        other => Err(format!("Unsupported ALTER TABLE operation: {}", other)),
+    }
+}
+
+fn add_column(
+    database: &mut Database,
+    table_name: &str,
+    column_def: &ColumnDef,
+    if_not_exists: bool,
+) -> Result<(), String> {
+    let mut table = database.tables[table_name].clone();
+    let column_name = &column_def.name.value;
+    if table.column_index(column_name).is_some() {
+        if if_not_exists {
+            database.report(format!(
+                "Column '{}' already exists in table '{}', skipping",
+                column_name, table_name
+            ));
+            return Ok(());
+        }
+        return Err(format!(
+            "Column '{}' already exists in table '{}'",
+            column_name, table_name
+        ));
+    }
+    let mut column_defs = [column_def.clone()];
+    for sequence in auto_increment_columns(database, table_name, &mut column_defs)? {
+        database.sequences.insert(sequence.name.clone(), sequence);
+    }
+    table.add_column(&column_defs[0])?;
+    database.resolve_foreign_keys(&mut table)?;
+
+    // Existing rows get the column default, evaluated for each row so that
+    // an auto-increment column numbers them, or NULL without one
+    let column = table.columns.last().unwrap();
+    for row in &mut table.data {
+        let value = match &column.default {
+            Some(default) => {
+                evaluate(default, &Scope::empty(), database)?.cast(&column.data_type)?
+            }
+            None => Value::Null,
+        };
+        row.push(value);
+    }
+    check_rows(&table, database)?;
+    database.tables.insert(table_name.to_string(), table);
+    database.report(format!(
+        "Added column '{}' to table '{}'",
+        column_name, table_name
+    ));
+    Ok(())
+}
+
+fn drop_column(
+    database: &mut Database,
+    table_name: &str,
+    column_name: &str,
+    if_exists: bool,
+    cascade: bool,
+) -> Result<(), String> {
+    let mut table = database.tables[table_name].clone();
+    let Some(index) = table.column_index(column_name) else {
+        if if_exists {
+            database.report(format!(
+                "Column '{}' does not exist in table: {:?}",
+                column_name, table_name
+            ));
+            return Ok(());
+        }
+        return Err(format!(
+            "Column '{}' does not exist in table '{}'",
+            column_name, table_name
+        ));
+    };
+
+    // Foreign keys of other tables that point at this column
+    let dependents: Vec<(String, String)> = database
+        .foreign_keys_referencing(table_name)
+        .into_iter()
+        .filter(|(_, constraint)| referred_columns(constraint).contains(&column_name.to_string()))
+        .filter_map(|(referencing, constraint)| {
+            constraint_name(constraint).map(|name| (referencing.name.clone(), name.value.clone()))
+        })
+        .collect();
+    if let Some((referencing, name)) = dependents.first() {
+        if !cascade {
+            return Err(format!(
+                "Cannot drop column '{}' of table '{}' because constraint '{}' on table '{}' depends on it",
+                column_name, table_name, name, referencing
+            ));
+        }
+    }
+
+    let views = views_using_column(database, table_name, column_name);
+    if let Some(view) = views.first() {
+        if !cascade {
+            return Err(format!(
+                "Cannot drop column '{}' of table '{}' because {} '{}' depends on it",
+                column_name,
+                table_name,
+                view_kind(&database.views[view]),
+                view
+            ));
+        }
+    }
+    for view_name in dependent_views(database, &views).into_iter().chain(views) {
+        remove_view(database, &view_name);
+    }
+
+    // Constraints of the table itself that involve the column go with it
+    table.columns.remove(index);
+    for row in &mut table.data {
+        row.remove(index);
+    }
+    table
+        .constraints
+        .retain(|constraint| !constraint_uses_column(constraint, column_name));
+    table
+        .indexes
+        .retain(|index| !index.columns.iter().any(|col| col == column_name));
+    database.tables.insert(table_name.to_string(), table);
+    drop_constraints(database, &dependents);
+    drop_owned_sequences(database, table_name, Some(column_name));
+    database.report(format!(
+        "Dropped column '{}' from table: {:?}",
+        column_name, table_name
+    ));
+    Ok(())
+}
+
+fn rename_table(
+    database: &mut Database,
+    table_name: &mut String,
+    new_name: &ObjectName,
+) -> Result<(), String> {
+    let new_name = new_name.to_string();
+    if database.tables.contains_key(&new_name) || database.views.contains_key(&new_name) {
+        return Err(format!("Relation '{}' already exists", new_name));
+    }
+    let mut table = database.tables.remove(table_name.as_str()).unwrap();
+    table.name = new_name.clone();
+    for constraint in &mut table.constraints {
+        if let TableConstraint::Check { expr, .. } = constraint {
+            rename_in_expr(expr, |idents| {
+                if let [qualifier, _] = idents {
+                    if qualifier.value == *table_name {
+                        qualifier.value = new_name.clone();
+                    }
+                }
+            });
+        }
+    }
+    database.tables.insert(new_name.clone(), table);
+
+    for table in database.tables.values_mut() {
+        for constraint in &mut table.constraints {
+            if let TableConstraint::ForeignKey { foreign_table, .. } = constraint {
+                if foreign_table.to_string() == *table_name {
+                    *foreign_table = ObjectName(vec![Ident::new(&new_name)]);
+                }
+            }
+        }
+    }
+    rename_view_dependencies(database, table_name, &new_name);
+    rename_sequence_owner(database, table_name, &new_name, None);
+    database.report(format!("Renamed table '{}' to '{}'", table_name, new_name));
+    *table_name = new_name;
+    Ok(())
+}
+
+fn rename_column(
+    database: &mut Database,
+    table_name: &str,
+    old_name: &Ident,
+    new_name: &Ident,
+) -> Result<(), String> {
+    let mut table = database.tables[table_name].clone();
+    let index = table.column_index(&old_name.value).ok_or_else(|| {
+        format!(
+            "Column '{}' does not exist in table '{}'",
+            old_name.value, table_name
+        )
+    })?;
+    if table.column_index(&new_name.value).is_some() {
+        return Err(format!(
+            "Column '{}' already exists in table '{}'",
+            new_name.value, table_name
+        ));
+    }
+    table.columns[index].name = new_name.value.clone();
+    for index in &mut table.indexes {
+        for column in index
+            .columns
+            .iter_mut()
+            .filter(|col| **col == old_name.value)
+        {
+            *column = new_name.value.clone();
+        }
+    }
+    let rename = |idents: &mut Vec<Ident>| {
+        for ident in idents
+            .iter_mut()
+            .filter(|ident| ident.value == old_name.value)
+        {
+            *ident = new_name.clone();
+        }
+    };
+    for constraint in &mut table.constraints {
+        match constraint {
+            TableConstraint::Unique { columns, .. }
+            | TableConstraint::ForeignKey { columns, .. } => rename(columns),
+            TableConstraint::Check { expr, .. } => rename_in_expr(expr, |idents| {
+                let qualified = match idents {
+                    [_] => true,
+                    [qualifier, _] => qualifier.value == table_name,
+                    _ => false,
+                };
+                if qualified && idents.last().unwrap().value == old_name.value {
+                    *idents.last_mut().unwrap() = new_name.clone();
+                }
+            }),
+            _ => {}
+        }
+    }
+    database.tables.insert(table_name.to_string(), table);
+
+    for table in database.tables.values_mut() {
+        for constraint in &mut table.constraints {
+            if let TableConstraint::ForeignKey {
+                foreign_table,
+                referred_columns,
+                ..
+            } = constraint
+            {
+                if foreign_table.to_string() == table_name {
+                    rename(referred_columns);
+                }
+            }
+        }
+    }
+    rename_view_column(database, table_name, &old_name.value, new_name);
+    rename_sequence_owner(
+        database,
+        table_name,
+        table_name,
+        Some((&old_name.value, &new_name.value)),
+    );
+    database.report(format!(
+        "Renamed column '{}' to '{}' in table '{}'",
+        old_name.value, new_name.value, table_name
+    ));
+    Ok(())
+}
+
+fn alter_column(
+    database: &mut Database,
+    table_name: &str,
+    column_name: &str,
+    operation: AlterColumnOperation,
+) -> Result<(), String> {
+    let mut table = database.tables[table_name].clone();
+    let index = table.column_index(column_name).ok_or_else(|| {
+        format!(
+            "Column '{}' does not exist in table '{}'",
+            column_name, table_name
+        )
+    })?;
+    match operation {
+        AlterColumnOperation::SetNotNull => {
+            if table.data.iter().any(|row| row[index].is_null()) {
+                return Err(format!(
+                    "Column '{}' of table '{}' contains null values",
+                    column_name, table_name
+                ));
+            }
+            table.columns[index].not_null = true;
+        }
+        AlterColumnOperation::DropNotNull => {
+            if table.primary_key().unwrap_or_default().contains(&index) {
+                return Err(format!("Column '{}' is in a primary key", column_name));
+            }
+            table.columns[index].not_null = false;
+        }
+        AlterColumnOperation::SetDefault { value } => {
+            // Reject defaults that cannot be stored in the column right away
+            evaluate(&value, &Scope::empty(), database)?.cast(&table.columns[index].data_type)?;
+            table.columns[index].default = Some(value);
+            table.columns[index].auto_increment = false;
+        }
+        AlterColumnOperation::DropDefault => {
+            table.columns[index].default = None;
+            table.columns[index].auto_increment = false;
+        }
+        AlterColumnOperation::SetDataType { data_type, using } => {
+            if let Some(default) = &table.columns[index].default {
+                evaluate(default, &Scope::empty(), database)?
+                    .cast(&data_type)
+                    .map_err(|_| {
+                        format!(
+                            "Default for column '{}' cannot be cast automatically to type {}",
+                            column_name, data_type
+                        )
+                    })?;
+            }
+            let columns = ColumnRef::for_table(table_name, &table.column_names());
+            let mut converted = Vec::with_capacity(table.data.len());
+            for row in &table.data {
+                let value = match &using {
+                    Some(expr) => evaluate(expr, &Scope::new(&columns, row), database)?,
+                    None => row[index].clone(),
+                };
+                converted.push(value.cast(&data_type).map_err(|error| {
+                    format!(
+                        "Column '{}' cannot be converted to {}: {}",
+                        column_name, data_type, error
+                    )
+                })?);
+            }
+            for (row, value) in table.data.iter_mut().zip(converted) {
+                row[index] = value;
+            }
+            table.columns[index].data_type = data_type;
+            check_rows(&table, database)?;
+        }
+    }
+    database.tables.insert(table_name.to_string(), table);
+    database.report(format!(
+        "Altered column '{}' of table '{}'",
+        column_name, table_name
+    ));
+    Ok(())
+}
+
+/// `ALTER COLUMN c SET GENERATED {ALWAYS | BY DEFAULT}`: whether an identity
+/// column also takes values other than those of its sequence.
+pub fn set_generated(
+    database: &mut Database,
+    table_name: &str,
+    column_name: &Ident,
+    always: bool,
+) -> Result<(), String> {
+    let table = database
+        .tables
+        .get(table_name)
+        .ok_or_else(|| format!("Table '{}' does not exist", table_name))?;
+    let index = table.column_index(&column_name.value).ok_or_else(|| {
+        format!(
+            "Column '{}' does not exist in table '{}'",
+            column_name.value, table_name
+        )
+    })?;
+    if identity_sequence(database, table_name, &column_name.value).is_none() {
+        return Err(format!(
+            "Column '{}' of table '{}' is not an identity column",
+            column_name.value, table_name
+        ));
+    }
+    let table = database.tables.get_mut(table_name).unwrap();
+    table.columns[index].generated_always = always;
+    database.report(format!(
+        "Altered column '{}' of table '{}'",
+        column_name.value, table_name
+    ));
+    Ok(())
+}
+
+fn add_constraint(
+    database: &mut Database,
+    table_name: &str,
+    constraint: TableConstraint,
+) -> Result<(), String> {
+    let mut table = database.tables[table_name].clone();
+    table.add_constraint(constraint)?;
+    database.resolve_foreign_keys(&mut table)?;
+    check_rows(&table, database)?;
+    let name = constraint_name(table.constraints.last().unwrap())
+        .unwrap()
+        .value
+        .clone();
+    database.tables.insert(table_name.to_string(), table);
+    database.report(format!(
+        "Added constraint '{}' to table '{}'",
+        name, table_name
+    ));
+    Ok(())
+}
+
+fn drop_constraint(
+    database: &mut Database,
+    table_name: &str,
+    name: &str,
+    if_exists: bool,
+    cascade: bool,
+) -> Result<(), String> {
+    let mut table = database.tables[table_name].clone();
+    let Some(index) = table.find_constraint(name) else {
+        if if_exists {
+            database.report(format!(
+                "Constraint '{}' of table '{}' does not exist, skipping",
+                name, table_name
+            ));
+            return Ok(());
+        }
+        return Err(format!(
+            "Constraint '{}' of table '{}' does not exist",
+            name, table_name
+        ));
+    };
+
+    // Foreign keys that rely on a primary key or unique constraint
+    let mut dependents = Vec::new();
+    if let TableConstraint::Unique { columns, .. } = &table.constraints[index] {
+        let key: Vec<String> = columns.iter().map(|col| col.value.clone()).collect();
+        for (referencing, constraint) in database.foreign_keys_referencing(table_name) {
+            if referred_columns(constraint) == key {
+                let dependent = constraint_name(constraint).unwrap().value.clone();
+                if !cascade {
+                    return Err(format!(
+                        "Cannot drop constraint '{}' on table '{}' because constraint '{}' on table '{}' depends on it",
+                        name, table_name, dependent, referencing.name
+                    ));
+                }
+                dependents.push((referencing.name.clone(), dependent));
+            }
+        }
+    }
+    table.constraints.remove(index);
+    database.tables.insert(table_name.to_string(), table);
+    drop_constraints(database, &dependents);
+    database.report(format!(
+        "Dropped constraint '{}' from table '{}'",
+        name, table_name
+    ));
+    Ok(())
+}
+
+/// Drops every table in `names` at once. Foreign keys of other tables that
+/// reference a dropped table block the drop, or are dropped with CASCADE.
+/// The same goes for views reading from a dropped table. Indexes belong to
+/// their table and always go with it.
+pub fn drop_tables(
+    database: &mut Database,
+    names: &[ObjectName],
+    if_exists: bool,
+    cascade: bool,
+) -> Result<(), String> {
+    let mut dropped: Vec<String> = Vec::new();
+    for name in names {
+        let table_name = name.to_string();
+        if database.is_materialized_view(&table_name) {
+            return Err(format!(
+                "'{}' is a materialized view, use DROP MATERIALIZED VIEW",
+                table_name
+            ));
+        }
+        if database.tables.contains_key(&table_name) {
+            if !dropped.contains(&table_name) {
+                dropped.push(table_name);
+            }
+        } else if if_exists {
+            database.report(format!("Table '{}' does not exist, skipping", table_name));
+        } else {
+            return Err(format!("Table '{}' does not exist", table_name));
+        }
+    }
+
+    let mut dependents = Vec::new();
+    for table_name in &dropped {
+        for (referencing, constraint) in database.foreign_keys_referencing(table_name) {
+            if dropped.contains(&referencing.name) {
+                continue;
+            }
+            let name = constraint_name(constraint).unwrap().value.clone();
+            if !cascade {
+                return Err(format!(
+                    "Cannot drop table '{}' because constraint '{}' on table '{}' depends on it",
+                    table_name, name, referencing.name
+                ));
+            }
+            dependents.push((referencing.name.clone(), name));
+        }
+    }
+    for table_name in &dropped {
+        if let Some(view) = dependent_views(database, std::slice::from_ref(table_name)).first() {
+            if !cascade {
+                return Err(format!(
+                    "Cannot drop table '{}' because {} '{}' depends on it",
+                    table_name,
+                    view_kind(&database.views[view]),
+                    view
+                ));
+            }
+        }
+    }
+    for view_name in dependent_views(database, &dropped) {
+        remove_view(database, &view_name);
+    }
+    drop_constraints(database, &dependents);
+    for table_name in dropped {
+        database.tables.remove(&table_name);
+        drop_owned_sequences(database, &table_name, None);
+        database.report(format!("Dropped table: {}", table_name));
+    }
+    Ok(())
+}
+
+/// Removes every row of a table and restarts its auto-increment counters. A
+/// table referenced by a foreign key of another table cannot be truncated, as
+/// that would orphan the referencing rows.
+pub fn truncate_table(database: &mut Database, name: &ObjectName) -> Result<(), String> {
+    let table_name = name.to_string();
+    if !database.tables.contains_key(&table_name) {
+        return Err(format!("Table '{}' does not exist", table_name));
+    }
+    if database.is_materialized_view(&table_name) {
+        return Err(format!(
+            "Cannot truncate materialized view '{}', use REFRESH MATERIALIZED VIEW",
+            table_name
+        ));
+    }
+    for (referencing, constraint) in database.foreign_keys_referencing(&table_name) {
+        if referencing.name != table_name {
+            return Err(format!(
+                "Cannot truncate table '{}' because constraint '{}' on table '{}' references it",
+                table_name,
+                constraint_name(constraint).unwrap().value,
+                referencing.name
+            ));
+        }
+    }
+    let table = database.tables.get_mut(&table_name).unwrap();
+    table.clear_rows();
+    restart_owned_sequences(database, &table_name);
+    database.report(format!("Truncated table: {}", table_name));
+    Ok(())
+}
+
+pub fn create_index(
+    database: &mut Database,
+    name: Option<&ObjectName>,
+    table_name: &ObjectName,
+    columns: &[OrderByExpr],
+    unique: bool,
+    if_not_exists: bool,
+) -> Result<(), String> {
+    let table_name = table_name.to_string();
+    let mut table = database
+        .tables
+        .get(&table_name)
+        .ok_or_else(|| format!("Table '{}' does not exist", table_name))?
+        .clone();
+    let columns = columns
+        .iter()
+        .map(|column| match &column.expr {
+            Expr::Identifier(ident) => Ok(ident.value.clone()),
+            expr => Err(format!("Unsupported index expression: {}", expr)),
+        })
+        .collect::<Result<Vec<String>, String>>()?;
+    let index_name = match name {
+        Some(name) => name.to_string(),
+        None => {
+            // PostgreSQL-style `table_column_idx` in the schema of the
+            // table, numbered if taken
+            let (schema, table) = split_name(&table_name);
+            let base = format!("{}_{}_idx", table, columns.join("_"));
+            let mut name = qualified_name(&schema, &base);
+            let mut counter = 1;
+            while database.index_table(&name).is_some() {
+                name = qualified_name(&schema, &format!("{}{}", base, counter));
+                counter += 1;
+            }
+            name
+        }
+    };
+    if database.index_table(&index_name).is_some() {
+        if if_not_exists {
+            database.report(format!("Index '{}' already exists, skipping", index_name));
+            return Ok(());
+        }
+        return Err(format!("Index '{}' already exists", index_name));
+    }
+    table.add_index(Index {
+        name: index_name.clone(),
+        columns,
+        unique,
+    })?;
+    check_rows(&table, database)?;
+    database.tables.insert(table_name.clone(), table);
+    database.report(format!(
+        "Created index '{}' on table '{}'",
+        index_name, table_name
+    ));
+    Ok(())
+}
+
+pub fn drop_indexes(
+    database: &mut Database,
+    names: &[ObjectName],
+    if_exists: bool,
+) -> Result<(), String> {
+    let mut dropped = Vec::new();
+    for name in names {
+        let index_name = name.to_string();
+        match database.index_table(&index_name) {
+            Some(table) => dropped.push((table.name.clone(), index_name)),
+            None if if_exists => {
+                database.report(format!("Index '{}' does not exist, skipping", index_name))
+            }
+            None => return Err(format!("Index '{}' does not exist", index_name)),
+        }
+    }
+    for (table_name, index_name) in dropped {
+        let table = database.tables.get_mut(&table_name).unwrap();
+        table.indexes.retain(|index| index.name != index_name);
+        database.report(format!("Dropped index: {}", index_name));
+    }
+    Ok(())
+}
+
+/// Validates every existing row after a schema change.
+pub fn check_rows(table: &Table, database: &Database) -> Result<(), String> {
+    for (index, row) in table.data.iter().enumerate() {
+        check_row(table, row, Some(index), database)?;
+    }
+    Ok(())
+}
+
+/// The referenced column names of a foreign key.
+fn referred_columns(constraint: &TableConstraint) -> Vec<String> {
+    match constraint {
+        TableConstraint::ForeignKey {
+            referred_columns, ..
+        } => referred_columns
+            .iter()
+            .map(|col| col.value.clone())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Removes the named constraints, given as (table, constraint) pairs.
+fn drop_constraints(database: &mut Database, constraints: &[(String, String)]) {
+    for (table_name, name) in constraints {
+        if let Some(table) = database.tables.get_mut(table_name) {
+            if let Some(index) = table.find_constraint(name) {
+                table.constraints.remove(index);
+                database.report(format!(
+                    "Dropped constraint '{}' on table '{}'",
+                    name, table_name
+                ));
+            }
+        }
+    }
+}
+
+fn constraint_uses_column(constraint: &TableConstraint, column_name: &str) -> bool {
+    match constraint {
+        TableConstraint::Unique { columns, .. } | TableConstraint::ForeignKey { columns, .. } => {
+            columns.iter().any(|col| col.value == column_name)
+        }
+        TableConstraint::Check { expr, .. } => visit_expressions(expr, |expr| {
+            let name = match expr {
+                Expr::Identifier(ident) => Some(ident),
+                Expr::CompoundIdentifier(idents) => idents.last(),
+                _ => None,
+            };
+            match name {
+                Some(ident) if ident.value == column_name => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        })
+        .is_break(),
+        _ => false,
+    }
+}
+
+/// Calls `rename` with the identifier parts of every column reference in `expr`.
+fn rename_in_expr(expr: &mut Expr, mut rename: impl FnMut(&mut [Ident])) {
+    let _ = visit_expressions_mut(expr, |expr| {
+        match expr {
+            Expr::Identifier(ident) => rename(std::slice::from_mut(ident)),
+            Expr::CompoundIdentifier(idents) => rename(idents),
+            _ => {}
+        }
+        ControlFlow::<()>::Continue(())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query_execution::StatementOutput;
+    use crate::sql_parsing::parse_statements;
+    use crate::transactions::Session;
+    use crate::value::Value;
+
+    fn run(database: &mut Database, sql: &str) -> Result<Vec<StatementOutput>, String> {
+        Session::default().execute(database, parse_statements(sql)?)
+    }
+
+    #[test]
+    fn dropping_the_last_column_leaves_a_table_that_can_be_reopened() {
+        let path = std::env::temp_dir().join(format!("dbms-{}-last-column.db", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let mut database = Database::new(&path);
+        run(
+            &mut database,
+            "CREATE TABLE t (id INT); INSERT INTO t VALUES (1), (2)",
+        )
+        .unwrap();
+        run(&mut database, "ALTER TABLE t DROP COLUMN id").unwrap();
+        database.save().unwrap();
+        let database = Database::open(&path).unwrap();
+        assert!(database.tables["t"].columns.is_empty());
+        assert_eq!(database.tables["t"].data.len(), 2);
+    }
+
+    fn rows(database: &mut Database, sql: &str) -> Vec<Vec<Value>> {
+        match run(database, sql).unwrap().pop() {
+            Some(StatementOutput::Rows(result)) => result.rows,
+            output => panic!("Expected rows, got {:?}", output),
+        }
+    }
+
+    #[test]
+    fn views_follow_renamed_columns() {
+        let mut database = Database::new("test.db");
+        run(
+            &mut database,
+            "CREATE TABLE t (a INT, b INT); INSERT INTO t VALUES (1, 2);
+            CREATE TABLE u (a INT); INSERT INTO u VALUES (1);
+            CREATE VIEW v AS SELECT a, t.b, a + 1 AS n FROM t WHERE a > 0;
+            CREATE VIEW w AS SELECT x.a FROM t AS x JOIN u ON x.a = u.a;
+            CREATE VIEW v2 AS SELECT a FROM v",
+        )
+        .unwrap();
+        run(&mut database, "ALTER TABLE t RENAME COLUMN a TO aa").unwrap();
+        let expected = vec![vec![
+            Value::Integer(1),
+            Value::Integer(2),
+            Value::Integer(2),
+        ]];
+        assert_eq!(rows(&mut database, "SELECT a, b, n FROM v"), expected);
+        assert_eq!(
+            rows(&mut database, "SELECT a FROM w"),
+            vec![vec![Value::Integer(1)]]
+        );
+        assert_eq!(
+            rows(&mut database, "SELECT a FROM v2"),
+            vec![vec![Value::Integer(1)]]
+        );
+        // The column of the other table keeps its name
+        assert_eq!(
+            database.views["w"].query.to_string(),
+            "SELECT x.aa AS a FROM t AS x JOIN u ON x.aa = u.a"
+        );
+    }
+
+    #[test]
+    fn views_keep_the_columns_they_use() {
+        let mut database = Database::new("test.db");
+        run(
+            &mut database,
+            "CREATE TABLE t (a INT, b INT);
+            CREATE VIEW v AS SELECT b FROM t WHERE a > 0;
+            CREATE VIEW v2 AS SELECT b FROM v;
+            CREATE VIEW s AS SELECT * FROM t",
+        )
+        .unwrap();
+        let err = run(&mut database, "ALTER TABLE t DROP COLUMN a").unwrap_err();
+        assert_eq!(
+            err,
+            "Cannot drop column 'a' of table 't' because view 'v' depends on it"
+        );
+        run(&mut database, "ALTER TABLE t DROP COLUMN a CASCADE").unwrap();
+        let views: Vec<&String> = database.views.keys().collect();
+        assert_eq!(views, vec!["s"]);
+        assert_eq!(database.tables["t"].columns.len(), 1);
+    }
+}
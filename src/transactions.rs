@@ -0,0 +1,1102 @@
+use super::database::{Database, Table, DEFAULT_SCHEMA};
+use super::information_schema::is_system_table;
+use super::query_execution::StatementOutput;
+use super::query_execution::{check_referencing_rows, check_row, execute_statement};
+use super::schemas::resolve_name;
+use super::sql_parsing::ParsedStatement;
+use super::value::Value;
+use sqlparser::ast::{visit_expressions, visit_relations, CopySource, Expr, ObjectName, Statement};
+use sqlparser::ast::{TableConstraint, TransactionAccessMode, TransactionIsolationLevel};
+use sqlparser::ast::{TransactionMode, Visit};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::ControlFlow;
+
+/// Identifies a transaction. Ids are handed out in the order transactions
+/// start, from 1.
+pub type TransactionId = u64;
+
+/// Stands for the transactions that every running transaction sees, which
+/// wrote the rows that are not versioned.
+pub const FROZEN: TransactionId = 0;
+
+/// The transactions that created and deleted one row of a table. A row is
+/// changed by deleting it and creating its new version.
+#[derive(Debug, Clone, Copy)]
+pub struct RowVersion {
+    pub created_by: TransactionId,
+    pub deleted_by: Option<TransactionId>,
+}
+
+/// Where a row of a statement's copy of a table comes from, given by the
+/// index in `data` of the stored row.
+#[derive(Debug, Clone, Copy)]
+pub enum RowOrigin {
+    /// The stored row, unchanged.
+    Stored(usize),
+    /// A new version of the stored row.
+    Updated(usize),
+    /// A row the statement created.
+    Created,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IsolationLevel {
+    /// Each statement sees the rows committed before it started.
+    ReadCommitted,
+    /// Every statement sees the rows committed before the first one started.
+    RepeatableRead,
+}
+
+/// The transactions of a database that have not ended yet. A transaction
+/// that rolls back takes its row versions with it, so any other transaction
+/// that is not running has committed.
+#[derive(Debug, Clone)]
+pub struct Transactions {
+    next_id: TransactionId,
+    /// Each running transaction with the oldest transaction that may have
+    /// been running when it started.
+    running: BTreeMap<TransactionId, TransactionId>,
+}
+
+impl Default for Transactions {
+    fn default() -> Self {
+        Transactions {
+            next_id: 1,
+            running: BTreeMap::new(),
+        }
+    }
+}
+
+impl Transactions {
+    fn begin(&mut self) -> TransactionId {
+        let id = self.next_id;
+        self.next_id += 1;
+        let oldest = self.running.keys().next().copied().unwrap_or(id);
+        self.running.insert(id, oldest);
+        id
+    }
+
+    fn end(&mut self, id: TransactionId) {
+        self.running.remove(&id);
+    }
+
+    pub fn is_running(&self, id: TransactionId) -> bool {
+        self.running.contains_key(&id)
+    }
+
+    /// What transaction `own` sees of the others at this moment: everything
+    /// committed so far. `FROZEN` gets the committed rows alone.
+    pub fn snapshot(&self, own: TransactionId) -> Snapshot {
+        Snapshot {
+            own,
+            next_id: self.next_id,
+            running: self
+                .running
+                .keys()
+                .copied()
+                .filter(|&id| id != own)
+                .collect(),
+        }
+    }
+
+    /// Transactions before this one have committed and are seen by every
+    /// snapshot that is still in use, so the rows they deleted are dead.
+    fn horizon(&self) -> TransactionId {
+        self.running.values().copied().min().unwrap_or(self.next_id)
+    }
+}
+
+/// The transactions whose writes a statement sees.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    own: TransactionId,
+    next_id: TransactionId,
+    running: Vec<TransactionId>,
+}
+
+impl Snapshot {
+    fn sees(&self, id: TransactionId) -> bool {
+        id == self.own || (id < self.next_id && !self.running.contains(&id))
+    }
+
+    pub fn is_visible(&self, version: &RowVersion) -> bool {
+        self.sees(version.created_by) && !version.deleted_by.is_some_and(|id| self.sees(id))
+    }
+}
+
+/// The transaction of a session, started by BEGIN.
+struct Transaction {
+    id: TransactionId,
+    isolation: IsolationLevel,
+    /// Taken by the first statement. REPEATABLE READ keeps using it, and rows
+    /// it does not see were committed by concurrent transactions.
+    snapshot: Option<Snapshot>,
+    /// Tables in which the transaction created or deleted rows.
+    written: BTreeSet<String>,
+    /// The values of the sequences that TRUNCATE restarted, from before the
+    /// first restart, which a rollback puts back.
+    restarted: BTreeMap<String, Option<i64>>,
+}
+
+/// A connection to a database, with the transaction it has open. Outside of
+/// BEGIN ... COMMIT each statement is a transaction of its own.
+///
+/// Queries and changes to rows run against a copy of the database holding
+/// the rows the transaction sees, and what they change is then written back
+/// as row versions. Other statements change the database directly, so they
+/// are only run while no transaction is, except for BACKUP, which writes what
+/// is committed.
+pub struct Session {
+    transaction: Option<Transaction>,
+    /// The isolation level of transactions that do not set one.
+    isolation: IsolationLevel,
+    /// The search path set by this session, which statements run with.
+    search_path: Vec<String>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            transaction: None,
+            isolation: IsolationLevel::ReadCommitted,
+            search_path: vec![DEFAULT_SCHEMA.to_string()],
+        }
+    }
+}
+
+impl Session {
+    pub fn execute(
+        &mut self,
+        database: &mut Database,
+        ast: Vec<ParsedStatement>,
+    ) -> Result<Vec<StatementOutput>, String> {
+        let mut output = Vec::new();
+        for statement in ast {
+            let statement = match statement {
+                ParsedStatement::Sql(statement) => match *statement {
+                    Statement::StartTransaction { modes, .. } => {
+                        output.push(StatementOutput::Message(self.begin(database, &modes)?));
+                        continue;
+                    }
+                    Statement::Commit { .. } => {
+                        output.push(StatementOutput::Message(self.commit(database)?));
+                        continue;
+                    }
+                    Statement::Rollback { .. } => {
+                        output.push(StatementOutput::Message(self.rollback(database)));
+                        continue;
+                    }
+                    Statement::SetTransaction { modes, session, .. } => {
+                        self.set_isolation(&modes, session)?;
+                        continue;
+                    }
+                    statement if changes_rows_only(&statement) => {
+                        output.extend(self.run_in_transaction(database, statement)?);
+                        continue;
+                    }
+                    statement => ParsedStatement::Sql(Box::new(statement)),
+                },
+                statement => statement,
+            };
+            // BACKUP writes the committed rows alone, whatever transactions
+            // are open
+            let backup = matches!(statement, ParsedStatement::BackupDatabase { .. });
+            if self.transaction.is_some() && !backup {
+                return Err(
+                    "Only queries and changes to rows can run inside a transaction block"
+                        .to_string(),
+                );
+            }
+            if !backup && !matches!(statement, ParsedStatement::Vacuum { .. }) {
+                if !database.transactions.running.is_empty() {
+                    return Err(
+                        "Only queries and changes to rows can run while other transactions are open"
+                            .to_string(),
+                    );
+                }
+                // With no transaction left to see them, old row versions go
+                vacuum_tables(database, None);
+            }
+            database.search_path = self.search_path.clone();
+            let result = execute_statement(database, statement);
+            self.search_path = database.search_path.clone();
+            output.extend(result?);
+        }
+        Ok(output)
+    }
+
+    /// The copy of the database that a statement which `reads_only` runs
+    /// against. Making it is all such a statement needs the database for.
+    pub fn read_view(&mut self, database: &Database, statement: &ParsedStatement) -> Database {
+        let snapshot = match &mut self.transaction {
+            Some(transaction) => statement_snapshot(database, transaction),
+            None => database.transactions.snapshot(FROZEN),
+        };
+        let tables = match statement {
+            ParsedStatement::Sql(statement) => statement_tables(database, statement),
+            _ => None,
+        };
+        let mut view = snapshot_database(database, &snapshot, tables.as_ref()).0;
+        view.search_path = self.search_path.clone();
+        view
+    }
+
+    /// The database as this session sees it, for reading outside of statements.
+    pub fn snapshot(&self, database: &Database) -> Database {
+        let snapshot = match &self.transaction {
+            Some(Transaction {
+                snapshot: Some(snapshot),
+                isolation: IsolationLevel::RepeatableRead,
+                ..
+            }) => snapshot.clone(),
+            Some(transaction) => database.transactions.snapshot(transaction.id),
+            None => database.transactions.snapshot(FROZEN),
+        };
+        let mut view = snapshot_database(database, &snapshot, None).0;
+        view.search_path = self.search_path.clone();
+        view
+    }
+
+    /// Rolls back a transaction left open when the session ends, returning
+    /// whether there was one.
+    pub fn close(&mut self, database: &mut Database) -> bool {
+        let Some(transaction) = self.transaction.take() else {
+            return false;
+        };
+        roll_back_transaction(database, &transaction);
+        true
+    }
+
+    fn begin(
+        &mut self,
+        database: &mut Database,
+        modes: &[TransactionMode],
+    ) -> Result<String, String> {
+        if self.transaction.is_some() {
+            return Err("A transaction is already in progress".to_string());
+        }
+        let isolation = isolation_level(modes)?.unwrap_or(self.isolation);
+        self.transaction = Some(Transaction {
+            id: database.transactions.begin(),
+            isolation,
+            snapshot: None,
+            written: BTreeSet::new(),
+            restarted: BTreeMap::new(),
+        });
+        Ok("Started a transaction".to_string())
+    }
+
+    fn commit(&mut self, database: &mut Database) -> Result<String, String> {
+        let Some(transaction) = self.transaction.take() else {
+            return Ok("No transaction is in progress".to_string());
+        };
+        commit_transaction(database, &transaction)?;
+        Ok("Committed the transaction".to_string())
+    }
+
+    fn rollback(&mut self, database: &mut Database) -> String {
+        let Some(transaction) = self.transaction.take() else {
+            return "No transaction is in progress".to_string();
+        };
+        roll_back_transaction(database, &transaction);
+        "Rolled back the transaction".to_string()
+    }
+
+    /// `SET TRANSACTION ISOLATION LEVEL ...` for the open transaction, or
+    /// `SET SESSION CHARACTERISTICS AS TRANSACTION ...` for later ones.
+    fn set_isolation(&mut self, modes: &[TransactionMode], session: bool) -> Result<(), String> {
+        let Some(isolation) = isolation_level(modes)? else {
+            return Ok(());
+        };
+        if session {
+            self.isolation = isolation;
+            return Ok(());
+        }
+        match &mut self.transaction {
+            Some(transaction) if transaction.snapshot.is_none() => {
+                transaction.isolation = isolation;
+                Ok(())
+            }
+            Some(_) => Err(
+                "SET TRANSACTION ISOLATION LEVEL must come before any query of the transaction"
+                    .to_string(),
+            ),
+            None => Err("SET TRANSACTION can only be used inside a transaction block".to_string()),
+        }
+    }
+
+    /// Runs a statement in the open transaction, or in one of its own that
+    /// commits when it succeeds.
+    fn run_in_transaction(
+        &mut self,
+        database: &mut Database,
+        statement: Statement,
+    ) -> Result<Vec<StatementOutput>, String> {
+        if let Some(transaction) = &mut self.transaction {
+            // A failing statement leaves the transaction as it was
+            return run_statement(database, transaction, &mut self.search_path, statement);
+        }
+        let mut transaction = Transaction {
+            id: database.transactions.begin(),
+            isolation: self.isolation,
+            snapshot: None,
+            written: BTreeSet::new(),
+            restarted: BTreeMap::new(),
+        };
+        match run_statement(database, &mut transaction, &mut self.search_path, statement) {
+            Ok(results) => {
+                commit_transaction(database, &transaction)?;
+                Ok(results)
+            }
+            Err(err) => {
+                roll_back_transaction(database, &transaction);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Statements that read the tables or change their rows but not the schema,
+/// which is what a transaction may do.
+fn changes_rows_only(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::Query(_)
+            | Statement::Insert { .. }
+            | Statement::Update { .. }
+            | Statement::Delete { .. }
+            | Statement::Truncate { .. }
+            | Statement::Copy { .. }
+            | Statement::ShowTables { .. }
+            | Statement::ShowColumns { .. }
+            | Statement::ShowCreate { .. }
+            | Statement::ExplainTable { .. }
+            | Statement::ShowVariable { .. }
+            | Statement::SetVariable { .. }
+    )
+}
+
+fn isolation_level(modes: &[TransactionMode]) -> Result<Option<IsolationLevel>, String> {
+    let mut isolation = None;
+    for mode in modes {
+        match mode {
+            // As in PostgreSQL, READ UNCOMMITTED behaves as READ COMMITTED
+            TransactionMode::IsolationLevel(
+                TransactionIsolationLevel::ReadUncommitted
+                | TransactionIsolationLevel::ReadCommitted,
+            ) => isolation = Some(IsolationLevel::ReadCommitted),
+            TransactionMode::IsolationLevel(TransactionIsolationLevel::RepeatableRead) => {
+                isolation = Some(IsolationLevel::RepeatableRead)
+            }
+            TransactionMode::IsolationLevel(TransactionIsolationLevel::Serializable) => {
+                return Err(
+                    "SERIALIZABLE isolation is not supported, use REPEATABLE READ".to_string(),
+                )
+            }
+            TransactionMode::AccessMode(TransactionAccessMode::ReadWrite) => {}
+            TransactionMode::AccessMode(TransactionAccessMode::ReadOnly) => {
+                return Err("Read-only transactions are not supported".to_string())
+            }
+        }
+    }
+    Ok(isolation)
+}
+
+/// Whether a statement only reads the database, so that it can run while
+/// others do. Calls to `nextval` and `setval` advance sequences, including
+/// those in the views the statement reads from.
+pub fn reads_only(database: &Database, statement: &ParsedStatement) -> bool {
+    let ParsedStatement::Sql(statement) = statement else {
+        return false;
+    };
+    if !matches!(
+        statement.as_ref(),
+        Statement::Query(_)
+            | Statement::ShowTables { .. }
+            | Statement::ShowColumns { .. }
+            | Statement::ShowCreate { .. }
+            | Statement::ExplainTable { .. }
+            | Statement::ShowVariable { .. }
+    ) || changes_sequences(statement)
+    {
+        return false;
+    }
+    let mut names = relation_names(statement);
+    let mut views = BTreeSet::new();
+    while let Some(name) = names.pop() {
+        let name = unqualified(&name);
+        for (key, view) in &database.views {
+            if unqualified(key) == name && views.insert(key.clone()) {
+                if changes_sequences(&view.query) {
+                    return false;
+                }
+                names.extend(view.dependencies());
+            }
+        }
+    }
+    true
+}
+
+fn changes_sequences<V: Visit>(node: &V) -> bool {
+    visit_expressions(node, |expr| match expr {
+        Expr::Function(function)
+            if ["nextval", "setval"].iter().any(|name| {
+                function
+                    .name
+                    .0
+                    .last()
+                    .is_some_and(|ident| ident.value.eq_ignore_ascii_case(name))
+            }) =>
+        {
+            ControlFlow::Break(())
+        }
+        _ => ControlFlow::Continue(()),
+    })
+    .is_break()
+}
+
+/// The names of the tables and views that `node` reads or writes.
+fn relation_names<V: Visit>(node: &V) -> Vec<String> {
+    let mut names = Vec::new();
+    let _ = visit_relations(node, |name| {
+        names.push(name.to_string());
+        ControlFlow::<()>::Continue(())
+    });
+    names
+}
+
+/// The last part of a name, which is all that names are compared by when
+/// looking for the objects a statement may use, so that none is missed.
+fn unqualified(name: &str) -> String {
+    let name = name.rsplit('.').next().unwrap_or(name);
+    name.trim_matches(|c| c == '"' || c == '`').to_lowercase()
+}
+
+/// The snapshot the next statement of a transaction runs with.
+fn statement_snapshot(database: &Database, transaction: &mut Transaction) -> Snapshot {
+    let first = transaction
+        .snapshot
+        .get_or_insert_with(|| database.transactions.snapshot(transaction.id));
+    match transaction.isolation {
+        IsolationLevel::RepeatableRead => first.clone(),
+        IsolationLevel::ReadCommitted => database.transactions.snapshot(transaction.id),
+    }
+}
+
+fn run_statement(
+    database: &mut Database,
+    transaction: &mut Transaction,
+    search_path: &mut Vec<String>,
+    statement: Statement,
+) -> Result<Vec<StatementOutput>, String> {
+    let snapshot = statement_snapshot(database, transaction);
+    let tables = statement_tables(database, &statement);
+    let (mut view, visible) = snapshot_database(database, &snapshot, tables.as_ref());
+    let restarts: Vec<(String, Option<i64>)> = match statement {
+        Statement::Truncate { .. } => database
+            .sequences
+            .iter()
+            .map(|(key, sequence)| (key.clone(), sequence.last_value.get()))
+            .collect(),
+        _ => vec![],
+    };
+    view.search_path = search_path.clone();
+    let results = execute_statement(&mut view, ParsedStatement::Sql(Box::new(statement)))?;
+    *search_path = std::mem::take(&mut view.search_path);
+    write_back(database, view, &visible, transaction)?;
+    for (key, last_value) in restarts {
+        if database.sequences[&key].last_value.get() != last_value {
+            transaction.restarted.entry(key).or_insert(last_value);
+        }
+    }
+    Ok(results)
+}
+
+/// The tables a statement may read or change: those it names, the tables
+/// behind the views it names and those that the constraints of the tables
+/// read. `None` stands for every table, for the statements that read the
+/// catalog.
+fn statement_tables(database: &Database, statement: &Statement) -> Option<BTreeSet<String>> {
+    let mut names = relation_names(statement);
+    match statement {
+        Statement::Query(_) | Statement::Update { .. } | Statement::Delete { .. } => {}
+        Statement::Insert { table_name, .. }
+        | Statement::Copy {
+            source: CopySource::Table { table_name, .. },
+            ..
+        } => names.push(table_name.to_string()),
+        Statement::Copy { .. } => {}
+        _ => return None,
+    }
+    related_tables(database, names)
+}
+
+/// The tables that may be meant by `names`, and those that the views and the
+/// CHECK constraints among them read, or that are linked to them by foreign
+/// keys in either direction.
+fn related_tables(database: &Database, mut names: Vec<String>) -> Option<BTreeSet<String>> {
+    let mut tables = BTreeSet::new();
+    let mut views = BTreeSet::new();
+    while let Some(name) = names.pop() {
+        let name = unqualified(&name);
+        if is_system_table(&format!("information_schema.{}", name)) {
+            return None;
+        }
+        for (key, table) in &database.tables {
+            if unqualified(key) == name && tables.insert(key.clone()) {
+                for constraint in &table.constraints {
+                    match constraint {
+                        TableConstraint::Check { expr, .. } => names.extend(relation_names(expr)),
+                        TableConstraint::ForeignKey { foreign_table, .. } => {
+                            names.push(foreign_table.to_string())
+                        }
+                        _ => {}
+                    }
+                }
+                names.extend(
+                    database
+                        .foreign_keys_referencing(key)
+                        .into_iter()
+                        .map(|(referencing, _)| referencing.name.clone()),
+                );
+            }
+        }
+        for (key, view) in &database.views {
+            if unqualified(key) == name && views.insert(key.clone()) {
+                names.extend(view.dependencies());
+            }
+        }
+    }
+    Some(tables)
+}
+
+/// A copy of the database holding the rows `snapshot` sees of the given
+/// tables, or of every table, with the index in `data` of each of them by
+/// table.
+fn snapshot_database(
+    database: &Database,
+    snapshot: &Snapshot,
+    tables: Option<&BTreeSet<String>>,
+) -> (Database, BTreeMap<String, Vec<usize>>) {
+    let mut view =
+        database.clone_with_tables(|key| tables.is_none_or(|tables| tables.contains(key)));
+    let mut visible = BTreeMap::new();
+    for (key, table) in &mut view.tables {
+        let indexes: Vec<usize> = (0..table.data.len())
+            .filter(|&index| {
+                table
+                    .versions
+                    .get(index)
+                    .is_none_or(|version| snapshot.is_visible(version))
+            })
+            .collect();
+        if indexes.len() < table.data.len() {
+            let mut data = std::mem::take(&mut table.data);
+            table.data = indexes
+                .iter()
+                .map(|&index| std::mem::take(&mut data[index]))
+                .collect();
+        }
+        table.versions.clear();
+        table.origins = Some(
+            indexes
+                .iter()
+                .map(|&index| RowOrigin::Stored(index))
+                .collect(),
+        );
+        visible.insert(key.clone(), indexes);
+    }
+    (view, visible)
+}
+
+/// What a statement did to the rows of a table.
+struct RowChanges {
+    /// Indexes in `data` of the rows it deleted or replaced.
+    deleted: Vec<usize>,
+    /// The rows it created, each placed after the row it replaces if any.
+    created: Vec<(Option<usize>, Vec<Value>)>,
+}
+
+/// Records the rows a statement changed in its copy of the database as
+/// versions written by the transaction. Nothing is written when one of
+/// those rows was changed by another transaction the statement did not see.
+fn write_back(
+    database: &mut Database,
+    view: Database,
+    visible: &BTreeMap<String, Vec<usize>>,
+    transaction: &mut Transaction,
+) -> Result<(), String> {
+    let mut changes = Vec::new();
+    let mut new_tables = Vec::new();
+    for (key, mut table) in view.tables {
+        let Some(stored) = database.tables.get(&key) else {
+            // COPY created the table
+            let created = std::mem::take(&mut table.data);
+            let created = created.into_iter().map(|row| (None, row)).collect();
+            changes.push((
+                key,
+                RowChanges {
+                    deleted: vec![],
+                    created,
+                },
+            ));
+            new_tables.push(table);
+            continue;
+        };
+        let changed = row_changes(stored, &visible[&key], table);
+        for &index in &changed.deleted {
+            if let Some(other) = stored.versions.get(index).and_then(|v| v.deleted_by) {
+                return Err(if database.transactions.is_running(other) {
+                    format!(
+                        "Could not change a row of table '{}' that another transaction is changing",
+                        key
+                    )
+                } else {
+                    format!(
+                        "Could not serialize access to table '{}' due to a concurrent update",
+                        key
+                    )
+                });
+            }
+        }
+        if !changed.deleted.is_empty() || !changed.created.is_empty() {
+            changes.push((key, changed));
+        }
+    }
+
+    // Sequences are not transactional, except for the restarts of TRUNCATE
+    for (key, sequence) in &view.sequences {
+        if let Some(stored) = database.sequences.get(key) {
+            stored.last_value.set(sequence.last_value.get());
+        }
+    }
+    for table in new_tables {
+        database.tables.insert(table.name.clone(), table);
+    }
+    for (key, changed) in changes {
+        let table = database.tables.get_mut(&key).unwrap();
+        if table.versions.is_empty() {
+            table.versions = vec![
+                RowVersion {
+                    created_by: FROZEN,
+                    deleted_by: None,
+                };
+                table.data.len()
+            ];
+        }
+        for &index in &changed.deleted {
+            table.versions[index].deleted_by = Some(transaction.id);
+        }
+        let version = RowVersion {
+            created_by: transaction.id,
+            deleted_by: None,
+        };
+        let (replacements, appended): (Vec<_>, Vec<_>) = changed
+            .created
+            .into_iter()
+            .partition(|(after, _)| after.is_some());
+        if !replacements.is_empty() {
+            // New versions follow the rows they replace, so that the order
+            // of the rows stays the same
+            let mut replacements = replacements.into_iter().peekable();
+            let data = std::mem::take(&mut table.data);
+            let versions = std::mem::take(&mut table.versions);
+            for (index, (row, old)) in data.into_iter().zip(versions).enumerate() {
+                table.data.push(row);
+                table.versions.push(old);
+                while let Some((_, row)) = replacements.next_if(|(after, _)| *after == Some(index))
+                {
+                    table.data.push(row);
+                    table.versions.push(version);
+                }
+            }
+        }
+        for (_, row) in appended {
+            table.data.push(row);
+            table.versions.push(version);
+        }
+        transaction.written.insert(key);
+    }
+    Ok(())
+}
+
+/// The rows a statement deleted, updated and created in its copy of a table,
+/// told apart by where each row of the copy comes from.
+fn row_changes(stored: &Table, visible: &[usize], table: Table) -> RowChanges {
+    let origins = table.origins.unwrap_or_default();
+    let mut remaining = vec![false; stored.data.len()];
+    for &index in visible {
+        remaining[index] = true;
+    }
+    let mut deleted = Vec::new();
+    let mut created = Vec::new();
+    for (row, origin) in table.data.into_iter().zip(origins) {
+        match origin {
+            RowOrigin::Stored(index) => remaining[index] = false,
+            RowOrigin::Updated(index) => {
+                remaining[index] = false;
+                deleted.push(index);
+                created.push((Some(index), row));
+            }
+            RowOrigin::Created => created.push((None, row)),
+        }
+    }
+    // The stored rows that are gone were deleted
+    deleted.extend(visible.iter().filter(|&&index| remaining[index]));
+    deleted.sort_unstable();
+    created.sort_by_key(|(after, _)| *after);
+    RowChanges { deleted, created }
+}
+
+/// Commits a transaction, then vacuums the tables it wrote. The commit fails,
+/// rolling the transaction back, when a row it created conflicts with a row
+/// that a concurrent transaction committed first.
+fn commit_transaction(database: &mut Database, transaction: &Transaction) -> Result<(), String> {
+    if let Err(err) = check_concurrent_rows(database, transaction) {
+        roll_back_transaction(database, transaction);
+        return Err(format!("{}, the transaction is rolled back", err));
+    }
+    database.transactions.end(transaction.id);
+    vacuum_tables(database, Some(&transaction.written));
+    Ok(())
+}
+
+/// Takes out the rows a transaction created and brings back those it deleted,
+/// along with the sequences it restarted.
+fn roll_back_transaction(database: &mut Database, transaction: &Transaction) {
+    for (key, last_value) in &transaction.restarted {
+        if let Some(sequence) = database.sequences.get(key) {
+            sequence.last_value.set(*last_value);
+        }
+    }
+    for key in &transaction.written {
+        if let Some(table) = database.tables.get_mut(key) {
+            let id = transaction.id;
+            let mut created = table
+                .versions
+                .iter()
+                .map(|version| version.created_by == id);
+            table.data.retain(|_| !created.next().unwrap_or(false));
+            table.versions.retain(|version| version.created_by != id);
+            for version in &mut table.versions {
+                if version.deleted_by == Some(id) {
+                    version.deleted_by = None;
+                }
+            }
+        }
+    }
+    database.transactions.end(transaction.id);
+    vacuum_tables(database, Some(&transaction.written));
+}
+
+/// Checks the constraints of the rows the transaction created, and that no
+/// foreign key refers to the rows it deleted, against the rows concurrent
+/// transactions committed. Only the tables are checked whose rows, or those
+/// of the tables their constraints link them to, such transactions changed.
+fn check_concurrent_rows(database: &Database, transaction: &Transaction) -> Result<(), String> {
+    let Some(first) = &transaction.snapshot else {
+        return Ok(());
+    };
+    let snapshot = database.transactions.snapshot(transaction.id);
+    // Committed, but after the transaction started
+    let concurrent =
+        |id: TransactionId| id != transaction.id && snapshot.sees(id) && !first.sees(id);
+    for key in &transaction.written {
+        let Some(table) = database.tables.get(key) else {
+            continue;
+        };
+        let tables = related_tables(database, vec![key.clone()]);
+        let changed = database
+            .tables
+            .iter()
+            .filter(|(name, _)| tables.as_ref().is_none_or(|tables| tables.contains(*name)))
+            .any(|(_, table)| {
+                table.versions.iter().any(|version| {
+                    concurrent(version.created_by) || version.deleted_by.is_some_and(concurrent)
+                })
+            });
+        if !changed {
+            continue;
+        }
+        let view = snapshot_database(database, &snapshot, tables.as_ref()).0;
+        let checked = &view.tables[key];
+        let created = table
+            .versions
+            .iter()
+            .filter(|version| snapshot.is_visible(version))
+            .enumerate()
+            .filter(|(_, version)| version.created_by == transaction.id);
+        for (index, _) in created {
+            check_row(checked, &checked.data[index], Some(index), &view)?;
+        }
+        let deleted: Vec<Vec<Value>> = table
+            .data
+            .iter()
+            .zip(&table.versions)
+            .filter(|(_, version)| version.deleted_by == Some(transaction.id))
+            .map(|(row, _)| row.clone())
+            .collect();
+        check_referencing_rows(checked, &deleted, &view)?;
+    }
+    Ok(())
+}
+
+/// Removes the row versions that no transaction can see any more from the
+/// given tables, or from every table. Returns how many went.
+fn vacuum_tables(database: &mut Database, tables: Option<&BTreeSet<String>>) -> usize {
+    let horizon = database.transactions.horizon();
+    let mut removed = 0;
+    for (key, table) in &mut database.tables {
+        if tables.is_none_or(|tables| tables.contains(key)) {
+            removed += vacuum_table(table, horizon);
+        }
+    }
+    removed
+}
+
+fn vacuum_table(table: &mut Table, horizon: TransactionId) -> usize {
+    if table.versions.is_empty() {
+        return 0;
+    }
+    let dead = |version: &RowVersion| version.deleted_by.is_some_and(|id| id < horizon);
+    let before = table.data.len();
+    let mut versions = table.versions.iter();
+    table.data.retain(|_| !versions.next().is_some_and(dead));
+    table.versions.retain(|version| !dead(version));
+    for version in &mut table.versions {
+        if version.created_by < horizon {
+            version.created_by = FROZEN;
+        }
+    }
+    if table
+        .versions
+        .iter()
+        .all(|version| version.created_by == FROZEN && version.deleted_by.is_none())
+    {
+        table.versions.clear();
+    }
+    before - table.data.len()
+}
+
+/// `VACUUM [table]`: removes the row versions that no transaction can see
+/// any more. Finished transactions already vacuum the tables they wrote, but
+/// not while an older transaction might still see the rows.
+pub fn vacuum(database: &mut Database, table_name: Option<&ObjectName>) -> Result<(), String> {
+    let tables = match table_name {
+        Some(name) => {
+            let key = resolve_name(database, name)?.to_string();
+            if !database.tables.contains_key(&key) {
+                return Err(format!("Table '{}' does not exist", key));
+            }
+            Some(BTreeSet::from([key]))
+        }
+        None => None,
+    };
+    let removed = vacuum_tables(database, tables.as_ref());
+    database.report(format!("Vacuumed, removed {} dead row version(s)", removed));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_parsing::parse_statements;
+
+    fn run(session: &mut Session, database: &mut Database, sql: &str) -> Result<(), String> {
+        session
+            .execute(database, parse_statements(sql)?)
+            .map(|_| ())
+    }
+
+    fn rows(session: &mut Session, database: &mut Database, sql: &str) -> Vec<Vec<Value>> {
+        let output = session
+            .execute(database, parse_statements(sql).unwrap())
+            .unwrap();
+        match output.into_iter().last() {
+            Some(StatementOutput::Rows(result)) => result.rows,
+            output => panic!("Expected rows, got {:?}", output),
+        }
+    }
+
+    fn integers(values: &[i64]) -> Vec<Vec<Value>> {
+        values
+            .iter()
+            .map(|value| vec![Value::Integer(*value)])
+            .collect()
+    }
+
+    fn setup(sql: &str) -> (Database, Session, Session) {
+        let mut database = Database::new("test.db");
+        let mut session = Session::default();
+        run(&mut session, &mut database, sql).unwrap();
+        (database, session, Session::default())
+    }
+
+    #[test]
+    fn uncommitted_rows_are_seen_only_by_their_transaction() {
+        let (mut database, mut a, mut b) = setup("CREATE TABLE t (x INT)");
+        run(&mut a, &mut database, "BEGIN; INSERT INTO t VALUES (1)").unwrap();
+        assert_eq!(
+            rows(&mut a, &mut database, "SELECT x FROM t"),
+            integers(&[1])
+        );
+        assert_eq!(
+            rows(&mut b, &mut database, "SELECT x FROM t"),
+            integers(&[])
+        );
+        run(&mut a, &mut database, "COMMIT").unwrap();
+        assert_eq!(
+            rows(&mut b, &mut database, "SELECT x FROM t"),
+            integers(&[1])
+        );
+    }
+
+    #[test]
+    fn repeatable_read_keeps_its_snapshot() {
+        let (mut database, mut a, mut b) = setup("CREATE TABLE t (x INT)");
+        run(
+            &mut a,
+            &mut database,
+            "BEGIN ISOLATION LEVEL REPEATABLE READ",
+        )
+        .unwrap();
+        assert_eq!(
+            rows(&mut a, &mut database, "SELECT x FROM t"),
+            integers(&[])
+        );
+        run(&mut b, &mut database, "INSERT INTO t VALUES (1)").unwrap();
+        assert_eq!(
+            rows(&mut a, &mut database, "SELECT x FROM t"),
+            integers(&[])
+        );
+        run(&mut a, &mut database, "COMMIT; BEGIN").unwrap();
+        assert_eq!(
+            rows(&mut a, &mut database, "SELECT x FROM t"),
+            integers(&[1])
+        );
+        run(&mut b, &mut database, "INSERT INTO t VALUES (2)").unwrap();
+        assert_eq!(
+            rows(&mut a, &mut database, "SELECT x FROM t"),
+            integers(&[1, 2])
+        );
+    }
+
+    #[test]
+    fn rollback_discards_changes() {
+        let (mut database, mut a, mut b) =
+            setup("CREATE TABLE t (x INT); INSERT INTO t VALUES (1)");
+        run(&mut a, &mut database, "BEGIN; UPDATE t SET x = 2").unwrap();
+        run(
+            &mut a,
+            &mut database,
+            "INSERT INTO t VALUES (3); DELETE FROM t WHERE x = 2",
+        )
+        .unwrap();
+        assert_eq!(
+            rows(&mut a, &mut database, "SELECT x FROM t"),
+            integers(&[3])
+        );
+        run(&mut a, &mut database, "ROLLBACK").unwrap();
+        assert_eq!(
+            rows(&mut a, &mut database, "SELECT x FROM t"),
+            integers(&[1])
+        );
+        assert_eq!(
+            rows(&mut b, &mut database, "SELECT x FROM t"),
+            integers(&[1])
+        );
+    }
+
+    #[test]
+    fn rollback_restores_sequences_truncate_restarted() {
+        let (mut database, mut a, _) =
+            setup("CREATE TABLE t (id SERIAL, x INT); INSERT INTO t (x) VALUES (1), (2)");
+        run(&mut a, &mut database, "BEGIN; TRUNCATE t; ROLLBACK").unwrap();
+        run(&mut a, &mut database, "INSERT INTO t (x) VALUES (3)").unwrap();
+        assert_eq!(
+            rows(&mut a, &mut database, "SELECT id FROM t"),
+            integers(&[1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn concurrent_changes_to_a_row_conflict() {
+        let (mut database, mut a, mut b) =
+            setup("CREATE TABLE t (x INT); INSERT INTO t VALUES (1), (2)");
+        run(&mut a, &mut database, "BEGIN; UPDATE t SET x = 3 - x").unwrap();
+        run(&mut b, &mut database, "BEGIN").unwrap();
+        assert!(run(&mut b, &mut database, "UPDATE t SET x = x + 10").is_err());
+        assert!(run(&mut b, &mut database, "DELETE FROM t WHERE x = 1").is_err());
+        run(&mut a, &mut database, "COMMIT").unwrap();
+        run(&mut b, &mut database, "ROLLBACK").unwrap();
+        assert_eq!(
+            rows(&mut b, &mut database, "SELECT x FROM t ORDER BY x"),
+            integers(&[1, 2])
+        );
+    }
+
+    #[test]
+    fn concurrent_changes_to_both_ends_of_a_foreign_key_conflict() {
+        let (mut database, mut a, mut b) = setup(
+            "CREATE TABLE p (id INT PRIMARY KEY); CREATE TABLE c (pid INT REFERENCES p (id));
+            INSERT INTO p VALUES (1), (2)",
+        );
+        // A row referring to a key that a concurrent transaction deleted
+        run(&mut a, &mut database, "BEGIN; INSERT INTO c VALUES (1)").unwrap();
+        run(&mut b, &mut database, "DELETE FROM p WHERE id = 1").unwrap();
+        let err = run(&mut a, &mut database, "COMMIT").unwrap_err();
+        assert!(err.contains("violates foreign key constraint"), "{}", err);
+        // A deleted key that a concurrent transaction started referring to
+        run(&mut a, &mut database, "BEGIN; DELETE FROM p WHERE id = 2").unwrap();
+        run(&mut b, &mut database, "INSERT INTO c VALUES (2)").unwrap();
+        let err = run(&mut a, &mut database, "COMMIT").unwrap_err();
+        assert!(err.contains("is still referenced"), "{}", err);
+        assert_eq!(
+            rows(&mut a, &mut database, "SELECT id FROM p"),
+            integers(&[2])
+        );
+        assert_eq!(
+            rows(&mut a, &mut database, "SELECT pid FROM c"),
+            integers(&[2])
+        );
+    }
+
+    #[test]
+    fn equal_rows_of_different_transactions_are_kept_apart() {
+        let (mut database, mut a, mut b) =
+            setup("CREATE TABLE t (x INT); INSERT INTO t VALUES (1)");
+        run(&mut a, &mut database, "BEGIN; INSERT INTO t VALUES (1)").unwrap();
+        run(&mut b, &mut database, "BEGIN; DELETE FROM t").unwrap();
+        run(&mut b, &mut database, "COMMIT").unwrap();
+        run(&mut a, &mut database, "COMMIT").unwrap();
+        assert_eq!(
+            rows(&mut a, &mut database, "SELECT x FROM t"),
+            integers(&[1])
+        );
+    }
+
+    #[test]
+    fn vacuum_removes_versions_no_transaction_sees() {
+        let (mut database, mut a, mut b) =
+            setup("CREATE TABLE t (x INT); INSERT INTO t VALUES (1), (2)");
+        run(
+            &mut b,
+            &mut database,
+            "BEGIN ISOLATION LEVEL REPEATABLE READ",
+        )
+        .unwrap();
+        assert_eq!(
+            rows(&mut b, &mut database, "SELECT x FROM t"),
+            integers(&[1, 2])
+        );
+        run(&mut a, &mut database, "DELETE FROM t WHERE x = 1").unwrap();
+        assert_eq!(vacuum_tables(&mut database, None), 0);
+        assert_eq!(
+            rows(&mut b, &mut database, "SELECT x FROM t"),
+            integers(&[1, 2])
+        );
+        run(&mut b, &mut database, "COMMIT").unwrap();
+        assert_eq!(vacuum_tables(&mut database, None), 1);
+        assert_eq!(database.tables["t"].data.len(), 1);
+    }
+}
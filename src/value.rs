@@ -0,0 +1,203 @@
+use sqlparser::ast::DataType;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A single cell value stored in a table row or produced by an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Text(String),
+}
+
+/// The storage class a declared column type maps to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Affinity {
+    Integer,
+    Float,
+    Boolean,
+    Text,
+}
+
+impl Affinity {
+    pub fn of(data_type: &DataType) -> Affinity {
+        match data_type {
+            DataType::TinyInt(_)
+            | DataType::UnsignedTinyInt(_)
+            | DataType::Int2(_)
+            | DataType::UnsignedInt2(_)
+            | DataType::SmallInt(_)
+            | DataType::UnsignedSmallInt(_)
+            | DataType::MediumInt(_)
+            | DataType::UnsignedMediumInt(_)
+            | DataType::Int(_)
+            | DataType::Int4(_)
+            | DataType::Integer(_)
+            | DataType::UnsignedInt(_)
+            | DataType::UnsignedInt4(_)
+            | DataType::UnsignedInteger(_)
+            | DataType::BigInt(_)
+            | DataType::UnsignedBigInt(_)
+            | DataType::Int8(_)
+            | DataType::UnsignedInt8(_) => Affinity::Integer,
+            DataType::Numeric(_)
+            | DataType::Decimal(_)
+            | DataType::BigNumeric(_)
+            | DataType::BigDecimal(_)
+            | DataType::Dec(_)
+            | DataType::Float(_)
+            | DataType::Float4
+            | DataType::Real
+            | DataType::Float8
+            | DataType::Double
+            | DataType::DoublePrecision => Affinity::Float,
+            DataType::Bool | DataType::Boolean => Affinity::Boolean,
+            _ => Affinity::Text,
+        }
+    }
+}
+
+impl Value {
+    /// Converts a SQL literal from the parser into a value.
+    pub fn from_literal(literal: &sqlparser::ast::Value) -> Result<Value, String> {
+        match literal {
+            sqlparser::ast::Value::Number(n, _) => Value::parse_number(n),
+            sqlparser::ast::Value::SingleQuotedString(s)
+            | sqlparser::ast::Value::DoubleQuotedString(s)
+            | sqlparser::ast::Value::EscapedStringLiteral(s)
+            | sqlparser::ast::Value::NationalStringLiteral(s)
+            | sqlparser::ast::Value::RawStringLiteral(s) => Ok(Value::Text(s.clone())),
+            sqlparser::ast::Value::DollarQuotedString(s) => Ok(Value::Text(s.value.clone())),
+            sqlparser::ast::Value::Boolean(b) => Ok(Value::Boolean(*b)),
+            sqlparser::ast::Value::Null => Ok(Value::Null),
+            other => Err(format!("Unsupported literal: {}", other)),
+        }
+    }
+
+    fn parse_number(text: &str) -> Result<Value, String> {
+        if let Ok(n) = text.parse::<i64>() {
+            Ok(Value::Integer(n))
+        } else if let Ok(f) = text.parse::<f64>() {
+            Ok(Value::Float(f))
+        } else {
+            Err(format!("Invalid number: {}", text))
+        }
+    }
+
+    /// Guesses the type of a stored value for columns without a declared type.
+    pub fn infer(text: &str) -> Value {
+        if let Ok(n) = text.parse::<i64>() {
+            Value::Integer(n)
+        } else if let Ok(f) = text.parse::<f64>() {
+            Value::Float(f)
+        } else {
+            Value::Text(text.to_string())
+        }
+    }
+
+    /// Converts the value to the storage class of `data_type`.
+    pub fn cast(self, data_type: &DataType) -> Result<Value, String> {
+        self.cast_to(Affinity::of(data_type))
+            .map_err(|value| format!("Cannot convert {} to {}", value, data_type))
+    }
+
+    pub fn cast_to(self, affinity: Affinity) -> Result<Value, Value> {
+        match (affinity, self) {
+            (_, Value::Null) => Ok(Value::Null),
+            (Affinity::Integer, Value::Integer(n)) => Ok(Value::Integer(n)),
+            (Affinity::Integer, Value::Float(f)) if f.fract() == 0.0 => {
+                Ok(Value::Integer(f as i64))
+            }
+            (Affinity::Integer, Value::Boolean(b)) => Ok(Value::Integer(b as i64)),
+            (Affinity::Integer, Value::Text(s)) => match s.trim().parse::<i64>() {
+                Ok(n) => Ok(Value::Integer(n)),
+                Err(_) => Err(Value::Text(s)),
+            },
+            (Affinity::Float, Value::Integer(n)) => Ok(Value::Float(n as f64)),
+            (Affinity::Float, Value::Float(f)) => Ok(Value::Float(f)),
+            (Affinity::Float, Value::Text(s)) => match s.trim().parse::<f64>() {
+                Ok(f) => Ok(Value::Float(f)),
+                Err(_) => Err(Value::Text(s)),
+            },
+            (Affinity::Boolean, Value::Boolean(b)) => Ok(Value::Boolean(b)),
+            (Affinity::Boolean, Value::Integer(n)) => Ok(Value::Boolean(n != 0)),
+            (Affinity::Boolean, Value::Text(s)) => match s.trim().to_lowercase().as_str() {
+                "true" | "t" | "yes" | "1" => Ok(Value::Boolean(true)),
+                "false" | "f" | "no" | "0" => Ok(Value::Boolean(false)),
+                _ => Err(Value::Text(s)),
+            },
+            (Affinity::Text, Value::Text(s)) => Ok(Value::Text(s)),
+            (Affinity::Text, value) => Ok(Value::Text(value.to_string())),
+            (_, value) => Err(value),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// Interprets the value as a boolean; NULL stays unknown.
+    pub fn as_bool(&self) -> Result<Option<bool>, String> {
+        match self {
+            Value::Null => Ok(None),
+            Value::Boolean(b) => Ok(Some(*b)),
+            Value::Integer(n) => Ok(Some(*n != 0)),
+            other => Err(format!("Expected a boolean, found {}", other)),
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Integer(n) => Some(*n as f64),
+            Value::Float(f) => Some(*f),
+            Value::Text(s) => s.trim().parse::<f64>().ok(),
+            _ => None,
+        }
+    }
+
+    /// SQL comparison; NULL compared with anything is unknown.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Null, _) | (_, Value::Null) => None,
+            (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+            (Value::Boolean(a), Value::Boolean(b)) => Some(a.cmp(b)),
+            (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+            (Value::Integer(_) | Value::Float(_), _) | (_, Value::Integer(_) | Value::Float(_)) => {
+                match (self.as_f64(), other.as_f64()) {
+                    (Some(a), Some(b)) => a.partial_cmp(&b),
+                    _ => Some(self.to_string().cmp(&other.to_string())),
+                }
+            }
+            _ => Some(self.to_string().cmp(&other.to_string())),
+        }
+    }
+
+    /// Total order for sorting: NULL sorts after every other value.
+    pub fn sort_cmp(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) => Ordering::Greater,
+            (_, Value::Null) => Ordering::Less,
+            _ => self.compare(other).unwrap_or(Ordering::Equal),
+        }
+    }
+
+    /// Whether two values are the same key, treating NULL as distinct from everything.
+    pub fn same_key(&self, other: &Value) -> bool {
+        self.compare(other) == Some(Ordering::Equal)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
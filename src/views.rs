@@ -0,0 +1,786 @@
+use super::database::{Column, Database, Table, View};
+use super::information_schema::is_system_table;
+use super::query_execution::QueryResult;
+use super::select_execution::{execute_query, resolve_from};
+use super::table_alteration::check_rows;
+use super::value::Value;
+use sqlparser::ast::{visit_expressions, visit_expressions_mut, Assignment, BinaryOperator};
+use sqlparser::ast::{ConflictTarget, DataType, Visit, Visitor};
+use sqlparser::ast::{Expr, GroupByExpr, Ident, ObjectName, OnConflictAction, OnInsert, Query};
+use sqlparser::ast::{SelectItem, SetExpr, Statement, TableAlias, TableFactor, TableWithJoins};
+use std::ops::ControlFlow;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn create_view(
+    database: &mut Database,
+    or_replace: bool,
+    materialized: bool,
+    name: &ObjectName,
+    columns: Vec<Ident>,
+    query: Query,
+    with_data: bool,
+) -> Result<(), String> {
+    let view_name = name.to_string();
+    match database.views.get(&view_name) {
+        // Only plain views can be replaced, and only by plain views
+        Some(existing) if !or_replace || existing.materialized || materialized => {
+            return Err(format!("Relation '{}' already exists", view_name));
+        }
+        None if database.tables.contains_key(&view_name) => {
+            return Err(format!("Relation '{}' already exists", view_name));
+        }
+        _ => {}
+    }
+    let mut view = View {
+        name: view_name.clone(),
+        columns,
+        query,
+        materialized,
+        refreshed_at: None,
+    };
+
+    // A view reading from itself, directly or through other views, would
+    // expand forever
+    let mut pending = view.dependencies();
+    let mut seen: Vec<String> = Vec::new();
+    while let Some(dependency) = pending.pop() {
+        if dependency == view_name {
+            return Err(format!("View '{}' cannot depend on itself", view_name));
+        }
+        if let Some(other) = database.views.get(&dependency) {
+            if !seen.contains(&dependency) {
+                pending.extend(other.dependencies());
+                seen.push(dependency);
+            }
+        }
+    }
+
+    // Run the query once so that a broken definition is rejected right away
+    let result = execute_query(database, &view.query)?;
+    let column_names = view_column_names(&view, &result)?;
+    if materialized {
+        // WITH NO DATA leaves the view empty until it is refreshed
+        let rows = if with_data { result.rows } else { vec![] };
+        let table = materialized_table(&view_name, &column_names, rows);
+        database.tables.insert(view_name.clone(), table);
+        if with_data {
+            view.refreshed_at = Some(unix_time());
+        }
+        database.report(format!("Created materialized view: {}", view_name));
+    } else {
+        database.report(format!("Created view: {}", view_name));
+    }
+    database.views.insert(view_name, view);
+    Ok(())
+}
+
+/// Recomputes the stored result of a materialized view.
+pub fn refresh_materialized_view(database: &mut Database, name: &ObjectName) -> Result<(), String> {
+    let view_name = name.to_string();
+    let view = database
+        .views
+        .get(&view_name)
+        .filter(|view| view.materialized)
+        .ok_or_else(|| format!("Materialized view '{}' does not exist", view_name))?;
+    let result = execute_query(database, &view.query)?;
+    let column_names = view_column_names(view, &result)?;
+    let mut table = materialized_table(&view_name, &column_names, result.rows);
+    // Indexes created on the view survive the refresh
+    for index in &database.tables[&view_name].indexes {
+        table.add_index(index.clone())?;
+    }
+    check_rows(&table, database)?;
+    database.tables.insert(view_name.clone(), table);
+    database.views.get_mut(&view_name).unwrap().refreshed_at = Some(unix_time());
+    database.report(format!("Refreshed materialized view: {}", view_name));
+    Ok(())
+}
+
+/// The output column names of a view, checking the optional column list.
+fn view_column_names(view: &View, result: &QueryResult) -> Result<Vec<String>, String> {
+    if view.columns.len() > result.columns.len() {
+        return Err(format!(
+            "CREATE VIEW specifies more column names than columns for view '{}'",
+            view.name
+        ));
+    }
+    let mut names = result.columns.clone();
+    for (name, column) in names.iter_mut().zip(&view.columns) {
+        *name = column.value.clone();
+    }
+    for (index, name) in names.iter().enumerate() {
+        if names[..index].contains(name) {
+            return Err(format!(
+                "Column '{}' specified more than once in view '{}'",
+                name, view.name
+            ));
+        }
+    }
+    Ok(names)
+}
+
+/// Stores a query result as a table, typing each column after its values.
+fn materialized_table(name: &str, column_names: &[String], rows: Vec<Vec<Value>>) -> Table {
+    let columns = column_names
+        .iter()
+        .enumerate()
+        .map(|(index, column_name)| {
+            let mut data_type = DataType::Text;
+            for value in rows.iter().map(|row| &row[index]) {
+                data_type = match (value, data_type) {
+                    (Value::Null, data_type) => data_type,
+                    (Value::Integer(_), DataType::Text) => DataType::Int(None),
+                    (Value::Float(_), DataType::Text | DataType::Int(_)) => DataType::Double,
+                    (Value::Boolean(_), DataType::Text) => DataType::Boolean,
+                    (Value::Integer(_) | Value::Float(_), data_type) => data_type,
+                    _ => {
+                        data_type = DataType::Text;
+                        break;
+                    }
+                };
+            }
+            Column::new(column_name, data_type)
+        })
+        .collect();
+    Table {
+        name: name.to_string(),
+        columns,
+        constraints: vec![],
+        indexes: vec![],
+        data: rows,
+        versions: vec![],
+        origins: None,
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Drops every view in `names`, which must all be plain views or all be
+/// materialized views. Views built on a dropped view block the drop, or are
+/// dropped with it under CASCADE.
+pub fn drop_views(
+    database: &mut Database,
+    names: &[ObjectName],
+    materialized: bool,
+    if_exists: bool,
+    cascade: bool,
+) -> Result<(), String> {
+    let kind = if materialized {
+        "Materialized view"
+    } else {
+        "View"
+    };
+    let mut dropped: Vec<String> = Vec::new();
+    for name in names {
+        let view_name = name.to_string();
+        match database.views.get(&view_name) {
+            Some(view) if view.materialized == materialized => {
+                if !dropped.contains(&view_name) {
+                    dropped.push(view_name);
+                }
+            }
+            Some(view) => {
+                return Err(format!(
+                    "'{}' is a {}, use DROP {}",
+                    view_name,
+                    view_kind(view),
+                    view_kind(view).to_uppercase()
+                ))
+            }
+            None if if_exists => {
+                database.report(format!("{} '{}' does not exist, skipping", kind, view_name))
+            }
+            None => return Err(format!("{} '{}' does not exist", kind, view_name)),
+        }
+    }
+    for view_name in &dropped {
+        let dependents = dependent_views(database, std::slice::from_ref(view_name));
+        match dependents.iter().find(|name| !dropped.contains(name)) {
+            Some(dependent) if !cascade => {
+                return Err(format!(
+                    "Cannot drop {} '{}' because {} '{}' depends on it",
+                    view_kind(&database.views[view_name]),
+                    view_name,
+                    view_kind(&database.views[dependent]),
+                    dependent
+                ))
+            }
+            _ => {}
+        }
+    }
+    let dependents = dependent_views(database, &dropped);
+    for view_name in dropped.into_iter().chain(dependents) {
+        remove_view(database, &view_name);
+    }
+    Ok(())
+}
+
+/// Removes a view, along with the stored result of a materialized view.
+pub fn remove_view(database: &mut Database, name: &str) {
+    if let Some(view) = database.views.remove(name) {
+        if view.materialized {
+            database.tables.remove(name);
+        }
+        database.report(format!("Dropped {}: {}", view_kind(&view), name));
+    }
+}
+
+pub fn view_kind(view: &View) -> &'static str {
+    if view.materialized {
+        "materialized view"
+    } else {
+        "view"
+    }
+}
+
+/// The views that read from any of `names`, directly or through other views.
+pub fn dependent_views(database: &Database, names: &[String]) -> Vec<String> {
+    let mut dependents: Vec<String> = Vec::new();
+    loop {
+        let found = database.views.values().find(|view| {
+            !names.contains(&view.name)
+                && !dependents.contains(&view.name)
+                && view
+                    .dependencies()
+                    .iter()
+                    .any(|name| names.contains(name) || dependents.contains(name))
+        });
+        match found {
+            Some(view) => dependents.push(view.name.clone()),
+            None => return dependents,
+        }
+    }
+}
+
+/// Points the views reading from a renamed table or view at its new name.
+pub fn rename_view_dependencies(database: &mut Database, old_name: &str, new_name: &str) {
+    for view in database.views.values_mut() {
+        let _ = sqlparser::ast::visit_relations_mut(&mut view.query, |name| {
+            if name.to_string() == old_name {
+                *name = ObjectName(vec![Ident::new(new_name)]);
+            }
+            ControlFlow::<()>::Continue(())
+        });
+    }
+}
+
+/// The names of the relations a query reads, with the aliases it gives them.
+#[derive(Default)]
+struct Relations(Vec<(ObjectName, Option<TableAlias>, Option<Query>)>);
+
+impl Visitor for Relations {
+    type Break = ();
+
+    fn pre_visit_table_factor(&mut self, factor: &TableFactor) -> ControlFlow<()> {
+        match factor {
+            TableFactor::Table { name, alias, .. } => {
+                self.0.push((name.clone(), alias.clone(), None))
+            }
+            TableFactor::Derived {
+                subquery, alias, ..
+            } => self
+                .0
+                .push((ObjectName(vec![]), alias.clone(), Some(*subquery.clone()))),
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// How the query of a view names column `column` of table `table`.
+struct ColumnReference<'a> {
+    column: &'a str,
+    /// The qualifiers that stand for the table, as the parts of the name.
+    qualifiers: Vec<Vec<String>>,
+    /// Whether another relation the query reads may have a column of the
+    /// same name, so that the bare name may not mean the table's column.
+    shared: bool,
+}
+
+impl<'a> ColumnReference<'a> {
+    fn new(database: &Database, query: &Query, table: &str, column: &'a str) -> Self {
+        let mut relations = Relations::default();
+        let _ = query.visit(&mut relations);
+        let mut reference = ColumnReference {
+            column,
+            qualifiers: vec![],
+            shared: false,
+        };
+        for (name, alias, subquery) in relations.0 {
+            let key = name.to_string();
+            if key == table && subquery.is_none() {
+                reference.shared |= !reference.qualifiers.is_empty();
+                match alias {
+                    Some(alias) => reference.qualifiers.push(vec![alias.name.value]),
+                    None => {
+                        let parts: Vec<String> = name.0.iter().map(|i| i.value.clone()).collect();
+                        reference.qualifiers.push(parts[parts.len() - 1..].to_vec());
+                        reference.qualifiers.push(parts);
+                    }
+                }
+                continue;
+            }
+            reference.shared |= match (&subquery, database.tables.get(&key)) {
+                (Some(subquery), _) => may_have_column(subquery, &[], column),
+                (None, Some(table)) => table.column_index(column).is_some(),
+                (None, None) => database
+                    .views
+                    .get(&key)
+                    .is_none_or(|view| may_have_column(&view.query, &view.columns, column)),
+            };
+        }
+        reference
+    }
+
+    fn matches(&self, idents: &[Ident]) -> bool {
+        match idents.split_last() {
+            Some((name, [])) => !self.shared && name.value == self.column,
+            Some((name, qualifier)) => {
+                name.value == self.column
+                    && self.qualifiers.iter().any(|parts| {
+                        parts.len() == qualifier.len()
+                            && parts
+                                .iter()
+                                .zip(qualifier)
+                                .all(|(part, i)| *part == i.value)
+                    })
+            }
+            None => false,
+        }
+    }
+
+    fn matches_expr(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Identifier(ident) => self.matches(std::slice::from_ref(ident)),
+            Expr::CompoundIdentifier(idents) => self.matches(idents),
+            _ => false,
+        }
+    }
+}
+
+/// Whether a column of the given name may come out of a query, telling by
+/// its select list alone.
+fn may_have_column(query: &Query, columns: &[Ident], column: &str) -> bool {
+    if !columns.is_empty() {
+        return columns.iter().any(|ident| ident.value == column);
+    }
+    let SetExpr::Select(select) = query.body.as_ref() else {
+        return true;
+    };
+    select.projection.iter().any(|item| match item {
+        SelectItem::UnnamedExpr(Expr::Identifier(ident)) => ident.value == column,
+        SelectItem::UnnamedExpr(Expr::CompoundIdentifier(idents)) => {
+            idents.last().is_some_and(|ident| ident.value == column)
+        }
+        SelectItem::UnnamedExpr(_) => false,
+        SelectItem::ExprWithAlias { alias, .. } => alias.value == column,
+        SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(..) => true,
+    })
+}
+
+/// The views whose queries name column `column` of table `table`. Those that
+/// select it through `*` alone follow the columns of the table.
+pub fn views_using_column(database: &Database, table: &str, column: &str) -> Vec<String> {
+    database
+        .views
+        .values()
+        .filter(|view| view.dependencies().iter().any(|name| name == table))
+        .filter(|view| {
+            let reference = ColumnReference::new(database, &view.query, table, column);
+            visit_expressions(&view.query, |expr| {
+                if reference.matches_expr(expr) {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })
+            .is_break()
+        })
+        .map(|view| view.name.clone())
+        .collect()
+}
+
+/// Points the views reading column `old_name` of table `table` at its new
+/// name. The columns of the views keep their names.
+pub fn rename_view_column(database: &mut Database, table: &str, old_name: &str, new_name: &Ident) {
+    for view_name in views_using_column(database, table, old_name) {
+        let view = &database.views[&view_name];
+        let reference = ColumnReference::new(database, &view.query, table, old_name);
+        let mut query = view.query.clone();
+        if view.columns.is_empty() {
+            keep_column_names(&mut query.body, &reference);
+        }
+        let _ = visit_expressions_mut(&mut query, |expr| {
+            match expr {
+                Expr::Identifier(ident) if reference.matches(std::slice::from_ref(ident)) => {
+                    *ident = new_name.clone()
+                }
+                Expr::CompoundIdentifier(idents) if reference.matches(idents) => {
+                    *idents.last_mut().unwrap() = new_name.clone()
+                }
+                _ => {}
+            }
+            ControlFlow::<()>::Continue(())
+        });
+        database.views.get_mut(&view_name).unwrap().query = query;
+    }
+}
+
+/// Aliases the result columns that are the referenced column itself with
+/// its name, so that they keep it when the column is renamed. A set
+/// operation takes its column names from its first query.
+fn keep_column_names(body: &mut SetExpr, reference: &ColumnReference) {
+    match body {
+        SetExpr::Select(select) => {
+            for item in &mut select.projection {
+                if let SelectItem::UnnamedExpr(expr) = item {
+                    if reference.matches_expr(expr) {
+                        *item = SelectItem::ExprWithAlias {
+                            expr: expr.clone(),
+                            alias: Ident::new(reference.column),
+                        };
+                    }
+                }
+            }
+        }
+        SetExpr::SetOperation { left, .. } => keep_column_names(left, reference),
+        SetExpr::Query(query) => keep_column_names(&mut query.body, reference),
+        _ => {}
+    }
+}
+
+/// How a simple view maps onto the single table or view it selects from.
+struct BaseRelation {
+    name: ObjectName,
+    /// The name the view's query uses for the base relation.
+    qualifier: String,
+    /// Pairs of (view column, base column).
+    columns: Vec<(String, String)>,
+    selection: Option<Expr>,
+}
+
+impl BaseRelation {
+    fn column(&self, view_column: &str) -> Option<&str> {
+        self.columns
+            .iter()
+            .find(|(name, _)| name == view_column)
+            .map(|(_, base)| base.as_str())
+    }
+
+    fn base_ident(&self, view_name: &str, ident: &Ident) -> Result<Ident, String> {
+        self.column(&ident.value).map(Ident::new).ok_or_else(|| {
+            format!(
+                "Column '{}' does not exist in view '{}'",
+                ident.value, view_name
+            )
+        })
+    }
+}
+
+/// Finds the relation behind an automatically updatable view: one that
+/// selects plain columns from a single table or view, without DISTINCT,
+/// grouping or LIMIT.
+fn base_relation(database: &Database, view: &View) -> Result<BaseRelation, String> {
+    let not_updatable = |reason: &str| {
+        Err(format!(
+            "Cannot write to view '{}': views {} are not automatically updatable",
+            view.name, reason
+        ))
+    };
+    let query = &view.query;
+    if query.with.is_some() {
+        return not_updatable("containing WITH");
+    }
+    if query.limit.is_some() || query.offset.is_some() || query.fetch.is_some() {
+        return not_updatable("containing LIMIT or OFFSET");
+    }
+    let SetExpr::Select(select) = query.body.as_ref() else {
+        return not_updatable("containing UNION, INTERSECT or EXCEPT");
+    };
+    if select.distinct.is_some() {
+        return not_updatable("containing DISTINCT");
+    }
+    if select.group_by != GroupByExpr::Expressions(vec![]) || select.having.is_some() {
+        return not_updatable("containing GROUP BY or HAVING");
+    }
+    let (name, alias) = match select.from.as_slice() {
+        [TableWithJoins {
+            relation: TableFactor::Table { name, alias, .. },
+            joins,
+        }] if joins.is_empty() => (name, alias),
+        _ => return not_updatable("that do not select from a single table or view"),
+    };
+    let qualifier = alias
+        .as_ref()
+        .map_or_else(|| name.to_string(), |alias| alias.name.value.clone());
+    let base_columns: Vec<String> = resolve_from(database, &select.from)?
+        .columns
+        .into_iter()
+        .map(|col| col.name)
+        .collect();
+
+    let mut columns = Vec::new();
+    for item in &select.projection {
+        let (column, alias) = match item {
+            SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(..) => {
+                columns.extend(base_columns.iter().map(|col| (col.clone(), col.clone())));
+                continue;
+            }
+            SelectItem::UnnamedExpr(expr) => (expr, None),
+            SelectItem::ExprWithAlias { expr, alias } => (expr, Some(alias)),
+        };
+        let column = match column {
+            Expr::Identifier(ident) => &ident.value,
+            Expr::CompoundIdentifier(idents) if idents.len() == 2 => &idents[1].value,
+            _ => return not_updatable("with columns that are not plain table columns"),
+        };
+        let name = alias.map_or(column, |alias| &alias.value);
+        columns.push((name.clone(), column.clone()));
+    }
+    for ((name, _), column) in columns.iter_mut().zip(&view.columns) {
+        *name = column.value.clone();
+    }
+    Ok(BaseRelation {
+        name: name.clone(),
+        qualifier,
+        columns,
+        selection: select.selection.clone(),
+    })
+}
+
+/// Rewrites INSERT, UPDATE and DELETE on an updatable view into the same
+/// statement on the table underneath. Other statements are returned as is.
+pub fn rewrite_view_write(database: &Database, statement: Statement) -> Result<Statement, String> {
+    let mut statement = statement;
+    loop {
+        let target = match &statement {
+            Statement::Insert { table_name, .. } => table_name.to_string(),
+            Statement::Update { table, .. } => match &table.relation {
+                TableFactor::Table { name, .. } => name.to_string(),
+                _ => return Ok(statement),
+            },
+            Statement::Delete { from, .. } => match from.first().map(|from| &from.relation) {
+                Some(TableFactor::Table { name, .. }) => name.to_string(),
+                _ => return Ok(statement),
+            },
+            _ => return Ok(statement),
+        };
+        if is_system_table(&target) {
+            return Err(format!(
+                "Cannot change '{}', it is a read-only system table",
+                target
+            ));
+        }
+        let Some(view) = database.views.get(&target) else {
+            return Ok(statement);
+        };
+        if view.materialized {
+            return Err(format!(
+                "Cannot change materialized view '{}', use REFRESH MATERIALIZED VIEW",
+                target
+            ));
+        }
+        let base = base_relation(database, view)?;
+        statement = rewrite_statement(statement, view, &base)?;
+    }
+}
+
+fn rewrite_statement(
+    mut statement: Statement,
+    view: &View,
+    base: &BaseRelation,
+) -> Result<Statement, String> {
+    match &mut statement {
+        Statement::Insert {
+            table_name,
+            columns,
+            on,
+            returning,
+            ..
+        } => {
+            let base_name = base.name.to_string();
+            let qualifiers = [
+                (view.name.as_str(), base_name.as_str()),
+                ("excluded", "excluded"),
+            ];
+            *columns = if columns.is_empty() {
+                base.columns
+                    .iter()
+                    .map(|(_, column)| Ident::new(column))
+                    .collect()
+            } else {
+                columns
+                    .iter()
+                    .map(|col| base.base_ident(&view.name, col))
+                    .collect::<Result<_, String>>()?
+            };
+            if let Some(OnInsert::OnConflict(on_conflict)) = on {
+                if let Some(ConflictTarget::Columns(columns)) = &mut on_conflict.conflict_target {
+                    for column in columns.iter_mut() {
+                        *column = base.base_ident(&view.name, column)?;
+                    }
+                }
+                if let OnConflictAction::DoUpdate(do_update) = &mut on_conflict.action {
+                    rewrite_assignments(&mut do_update.assignments, view, base, &qualifiers)?;
+                    if let Some(selection) = &mut do_update.selection {
+                        to_base_columns(selection, base, &qualifiers);
+                    }
+                }
+            }
+            rewrite_returning(returning, base, &qualifiers);
+            *table_name = base.name.clone();
+        }
+        Statement::Update {
+            table,
+            assignments,
+            selection,
+            returning,
+            ..
+        } => {
+            let qualifier = retarget(&mut table.relation, view, base);
+            let qualifiers = [(qualifier.as_str(), qualifier.as_str())];
+            rewrite_assignments(assignments, view, base, &qualifiers)?;
+            *selection = combined_selection(selection.take(), base, &qualifier);
+            rewrite_returning(returning, base, &qualifiers);
+        }
+        Statement::Delete {
+            from,
+            selection,
+            returning,
+            ..
+        } => {
+            let qualifier = retarget(&mut from[0].relation, view, base);
+            let qualifiers = [(qualifier.as_str(), qualifier.as_str())];
+            *selection = combined_selection(selection.take(), base, &qualifier);
+            rewrite_returning(returning, base, &qualifiers);
+        }
+        _ => {}
+    }
+    Ok(statement)
+}
+
+/// Points an UPDATE or DELETE target at the base relation, keeping the name
+/// the statement uses for it as an alias. Returns that name.
+fn retarget(relation: &mut TableFactor, view: &View, base: &BaseRelation) -> String {
+    let TableFactor::Table { name, alias, .. } = relation else {
+        return view.name.clone();
+    };
+    let qualifier = alias
+        .as_ref()
+        .map_or_else(|| view.name.clone(), |alias| alias.name.value.clone());
+    *name = base.name.clone();
+    *alias = Some(TableAlias {
+        name: Ident::new(&qualifier),
+        columns: vec![],
+    });
+    qualifier
+}
+
+fn rewrite_assignments(
+    assignments: &mut [Assignment],
+    view: &View,
+    base: &BaseRelation,
+    qualifiers: &[(&str, &str)],
+) -> Result<(), String> {
+    for Assignment { id, value } in assignments {
+        // `SET col`, `SET v.col` and `SET (a, b)` all name view columns
+        let columns = match (id.len(), &*value) {
+            (2, expr) if !matches!(expr, Expr::Tuple(_) | Expr::Subquery(_)) => &mut id[1..],
+            _ => &mut id[..],
+        };
+        for column in columns {
+            *column = base.base_ident(&view.name, column)?;
+        }
+        to_base_columns(value, base, qualifiers);
+    }
+    Ok(())
+}
+
+fn rewrite_returning(
+    returning: &mut Option<Vec<SelectItem>>,
+    base: &BaseRelation,
+    qualifiers: &[(&str, &str)],
+) {
+    for item in returning.iter_mut().flatten() {
+        match item {
+            SelectItem::UnnamedExpr(expr) => {
+                // Keep the view column name as the output name
+                let name = match expr {
+                    Expr::Identifier(ident) => Some(ident.clone()),
+                    Expr::CompoundIdentifier(idents) => idents.last().cloned(),
+                    _ => None,
+                };
+                let mut base_expr = expr.clone();
+                to_base_columns(&mut base_expr, base, qualifiers);
+                *item = match name {
+                    Some(alias) => SelectItem::ExprWithAlias {
+                        expr: base_expr,
+                        alias,
+                    },
+                    None => SelectItem::UnnamedExpr(base_expr),
+                };
+            }
+            SelectItem::ExprWithAlias { expr, .. } => to_base_columns(expr, base, qualifiers),
+            _ => {}
+        }
+    }
+}
+
+/// ANDs the view's own WHERE clause, which speaks about the base relation
+/// under the view's qualifier, onto the statement's WHERE clause.
+fn combined_selection(
+    selection: Option<Expr>,
+    base: &BaseRelation,
+    qualifier: &str,
+) -> Option<Expr> {
+    let selection = selection.map(|mut selection| {
+        to_base_columns(&mut selection, base, &[(qualifier, qualifier)]);
+        selection
+    });
+    let view_selection = base.selection.clone().map(|mut view_selection| {
+        let _ = visit_expressions_mut(&mut view_selection, |expr| {
+            if let Expr::CompoundIdentifier(idents) = expr {
+                if idents.len() == 2 && idents[0].value == base.qualifier {
+                    idents[0] = Ident::new(qualifier);
+                }
+            }
+            ControlFlow::<()>::Continue(())
+        });
+        view_selection
+    });
+    match (view_selection, selection) {
+        (Some(left), Some(right)) => Some(Expr::BinaryOp {
+            left: Box::new(Expr::Nested(Box::new(left))),
+            op: BinaryOperator::And,
+            right: Box::new(Expr::Nested(Box::new(right))),
+        }),
+        (left, right) => left.or(right),
+    }
+}
+
+/// Renames view columns in a statement expression to the base columns,
+/// both unqualified and qualified by one of `qualifiers` (old, new).
+fn to_base_columns(expr: &mut Expr, base: &BaseRelation, qualifiers: &[(&str, &str)]) {
+    let _ = visit_expressions_mut(expr, |expr| {
+        match expr {
+            Expr::Identifier(ident) => {
+                if let Some(column) = base.column(&ident.value) {
+                    ident.value = column.to_string();
+                }
+            }
+            Expr::CompoundIdentifier(idents) if idents.len() == 2 => {
+                let found = qualifiers.iter().find(|(old, _)| idents[0].value == *old);
+                if let (Some((_, new)), Some(column)) = (found, base.column(&idents[1].value)) {
+                    idents[0].value = new.to_string();
+                    idents[1].value = column.to_string();
+                }
+            }
+            _ => {}
+        }
+        ControlFlow::<()>::Continue(())
+    });
+}